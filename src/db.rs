@@ -2,9 +2,9 @@ extern crate pest;
 extern crate regex;
 extern crate rusqlite;
 
-use crate::network::{rextcostfcn, rcostfcn, rvjc};
+use crate::network::{rcostfpr, rextcostfcn, rcostfcn, rvjc_retry};
 use crate::util::views::TagChange;
-use crate::util::{Card, CardLayout, CardStat, CommanderType, Deck, DefaultFilter, SortOrder};
+use crate::util::{AutoTagRule, Card, CardLayout, CardPrinting, CardStat, CommanderType, Deck, DeckType, DefaultFilter, SortOrder, Zone, tag_under};
 
 use self::rusqlite::functions::FunctionFlags;
 use self::rusqlite::{params, Connection};
@@ -12,7 +12,7 @@ use regex::Regex;
 use rusqlite::{named_params, Error, Result, Row};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use std::{collections::HashMap, convert::TryInto, sync::Mutex};
+use std::{collections::{HashMap, HashSet}, convert::TryInto, sync::Mutex};
 type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
 use chrono::{Datelike, Duration, TimeZone, Utc};
 use pest::{iterators::Pair, Parser};
@@ -20,11 +20,19 @@ use pest_derive::Parser;
 use std::{thread, time};
 
 const DB_FILE: &str = "lieutenant.db";
+const FETCH_ATTEMPTS: u32 = 3;
+// Bumped whenever ivcfjsmap starts storing more per-set data, so that updatedb re-reads older sets.
+const IMPORT_VERSION: i32 = 3;
+// The cards of a deck that are actually played, as opposed to its side zones.
+const DECK_ZONES: &str = "zone IN ('commander', 'main')";
+// How many edits of each deck can be undone.
+const HISTORY_LEN: u32 = 200;
 
 #[derive(Default)]
 pub struct CardFilter {
     pub did: i32,
     color: String,
+    pub dt: DeckType,
     pub df: DefaultFilter,
     pub so: SortOrder,
 }
@@ -43,10 +51,69 @@ pub struct Set {
     pub set_type: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+pub struct Ruling {
+    pub date: String,
+    pub text: String,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Printing {
+    pub set_code: String,
+    pub number: String,
+    pub rarity: String,
+    pub date: String,
+}
+
+// A deck that runs a card, and the card's tags and zone there.
+#[derive(PartialEq, Debug, Clone)]
+pub struct CardUse {
+    pub did: i32,
+    pub deck: String,
+    pub archived: bool,
+    pub zone: Zone,
+    pub quantity: u32,
+    pub tags: Vec<String>,
+}
+
+// A rule the deck breaks, and the card breaking it when there is one.
+#[derive(Debug, PartialEq)]
+pub struct Violation {
+    pub card: Option<String>,
+    pub problem: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.card {
+            Some(card) => write!(f, "{}: {}", card, self.problem),
+            None => write!(f, "{}", self.problem),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SetImport {
+    pub added: usize,
+    pub duplicates: usize,
+    pub illegal: usize,
+    pub failed: usize,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct ImportCard {
     pub name: String,
     pub tags: Option<String>,
+    #[serde(default)]
+    pub set_code: Option<String>,
+    #[serde(default)]
+    pub number: Option<String>,
+    #[serde(default)]
+    pub foil: Option<bool>,
+    #[serde(default)]
+    pub quantity: Option<u32>,
+    #[serde(default)]
+    pub zone: Option<String>,
 }
 
 enum ParseMode {
@@ -66,6 +133,8 @@ enum FilterField {
     Toughness,
     Color,
     Identity,
+    Set,
+    Zone,
     None,
 }
 
@@ -83,12 +152,14 @@ impl CardFilter {
     pub fn from(
         did: i32,
         color: &str,
+        deck_type: DeckType,
         default_filter: DefaultFilter,
         sort_order: SortOrder,
     ) -> CardFilter {
         CardFilter {
             did,
             color: color.to_string(),
+            dt: deck_type,
             df: default_filter,
             so: sort_order,
         }
@@ -103,6 +174,7 @@ impl CardFilter {
                 }
                 let ci = match colors.len() {
                     0 => String::from("1=1"),
+                    _ if !self.dt.rules().identity => String::from("1=1"),
                     _ => {
                         format!("color_identity REGEXP \'^[^{}]*$\'", &colors)
                     }
@@ -112,8 +184,9 @@ impl CardFilter {
 LEFT OUTER JOIN deck_contents
 ON cards.name = deck_contents.card_name
 AND deck_contents.deck = {}
-WHERE {}",
-                    self.did, ci
+WHERE {}
+AND {}",
+                    self.did, ci, legality_clause(self.dt)
                 )
             }
             false => {
@@ -186,13 +259,25 @@ WHERE deck_contents.deck = {}",
                     s += &CardFilter::helper(r, &FilterField::Type);
                 }
             }
+            Rule::set => {
+                let i = p.into_inner();
+                for r in i {
+                    s += &CardFilter::helper(r, &FilterField::Set);
+                }
+            }
+            Rule::zone => {
+                let i = p.into_inner();
+                for r in i {
+                    s += &CardFilter::helper(r, &FilterField::Zone);
+                }
+            }
             Rule::tag => {
                 //TODO: Check if brackets are necessary here.
                 let i = p.into_inner();
                 for r in i {
                     if r.as_str() == "!" {
                         s += "tags IS NULL";
-                    } else if r.as_rule() == Rule::text_token {
+                    } else if r.as_rule() == Rule::tag_token {
                         s += "tags IS NOT NULL AND (";
                         s += &CardFilter::helper(r, &FilterField::Tag);
                         s += ")"
@@ -248,13 +333,13 @@ WHERE deck_contents.deck = {}",
                 };
                 s = format!("ORDER BY {field} {order};");
             }
-            Rule::text_token => {
+            Rule::text_token | Rule::tag_token => {
                 let i = p.into_inner();
                 for r in i {
                     s += &CardFilter::helper(r, mode);
                 }
             }
-            Rule::bracketed_text => {
+            Rule::bracketed_text | Rule::bracketed_tag => {
                 s.push('(');
                 let i = p.into_inner();
                 for r in i {
@@ -274,7 +359,7 @@ WHERE deck_contents.deck = {}",
                     s += &CardFilter::helper(r, mode);
                 }
             }
-            Rule::word | Rule::phrase => {
+            Rule::word | Rule::phrase | Rule::tag_word => {
                 let mut a = p.as_str().replace('\"', "");
                 let mut flag = " ";
                 if let Some(i) = p.into_inner().next() {
@@ -309,11 +394,30 @@ WHERE deck_contents.deck = {}",
                     }
                 }
 
+                // Zones match by their full name or one of these aliases, so m doesn't also find maybeboard.
+                if mode == &FilterField::Zone {
+                    match a.as_str() {
+                        "c" | "cmdr" => a = String::from("commander"),
+                        "m" => a = String::from("main"),
+                        "mb" | "maybe" => a = String::from("maybeboard"),
+                        "sb" | "side" => a = String::from("sideboard"),
+                        "consider" => a = String::from("considering"),
+                        _ => {}
+                    }
+                }
+
                 let (field, comparison, capture) = match mode {
                     FilterField::Name => ("name", "LIKE", format!("\"%{a}%\"")),
                     FilterField::Text => ("card_text", "LIKE", format!("\"%{a}%\"")),
                     FilterField::Type => ("types", "LIKE", format!("\"%{a}%\"")),
-                    FilterField::Tag => ("tags", "REGEXP", format!(r#"'(?:\||^){a}(?:$|\|)'"#)),
+                    // A tag also matches its children, so removal finds removal/creature.
+                    FilterField::Tag => ("tags", "REGEXP", format!(r#"'(?:\||^){a}(?:/[^|]*)?(?:$|\|)'"#)),
+                    FilterField::Set => (
+                        "name",
+                        "IN",
+                        format!("(SELECT card_name FROM printings WHERE set_code = '{a}' COLLATE NOCASE)"),
+                    ),
+                    FilterField::Zone => ("zone", "LIKE", format!("\"{a}\"")),
                     _ => ("", "", String::new()),
                 };
 
@@ -526,7 +630,9 @@ pub fn initdb(conn: &Connection) -> Result<()> {
         "create table if not exists rulings (
             id integer primary key,
             date text not null,
-            text text not null 
+            text text not null,
+            card_name text,
+            foreign key (card_name) references cards(name)
         )",
         [],
     )?;
@@ -537,7 +643,22 @@ pub fn initdb(conn: &Connection) -> Result<()> {
             code text not null unique, 
             name text not null unique,
             date text not null,
-            set_type text NOT NULL
+            set_type text NOT NULL,
+            import_version integer not null default 0
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "create table if not exists printings (
+            id integer primary key,
+            card_name text not null,
+            set_code text not null,
+            number text not null,
+            rarity text not null,
+            date text not null,
+            foreign key (card_name) references cards(name),
+            unique (card_name, set_code, number)
         )",
         [],
     )?;
@@ -573,22 +694,201 @@ pub fn initdb(conn: &Connection) -> Result<()> {
             commander2 text,
             deck_type text not null,
             notes text,
+            companion text,
+            labels text,
+            modified text,
+            archived integer not null default 0,
+            deleted text,
             foreign key (commander) references cards(name),
             foreign key (commander2) references cards(name))",
         [],
     )?;
 
     conn.execute(
-        "create table if not exists deck_contents2 (
+        "create table if not exists deck_contents (
             id integer primary key,
             card_name text not null,
             deck integer not null,
             tags text,
+            printing_set text,
+            printing_number text,
+            foil integer not null default 0,
+            printing_price real,
+            printing_price_date text,
+            quantity integer not null default 1,
+            zone text not null default 'main',
             foreign key (deck) references decks(id) ON DELETE CASCADE,
             unique (deck, card_name) on conflict ignore)",
         [],
     )?;
 
+    conn.execute(
+        "create table if not exists snapshots (
+            id integer primary key,
+            deck integer not null,
+            name text not null,
+            created text not null,
+            commander text not null,
+            commander2 text,
+            companion text,
+            notes text,
+            foreign key (deck) references decks(id) ON DELETE CASCADE)",
+        [],
+    )?;
+
+    conn.execute(
+        "create table if not exists snapshot_contents (
+            id integer primary key,
+            snapshot integer not null,
+            card_name text not null,
+            tags text,
+            quantity integer not null default 1,
+            zone text not null default 'main',
+            printing_set text,
+            printing_number text,
+            foil integer not null default 0,
+            foreign key (snapshot) references snapshots(id) ON DELETE CASCADE)",
+        [],
+    )?;
+
+    // Each row holds a card's state in a deck before an edit (or before an undo, for redo rows).
+    // Rows with the same batch were changed together and are restored together.
+    conn.execute(
+        "create table if not exists deck_history (
+            id integer primary key,
+            deck integer not null,
+            batch integer not null,
+            redo integer not null default 0,
+            card_name text not null,
+            present integer not null,
+            tags text,
+            quantity integer,
+            zone text,
+            printing_set text,
+            printing_number text,
+            foil integer,
+            foreign key (deck) references decks(id) ON DELETE CASCADE)",
+        [],
+    )?;
+
+    // The deck's own commanders, companion and notes before an edit in the batch that changed them.
+    conn.execute(
+        "create table if not exists deck_row_history (
+            id integer primary key,
+            deck integer not null,
+            batch integer not null,
+            redo integer not null default 0,
+            commander text not null,
+            commander2 text,
+            companion text,
+            notes text,
+            foreign key (deck) references decks(id) ON DELETE CASCADE)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+fn rvcol(conn: &Connection, table: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({});", table))?;
+    let cols = stmt.query_map([], |row| row.get::<usize, String>(1))?.collect();
+    cols
+}
+
+pub fn migratedb(conn: &Connection) -> Result<()> {
+    initdb(conn)?;
+
+    if !rvcol(conn, "rulings")?.contains(&String::from("card_name")) {
+        conn.execute(
+            "ALTER TABLE rulings ADD COLUMN card_name text REFERENCES cards(name)",
+            [],
+        )?;
+    }
+    conn.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS rulings_card ON rulings (card_name, date, text)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS printings_set ON printings (set_code)",
+        [],
+    )?;
+
+    let cols = rvcol(conn, "deck_contents")?;
+    if !cols.contains(&String::from("printing_set")) {
+        conn.execute_batch(
+            "ALTER TABLE deck_contents ADD COLUMN printing_set text;
+            ALTER TABLE deck_contents ADD COLUMN printing_number text;
+            ALTER TABLE deck_contents ADD COLUMN foil integer NOT NULL DEFAULT 0;
+            ALTER TABLE deck_contents ADD COLUMN printing_price real;
+            ALTER TABLE deck_contents ADD COLUMN printing_price_date text;",
+        )?;
+    }
+    if !cols.contains(&String::from("quantity")) {
+        conn.execute(
+            "ALTER TABLE deck_contents ADD COLUMN quantity integer NOT NULL DEFAULT 1",
+            [],
+        )?;
+    }
+    if !cols.contains(&String::from("zone")) {
+        // Zones replace the main and side tags, so those are moved over and removed.
+        conn.execute_batch(
+            "BEGIN TRANSACTION;
+            ALTER TABLE deck_contents ADD COLUMN zone text NOT NULL DEFAULT 'main';
+            UPDATE deck_contents SET zone = CASE
+                WHEN ('|' || tags || '|') LIKE '%|main|%' THEN 'main'
+                WHEN ('|' || tags || '|') LIKE '%|side|%' THEN 'sideboard'
+                ELSE 'considering' END;
+            UPDATE deck_contents SET zone = 'commander'
+                WHERE card_name IN (SELECT commander FROM decks WHERE id = deck_contents.deck)
+                OR card_name IN (SELECT commander2 FROM decks WHERE id = deck_contents.deck)
+                OR card_name IN (SELECT related_cards FROM cards WHERE name IN (
+                    SELECT commander FROM decks WHERE id = deck_contents.deck
+                    UNION SELECT commander2 FROM decks WHERE id = deck_contents.deck));
+            UPDATE deck_contents SET tags = NULLIF(TRIM(REPLACE(REPLACE(
+                '|' || tags || '|', '|main|', '|'), '|side|', '|'), '|'), '')
+                WHERE tags IS NOT NULL;
+            COMMIT TRANSACTION;",
+        )?;
+    }
+    let cols = rvcol(conn, "decks")?;
+    if !cols.contains(&String::from("companion")) {
+        conn.execute("ALTER TABLE decks ADD COLUMN companion text", [])?;
+    }
+    if !cols.contains(&String::from("labels")) {
+        // Decks from before the column count as modified now, so they still sort and show a date.
+        conn.execute_batch(
+            "ALTER TABLE decks ADD COLUMN labels text;
+            ALTER TABLE decks ADD COLUMN modified text;
+            UPDATE decks SET modified = datetime('now', 'localtime');",
+        )?;
+    }
+    if !cols.contains(&String::from("archived")) {
+        conn.execute_batch(
+            "ALTER TABLE decks ADD COLUMN archived integer NOT NULL DEFAULT 0;
+            ALTER TABLE decks ADD COLUMN deleted text;",
+        )?;
+    }
+
+    // Any change to a deck or its cards marks the deck as modified; refreshing cached prices
+    // doesn't. The update trigger is recreated since older databases fire it on any column.
+    conn.execute_batch(
+        "CREATE TRIGGER IF NOT EXISTS deck_contents_inserted AFTER INSERT ON deck_contents BEGIN
+            UPDATE decks SET modified = datetime('now', 'localtime') WHERE id = NEW.deck;
+        END;
+        DROP TRIGGER IF EXISTS deck_contents_updated;
+        CREATE TRIGGER deck_contents_updated
+        AFTER UPDATE OF tags, quantity, zone, printing_set, printing_number, foil ON deck_contents BEGIN
+            UPDATE decks SET modified = datetime('now', 'localtime') WHERE id = NEW.deck;
+        END;
+        CREATE TRIGGER IF NOT EXISTS deck_contents_deleted AFTER DELETE ON deck_contents BEGIN
+            UPDATE decks SET modified = datetime('now', 'localtime') WHERE id = OLD.deck;
+        END;
+        CREATE TRIGGER IF NOT EXISTS decks_updated
+        AFTER UPDATE OF name, commander, commander2, companion, notes ON decks BEGIN
+            UPDATE decks SET modified = datetime('now', 'localtime') WHERE id = NEW.id;
+        END;",
+    )?;
+
     Ok(())
 }
 
@@ -615,42 +915,83 @@ pub fn updatedb(conn: &Connection, mut sets: Vec<Set>) -> Result<usize> {
         let mut stmt = conn.prepare("ALTER TABLE sets ADD COLUMN set_type text NOT NULL")?;
         stmt.execute([]).unwrap();
     }
+    if !cols.contains(&String::from("import_version")) {
+        let mut stmt = conn.prepare("ALTER TABLE sets ADD COLUMN import_version integer NOT NULL DEFAULT 0")?;
+        stmt.execute([]).unwrap();
+    }
 
-    stmt = conn.prepare("SELECT * FROM sets;")?;
-    let rows: Result<Vec<Set>> = stmt
-        .query_map([], |row| {
-            Ok(Set {
-                code: row.get(1)?,
-                name: row.get(2)?,
-                date: row.get(3)?,
-                set_type: row.get(4)?,
-            })
-        })?
+    stmt = conn.prepare("SELECT code, import_version FROM sets;")?;
+    let rows: Result<HashMap<String, i32>> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
         .collect();
     let existing_sets = rows?;
     let mut set_stmt = conn.prepare(
-        "INSERT INTO sets (code, name, date, set_type) VALUES (:code, :name, :date, :set_type);",
+        "INSERT INTO sets (code, name, date, set_type, import_version)
+        VALUES (:code, :name, :date, :set_type, :version)
+        ON CONFLICT (code) DO UPDATE SET import_version = :version;",
     )?;
 
     let now = Utc::now();
     let date = format!("{}-{:02}-{:02}", now.year(), now.month(), now.day());
+    let mut skipped = Vec::new();
 
     for set in sets {
-        if !existing_sets.contains(&set) && set.date <= date {
-            println!("New set found: {}. Adding to existing sets.", set.name);
+        if set.date > date {
+            continue;
+        }
+        match existing_sets.get(&set.code) {
+            Some(v) if *v >= IMPORT_VERSION => continue,
+            Some(_) => println!("Set {} was imported by an older version. Reading it again.", set.name),
+            None => println!("New set found: {}. Adding to existing sets.", set.name),
+        }
 
-            let vjc = rvjc(&set.code).unwrap();
-            let (success, _failure) = ivcfjsmap(conn, vjc)?;
-            // println!("Added {} cards, with {} not added.", success, failure);
+        let vjc = match rvjc_retry(&set.code, FETCH_ATTEMPTS) {
+            Ok(vjc) => vjc,
+            Err(e) => {
+                println!("Skipping {}: {}", set.name, e);
+                skipped.push((set.name, e.to_string()));
+                continue;
+            }
+        };
 
+        // The set row is committed with its cards, so an interrupted update resumes from the
+        // first set that never made it into the sets table.
+        let res = transact(conn, || {
+            let summary = ivcfjsmap(conn, vjc, &set)?;
             set_stmt.execute(named_params! {
                 ":code": set.code,
                 ":name": set.name,
                 ":date": set.date,
-                ":set_type": set.set_type
+                ":set_type": set.set_type,
+                ":version": IMPORT_VERSION
             })?;
-            new_cards += success;
+            Ok(summary)
+        });
+
+        match res {
+            Ok(summary) => {
+                println!(
+                    "Added {} cards from {}. {} were already known and {} are not commander-legal.",
+                    summary.added, set.name, summary.duplicates, summary.illegal
+                );
+                if summary.failed > 0 {
+                    println!("{} cards from {} could not be added.", summary.failed, set.name);
+                }
+                new_cards += summary.added;
+            }
+            Err(e) => {
+                println!("Skipping {}: {}", set.name, e);
+                skipped.push((set.name, e.to_string()));
+            }
+        }
+    }
+
+    if !skipped.is_empty() {
+        println!("{} sets could not be imported:", skipped.len());
+        for (name, e) in skipped {
+            println!("  {}: {}", name, e);
         }
+        println!("Run the update command again to retry them.");
     }
 
     Ok(new_cards)
@@ -678,23 +1019,22 @@ pub fn ucfsqlite(conn_primary: &Connection, conn_secondary: &Connection) -> Resu
     let (mut success, mut failure) = (0, 0);
 
     println!("Generated card array. {} total cards.", result.len());
-    conn_primary.execute_batch("BEGIN TRANSACTION;")?;
-
-    for (name, rarity) in result {
-        match stmt.execute(named_params! {
-            ":name": name,
-            ":rarity": rarity,
-        }) {
-            Ok(_) => {
-                success += 1;
-            }
-            Err(_) => {
-                failure += 1;
+    transact(conn_primary, || {
+        for (name, rarity) in result {
+            match stmt.execute(named_params! {
+                ":name": name,
+                ":rarity": rarity,
+            }) {
+                Ok(_) => {
+                    success += 1;
+                }
+                Err(_) => {
+                    failure += 1;
+                }
             }
         }
-    }
-
-    conn_primary.execute_batch("COMMIT TRANSACTION;")?;
+        Ok(())
+    })?;
 
     println!(
         "{} cards changed successfully. {} failures.",
@@ -704,19 +1044,27 @@ pub fn ucfsqlite(conn_primary: &Connection, conn_secondary: &Connection) -> Resu
     Ok(())
 }
 
-pub fn ivcfjsmap(conn: &Connection, vjc: Vec<JsonCard>) -> Result<(usize, usize)> {
+pub fn ivcfjsmap(conn: &Connection, vjc: Vec<JsonCard>, set: &Set) -> Result<SetImport> {
     let mut stmt = conn.prepare("INSERT INTO cards (
         name, mana_cost, cmc, types, card_text, power, toughness, loyalty, color_identity, related_cards, layout, side, legalities, rarity
     ) VALUES (
             :name, :mana_cost, :cmc, :types, :card_text, :power, :toughness, :loyalty, :color_identity, :related_cards, :layout, :side, :legalities, :rarity
-    )")?;
-    let (mut success, mut failure) = (0, 0);
+    ) ON CONFLICT (name) DO NOTHING")?;
+    let mut ruling_stmt = conn.prepare(
+        "INSERT INTO rulings (card_name, date, text) VALUES (:card_name, :date, :text)
+        ON CONFLICT DO NOTHING",
+    )?;
+    let mut printing_stmt = conn.prepare(
+        "INSERT INTO printings (card_name, set_code, number, rarity, date)
+        VALUES (:card_name, :set_code, :number, :rarity, :date)
+        ON CONFLICT DO NOTHING",
+    )?;
+    let mut summary = SetImport::default();
 	let mut melds = Vec::new();
 	let mut meld_bases = Vec::new();
 	
 
     print!("Found {} cards. ", vjc.len());
-    conn.execute_batch("BEGIN TRANSACTION;")?;
 
     for c in vjc {
         let mut name = c.name.clone();
@@ -751,48 +1099,79 @@ pub fn ivcfjsmap(conn: &Connection, vjc: Vec<JsonCard>) -> Result<(usize, usize)
             _ => {}
         }
 
-        let c = c.clone();
-
-        match stmt.execute(named_params! {
-            ":name": name,
-            ":mana_cost": c.mana_cost,
-            ":cmc": c.cmc,
-            ":types": c.types,
-            ":card_text": c.text,
-            ":power": c.power,
-            ":toughness": c.toughness,
-            ":loyalty": c.loyalty,
-            ":color_identity": c.color_identity.join("|"),
-            ":related_cards": related,
-            ":layout": c.layout,
-            ":side": side,
-            ":legalities": c.legalities.to_string(),
-            ":rarity": c.rarity,
-        }) {
-            Ok(_) => {
-                success += 1;
+        // A card that can't be stored is counted and skipped. Its savepoint undoes any rows it
+        // left behind, while errors outside of it roll back the whole set.
+        conn.execute_batch("SAVEPOINT card;")?;
+        let res = (|| -> Result<bool> {
+            // Reprints of cards already in the database are expected and skipped.
+            let changed = stmt.execute(named_params! {
+                ":name": name,
+                ":mana_cost": c.mana_cost,
+                ":cmc": c.cmc,
+                ":types": c.types,
+                ":card_text": c.text,
+                ":power": c.power,
+                ":toughness": c.toughness,
+                ":loyalty": c.loyalty,
+                ":color_identity": c.color_identity.join("|"),
+                ":related_cards": related,
+                ":layout": c.layout,
+                ":side": side,
+                ":legalities": c.legalities.to_string(),
+                ":rarity": c.rarity,
+            })?;
+            if changed == 0 {
+                // Legalities change over time, so reprints refresh them.
+                conn.execute(
+                    "UPDATE cards SET legalities = :legalities WHERE name = :name",
+                    named_params! {":legalities": c.legalities.to_string(), ":name": name},
+                )?;
             }
-            Err(_) => {
-                // Usually an unset or duplicate cards
-                failure += 1;
+
+            for r in &c.rulings {
+                ruling_stmt.execute(named_params! {":card_name": name, ":date": r.date, ":text": r.text})?;
+            }
+            printing_stmt.execute(named_params! {
+                ":card_name": name,
+                ":set_code": set.code,
+                ":number": c.number,
+                ":rarity": c.rarity,
+                ":date": set.date,
+            })?;
+            Ok(changed == 0)
+        })();
+        match res {
+            Ok(duplicate) => {
+                conn.execute_batch("RELEASE card;")?;
+                if duplicate {
+                    summary.duplicates += 1;
+                } else {
+                    summary.added += 1;
+                }
+            }
+            Err(e) => {
+                conn.execute_batch("ROLLBACK TO card; RELEASE card;")?;
+                println!("Could not add {}: {}", name, e);
+                summary.failed += 1;
             }
         }
     }
 
-    let illegal = conn.execute(
+    summary.illegal = conn.execute(
         "DELETE
 FROM cards
 WHERE legalities = \"\"",
         [],
     )?;
-
-    println!(
-        "Added {} to database. {} were not added (duplicate or illegal).",
-        success,
-        failure + illegal
-    );
-
-    conn.execute_batch("COMMIT TRANSACTION;")?;
+    summary.added = summary.added.saturating_sub(summary.illegal);
+    conn.execute(
+        "DELETE FROM rulings WHERE card_name NOT IN (SELECT name FROM cards)",
+        [],
+    )?;
+    conn.execute(
+        "DELETE FROM printings WHERE card_name NOT IN (SELECT name FROM cards)",
+        [],
+    )?;
 
 	for cn in &melds {
 		let mut a = String::new();
@@ -806,16 +1185,16 @@ WHERE legalities = \"\"",
 				}
 			}
 		}
-		let _ = ucfm(conn, &a, &b, cn);
-		_ = ucfm(conn, &b, &a, cn);
+		ucfm(conn, &a, &b, cn)?;
+		ucfm(conn, &b, &a, cn)?;
 		if a < b {
-			_ = ucfm(conn, &cn, &a, &b);
+			ucfm(conn, &cn, &a, &b)?;
 		} else {
-			_ = ucfm(conn, &cn, &b, &a);
+			ucfm(conn, &cn, &b, &a)?;
 		};
 	}
 
-    Ok((success, failure))
+    Ok(summary)
 }
 
 fn ucfm(conn: &Connection, cn: &String, ran: &String, rbn: &String) -> Result<()> {
@@ -870,121 +1249,810 @@ pub fn dcntodc(conn: &Connection, c: &str, did: i32) -> Result<()> {
     Ok(())
 }
 
-pub fn ttindc(conn: &Connection, c: &str, t: &String, did: i32) -> Option<Card> {
-    let mut stmt = conn
-        .prepare(
-            "UPDATE deck_contents 
-        SET tags = :tags
-        WHERE card_name = :name
-        AND deck = :did;",
-        )
-        .unwrap();
+// A card's row in a deck, as kept in the deck's edit history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CardState {
+    tags: Option<String>,
+    quantity: u32,
+    zone: String,
+    printing_set: Option<String>,
+    printing_number: Option<String>,
+    foil: bool,
+}
 
-    let mut card = rcfn(conn, c, Some(did)).unwrap();
-    let cc = if t.eq(&String::from("main")) || t.eq(&String::from("main")) {
-        match &card.lo {
-            CardLayout::Paired(_, _, n) => Some(rcfn(conn, n, Some(did)).unwrap()),
-            _ => None,
-        }
-    } else {
-        None
-    };
+// The deck's own fields that an edit can change alongside its cards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeckRowState {
+    commander: String,
+    commander2: Option<String>,
+    companion: Option<String>,
+    notes: Option<String>,
+}
 
-    let tags = if card.tags.contains(t) {
-        card.tags
-            .remove(card.tags.iter().position(|x| x == t).unwrap());
-        if card.tags.is_empty() {
-            None
-        } else {
-            Some(card.tags.join("|"))
-        }
-    } else {
-        card.tags.push(t.clone());
-        card.tags.sort();
-        Some(card.tags.join("|"))
-    };
+// A deck before an edit, as read by rvcsfd and saved by ihfd.
+#[derive(Debug, Default)]
+pub struct DeckState {
+    row: Option<DeckRowState>,
+    cards: Vec<(String, Option<CardState>)>,
+}
 
-    stmt.execute(named_params! {":tags": tags, ":name": c, ":did": did})
-        .unwrap();
-    if let Some(mut cc) = cc {
-        if !card.tags.contains(t) && cc.tags.contains(t) {
-            cc.tags.remove(cc.tags.iter().position(|x| x == t).unwrap());
-        } else if card.tags.contains(t) && !cc.tags.contains(t) {
-            cc.tags.push(t.clone());
-        }
-        let t = if cc.tags.is_empty() {
-            None
-        } else {
-            Some(cc.tags.join("|"))
-        };
-        stmt.execute(named_params! {":tags": t, ":name": cc.name, ":did": did})
-            .unwrap();
-    }
-    Some(card)
+fn rrsfd(conn: &Connection, did: i32) -> Result<DeckRowState> {
+    conn.query_row(
+        "SELECT commander, commander2, companion, notes FROM decks WHERE id = :did",
+        named_params! {":did": did},
+        |row| {
+            Ok(DeckRowState {
+                commander: row.get(0)?,
+                commander2: row.get(1)?,
+                companion: row.get(2)?,
+                notes: row.get(3)?,
+            })
+        },
+    )
 }
 
-pub fn utindc(conn: &Connection, change: TagChange, cf: &CardFilter) {
-    let mut stmt = conn
-        .prepare(
-            "UPDATE deck_contents 
-        SET tags = :tags
+fn ursindc(conn: &Connection, s: &DeckRowState, did: i32) -> Result<()> {
+    conn.execute(
+        "UPDATE decks
+        SET commander = :commander, commander2 = :commander2, companion = :companion, notes = :notes
+        WHERE id = :did",
+        named_params! {
+            ":commander": s.commander,
+            ":commander2": s.commander2,
+            ":companion": s.companion,
+            ":notes": s.notes,
+            ":did": did,
+        },
+    )?;
+    Ok(())
+}
+
+fn ihrs(conn: &Connection, batch: i64, redo: bool, s: &DeckRowState, did: i32) -> Result<()> {
+    conn.execute(
+        "INSERT INTO deck_row_history (deck, batch, redo, commander, commander2, companion, notes)
+        VALUES (:did, :batch, :redo, :commander, :commander2, :companion, :notes)",
+        named_params! {
+            ":did": did,
+            ":batch": batch,
+            ":redo": redo,
+            ":commander": s.commander,
+            ":commander2": s.commander2,
+            ":companion": s.companion,
+            ":notes": s.notes,
+        },
+    )?;
+    Ok(())
+}
+
+// None when the card isn't in the deck.
+fn rcsfd(conn: &Connection, cn: &str, did: i32) -> Result<Option<CardState>> {
+    let state = conn.query_row(
+        "SELECT tags, quantity, zone, printing_set, printing_number, foil
+        FROM deck_contents
         WHERE card_name = :name
         AND deck = :did;",
-        )
-        .unwrap();
-    match change {
-        TagChange::Delete(tag) => {
-            if conn.execute_batch("BEGIN TRANSACTION;").is_err() {
-                panic!("Issue with update");
-            }
-            let query = cf.make_query(false, format!("tag:{tag}").as_str());
-            let vc = rvcfcf(conn, &query).unwrap();
-            for mut c in vc {
-                let i = c.tags.iter().position(|s| s == &tag).unwrap();
-                c.tags.remove(i);
-                let tags = if c.tags.is_empty() {
-                    None
-                } else {
-                    Some(c.tags.join("|"))
-                };
-                stmt.execute(named_params! {":tags": tags, ":name": c.name, ":did": cf.did})
-                    .unwrap();
-            }
-            if conn.execute_batch("COMMIT TRANSACTION;").is_err() {
-                panic!("Issue with update");
-            }
-        }
-        TagChange::Change(old, new) => {
-            if conn.execute_batch("BEGIN TRANSACTION;").is_err() {
-                panic!("Issue with update");
-            }
-            let query = cf.make_query(false, format!("tag:{old}").as_str());
-            let vc = rvcfcf(conn, &query).unwrap();
-            for mut c in vc {
-                let i = c.tags.iter().position(|s| s == &old).unwrap();
-                c.tags.remove(i);
-                c.tags.push(new.clone());
-                c.tags.sort();
-                let tags = c.tags.join("|");
-                stmt.execute(named_params! {":tags": tags, ":name": c.name, ":did": cf.did})
-                    .unwrap();
-            }
-            if conn.execute_batch("COMMIT TRANSACTION;").is_err() {
-                panic!("Issue with update");
-            }
-        }
-        TagChange::Insert(_) => {}
+        named_params! {":name": cn, ":did": did},
+        |row| {
+            Ok(CardState {
+                tags: row.get(0)?,
+                quantity: row.get(1)?,
+                zone: row.get(2)?,
+                printing_set: row.get(3)?,
+                printing_number: row.get(4)?,
+                foil: row.get(5)?,
+            })
+        },
+    );
+    match state {
+        Ok(s) => Ok(Some(s)),
+        Err(Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
     }
 }
 
-pub fn cindid(conn: &Connection, c: &str, did: i32) -> bool {
-    let a = conn.query_row(
-        "SELECT card_name FROM deck_contents WHERE card_name = ? AND deck = ?;",
-        params![c, did],
-        |_| Ok(0),
-    );
-
-    a.is_ok()
+// Reads the state of some cards and their other faces in a deck, along with the deck's
+// commanders, companion and notes. Passing it to ihfd after an edit makes that edit undoable.
+pub fn rvcsfd(conn: &Connection, vcn: &[String], did: i32) -> Result<DeckState> {
+    let mut faces: Vec<String> = Vec::new();
+    for cn in vcn {
+        faces.push(cn.clone());
+        match rcfn(conn, cn, None).map(|c| c.lo) {
+            Ok(CardLayout::Paired(_, _, n)) => faces.push(n),
+            Ok(CardLayout::Meld(_, n, m)) => faces.extend([n, m]),
+            _ => {}
+        }
+    }
+    faces.sort();
+    faces.dedup();
+    let cards = faces
+        .into_iter()
+        .map(|cn| rcsfd(conn, &cn, did).map(|s| (cn, s)))
+        .collect::<Result<_>>()?;
+    Ok(DeckState { row: Some(rrsfd(conn, did)?), cards })
+}
+
+// Puts a card's row back the way it was.
+fn ucsindc(conn: &Connection, cn: &str, state: &Option<CardState>, did: i32) -> Result<()> {
+    let s = match state {
+        Some(s) => s,
+        None => return dcntodc(conn, cn, did),
+    };
+    conn.execute(
+        "INSERT INTO deck_contents (card_name, deck) VALUES (:name, :did)",
+        named_params! {":name": cn, ":did": did},
+    )?;
+    // The cached printing price only holds while the printing stays the same.
+    conn.execute(
+        "UPDATE deck_contents
+        SET tags = :tags, quantity = :quantity, zone = :zone,
+        printing_price = CASE WHEN printing_set IS :set AND printing_number IS :number THEN printing_price END,
+        printing_price_date = CASE WHEN printing_set IS :set AND printing_number IS :number THEN printing_price_date END,
+        printing_set = :set, printing_number = :number, foil = :foil
+        WHERE card_name = :name
+        AND deck = :did;",
+        named_params! {
+            ":tags": s.tags,
+            ":quantity": s.quantity,
+            ":zone": s.zone,
+            ":set": s.printing_set,
+            ":number": s.printing_number,
+            ":foil": s.foil,
+            ":name": cn,
+            ":did": did,
+        },
+    )?;
+    Ok(())
+}
+
+fn ihcs(conn: &Connection, batch: i64, redo: bool, cn: &str, state: &Option<CardState>, did: i32) -> Result<()> {
+    let empty = CardState {
+        tags: None,
+        quantity: 0,
+        zone: String::new(),
+        printing_set: None,
+        printing_number: None,
+        foil: false,
+    };
+    let s = state.as_ref().unwrap_or(&empty);
+    conn.execute(
+        "INSERT INTO deck_history
+        (deck, batch, redo, card_name, present, tags, quantity, zone, printing_set, printing_number, foil)
+        VALUES (:did, :batch, :redo, :name, :present, :tags, :quantity, :zone, :set, :number, :foil)",
+        named_params! {
+            ":did": did,
+            ":batch": batch,
+            ":redo": redo,
+            ":name": cn,
+            ":present": state.is_some(),
+            ":tags": s.tags,
+            ":quantity": s.quantity,
+            ":zone": s.zone,
+            ":set": s.printing_set,
+            ":number": s.printing_number,
+            ":foil": s.foil,
+        },
+    )?;
+    Ok(())
+}
+
+fn rnhb(conn: &Connection) -> Result<i64> {
+    conn.query_row(
+        "SELECT COALESCE(MAX(batch), 0) + 1 FROM (
+            SELECT batch FROM deck_history UNION ALL SELECT batch FROM deck_row_history
+        )",
+        [],
+        |row| row.get(0),
+    )
+}
+
+// Saves the earlier state of the cards and deck fields an edit changed as one step of the
+// deck's history, which also clears anything waiting to be redone. Returns whether anything
+// changed.
+pub fn ihfd(conn: &Connection, before: DeckState, did: i32) -> Result<bool> {
+    let mut changed = Vec::new();
+    for (cn, state) in before.cards {
+        if rcsfd(conn, &cn, did)? != state {
+            changed.push((cn, state));
+        }
+    }
+    let row = match before.row {
+        Some(row) if rrsfd(conn, did)? != row => Some(row),
+        _ => None,
+    };
+    if changed.is_empty() && row.is_none() {
+        return Ok(false);
+    }
+    transact(conn, || {
+        for table in ["deck_history", "deck_row_history"] {
+            conn.execute(
+                &format!("DELETE FROM {table} WHERE deck = :did AND redo = 1"),
+                named_params! {":did": did},
+            )?;
+        }
+        let batch = rnhb(conn)?;
+        for (cn, state) in &changed {
+            ihcs(conn, batch, false, cn, state, did)?;
+        }
+        if let Some(row) = &row {
+            ihrs(conn, batch, false, row, did)?;
+        }
+        for table in ["deck_history", "deck_row_history"] {
+            conn.execute(
+                &format!(
+                    "DELETE FROM {table}
+                    WHERE deck = :did
+                    AND batch NOT IN (
+                        SELECT DISTINCT batch FROM (
+                            SELECT batch FROM deck_history WHERE deck = :did
+                            UNION ALL SELECT batch FROM deck_row_history WHERE deck = :did
+                        ) ORDER BY batch DESC LIMIT :len
+                    );"
+                ),
+                named_params! {":did": did, ":len": HISTORY_LEN},
+            )?;
+        }
+        Ok(true)
+    })
+}
+
+// Undoes the deck's last edit, or redoes the last undone one. Returns how many cards changed,
+// counting a change to the deck's own fields as one more.
+pub fn uhfd(conn: &Connection, did: i32, redo: bool) -> Result<usize> {
+    let batch: Option<i64> = conn.query_row(
+        "SELECT MAX(batch) FROM (
+            SELECT batch FROM deck_history WHERE deck = :did AND redo = :redo
+            UNION ALL SELECT batch FROM deck_row_history WHERE deck = :did AND redo = :redo
+        )",
+        named_params! {":did": did, ":redo": redo},
+        |row| row.get(0),
+    )?;
+    let batch = match batch {
+        Some(b) => b,
+        None => return Ok(0),
+    };
+    let mut stmt = conn.prepare(
+        "SELECT card_name, present, tags, quantity, zone, printing_set, printing_number, foil
+        FROM deck_history
+        WHERE batch = :batch
+        ORDER BY id DESC;",
+    )?;
+    let rows: Vec<(String, Option<CardState>)> = stmt
+        .query_map(named_params! {":batch": batch}, |row| {
+            let state = match row.get::<usize, bool>(1)? {
+                true => Some(CardState {
+                    tags: row.get(2)?,
+                    quantity: row.get(3)?,
+                    zone: row.get(4)?,
+                    printing_set: row.get(5)?,
+                    printing_number: row.get(6)?,
+                    foil: row.get(7)?,
+                }),
+                false => None,
+            };
+            Ok((row.get(0)?, state))
+        })?
+        .collect::<Result<_>>()?;
+    let mut stmt = conn.prepare(
+        "SELECT commander, commander2, companion, notes FROM deck_row_history WHERE batch = :batch",
+    )?;
+    let row: Option<DeckRowState> = stmt
+        .query_map(named_params! {":batch": batch}, |row| {
+            Ok(DeckRowState {
+                commander: row.get(0)?,
+                commander2: row.get(1)?,
+                companion: row.get(2)?,
+                notes: row.get(3)?,
+            })
+        })?
+        .next()
+        .transpose()?;
+    transact(conn, || {
+        let next = rnhb(conn)?;
+        for (cn, state) in &rows {
+            ihcs(conn, next, !redo, cn, &rcsfd(conn, cn, did)?, did)?;
+            ucsindc(conn, cn, state, did)?;
+        }
+        if let Some(row) = &row {
+            ihrs(conn, next, !redo, &rrsfd(conn, did)?, did)?;
+            ursindc(conn, row, did)?;
+        }
+        for table in ["deck_history", "deck_row_history"] {
+            conn.execute(
+                &format!("DELETE FROM {table} WHERE batch = :batch"),
+                named_params! {":batch": batch},
+            )?;
+        }
+        Ok(rows.len() + row.iter().count())
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub id: i32,
+    pub name: String,
+    pub created: String,
+}
+
+// What changed between two versions of a deck.
+#[derive(Debug, Default, PartialEq)]
+pub struct DeckDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    // Each card with the tags it lost and the tags it gained.
+    pub retagged: Vec<(String, Vec<String>, Vec<String>)>,
+    // Each card with its old and new zone.
+    pub moved: Vec<(String, String, String)>,
+    // Each card with its old and new number of copies.
+    pub recounted: Vec<(String, u32, u32)>,
+    pub commanders: Option<(String, String)>,
+    pub notes: bool,
+}
+
+impl DeckDiff {
+    pub fn is_empty(&self) -> bool {
+        *self == DeckDiff::default()
+    }
+}
+
+// Saves the deck's cards, tags, commanders and notes under a name.
+pub fn isfd(conn: &Connection, did: i32, name: &str) -> Result<i32> {
+    transact(conn, || {
+        conn.execute(
+            "INSERT INTO snapshots (deck, name, created, commander, commander2, companion, notes)
+            SELECT id, :name, datetime('now', 'localtime'), commander, commander2, companion, notes
+            FROM decks WHERE id = :did;",
+            named_params! {":did": did, ":name": name},
+        )?;
+        let sid = conn.last_insert_rowid() as i32;
+        conn.execute(
+            "INSERT INTO snapshot_contents
+            (snapshot, card_name, tags, quantity, zone, printing_set, printing_number, foil)
+            SELECT :sid, card_name, tags, quantity, zone, printing_set, printing_number, foil
+            FROM deck_contents WHERE deck = :did;",
+            named_params! {":did": did, ":sid": sid},
+        )?;
+        Ok(sid)
+    })
+}
+
+// Newest first.
+pub fn rvsfd(conn: &Connection, did: i32) -> Result<Vec<Snapshot>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, name, created FROM snapshots WHERE deck = :did ORDER BY id DESC;",
+    )?;
+    let a = stmt
+        .query_map(named_params! {":did": did}, |row| {
+            Ok(Snapshot {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                created: row.get(2)?,
+            })
+        })?
+        .collect();
+    a
+}
+
+pub fn dsfd(conn: &Connection, sid: i32) -> Result<()> {
+    transact(conn, || {
+        conn.execute(
+            "DELETE FROM snapshot_contents WHERE snapshot = :sid",
+            named_params! {":sid": sid},
+        )?;
+        conn.execute("DELETE FROM snapshots WHERE id = :sid", named_params! {":sid": sid})?;
+        Ok(())
+    })
+}
+
+// A version of a deck: each card's tags, zone and quantity, its commanders and its notes. None is
+// the deck as it is now, Some the snapshot with that id. Back faces are left out.
+type DeckVersion = (HashMap<String, (Vec<String>, String, u32)>, String, String);
+
+fn rdvfd(conn: &Connection, did: i32, osid: Option<i32>) -> Result<DeckVersion> {
+    let (contents, owner, head) = match osid {
+        None => ("deck_contents", "deck", "decks"),
+        Some(_) => ("snapshot_contents", "snapshot", "snapshots"),
+    };
+    let id = osid.unwrap_or(did);
+    let mut stmt = conn.prepare(&format!(
+        "SELECT card_name, tags, zone, quantity
+        FROM {contents}
+        INNER JOIN cards
+        ON cards.name = {contents}.card_name
+        WHERE {owner} = :id
+        AND (side != 'b' OR layout == 'split' OR layout == 'modal_dfc');"
+    ))?;
+    let cards = stmt
+        .query_map(named_params! {":id": id}, |row| {
+            let tags = row.get::<usize, Option<String>>(1)?.map(stovs).unwrap_or_default();
+            Ok((row.get(0)?, (tags, row.get(2)?, row.get(3)?)))
+        })?
+        .collect::<Result<_>>()?;
+    let (commanders, notes) = conn.query_row(
+        &format!("SELECT commander, commander2, notes FROM {head} WHERE id = :id"),
+        named_params! {":id": id},
+        |row| {
+            let mut coms: Vec<String> = vec![row.get(0)?];
+            coms.extend(row.get::<usize, Option<String>>(1)?);
+            let coms = coms.join(" & ");
+            Ok((coms, row.get::<usize, Option<String>>(2)?.unwrap_or_default()))
+        },
+    )?;
+    Ok((cards, commanders, notes))
+}
+
+// Compares two versions of a deck, each either a snapshot id or None for the deck as it is now.
+pub fn rdiff(conn: &Connection, did: i32, from: Option<i32>, to: Option<i32>) -> Result<DeckDiff> {
+    let (old, old_coms, old_notes) = rdvfd(conn, did, from)?;
+    let (new, new_coms, new_notes) = rdvfd(conn, did, to)?;
+    let mut diff = DeckDiff::default();
+    for (cn, (tags, zone, quantity)) in &new {
+        match old.get(cn) {
+            None => diff.added.push(cn.clone()),
+            Some((old_tags, old_zone, old_quantity)) => {
+                let lost: Vec<String> = old_tags.iter().filter(|t| !tags.contains(t)).cloned().collect();
+                let gained: Vec<String> = tags.iter().filter(|t| !old_tags.contains(t)).cloned().collect();
+                if !lost.is_empty() || !gained.is_empty() {
+                    diff.retagged.push((cn.clone(), lost, gained));
+                }
+                if old_zone != zone {
+                    diff.moved.push((cn.clone(), old_zone.clone(), zone.clone()));
+                }
+                if old_quantity != quantity {
+                    diff.recounted.push((cn.clone(), *old_quantity, *quantity));
+                }
+            }
+        }
+    }
+    diff.removed = old.keys().filter(|cn| !new.contains_key(*cn)).cloned().collect();
+    diff.added.sort();
+    diff.removed.sort();
+    diff.retagged.sort();
+    diff.moved.sort();
+    diff.recounted.sort();
+    if old_coms != new_coms {
+        diff.commanders = Some((old_coms, new_coms));
+    }
+    diff.notes = old_notes != new_notes;
+    Ok(diff)
+}
+
+// Puts a deck back the way a snapshot saved it. The change can be undone.
+pub fn usfs(conn: &Connection, did: i32, sid: i32) -> Result<()> {
+    let mut vcn: Vec<String> = rvcnfcf(conn, &format!("INNER JOIN deck_contents ON cards.name = deck_contents.card_name WHERE deck = {did}"))?;
+    let mut stmt = conn.prepare("SELECT card_name FROM snapshot_contents WHERE snapshot = :sid")?;
+    let saved: Result<Vec<String>> = stmt.query_map(named_params! {":sid": sid}, |row| row.get(0))?.collect();
+    vcn.extend(saved?);
+    let before = rvcsfd(conn, &vcn, did)?;
+    transact(conn, || {
+        conn.execute("DELETE FROM deck_contents WHERE deck = :did", named_params! {":did": did})?;
+        conn.execute(
+            "INSERT INTO deck_contents
+            (card_name, deck, tags, quantity, zone, printing_set, printing_number, foil)
+            SELECT card_name, :did, tags, quantity, zone, printing_set, printing_number, foil
+            FROM snapshot_contents WHERE snapshot = :sid;",
+            named_params! {":did": did, ":sid": sid},
+        )?;
+        conn.execute(
+            "UPDATE decks
+            SET (commander, commander2, companion, notes) =
+            (SELECT commander, commander2, companion, notes FROM snapshots WHERE id = :sid)
+            WHERE id = :did;",
+            named_params! {":did": did, ":sid": sid},
+        )?;
+        Ok(())
+    })?;
+    ihfd(conn, before, did)?;
+    Ok(())
+}
+
+// Runs f inside one transaction, rolling everything back if it fails.
+fn transact<T>(conn: &Connection, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    conn.execute_batch("BEGIN TRANSACTION;")?;
+    match f() {
+        Ok(t) => {
+            conn.execute_batch("COMMIT TRANSACTION;")?;
+            Ok(t)
+        }
+        Err(e) => {
+            conn.execute_batch("ROLLBACK TRANSACTION;")?;
+            Err(e)
+        }
+    }
+}
+
+// Adds each named card that isn't in the deck yet, returning every card added.
+pub fn ivctodc(conn: &Connection, vcn: &[String], did: i32) -> Result<Vec<Card>> {
+    let mut cf = CardFilter::new();
+    cf.did = did;
+    let mut vcdec = rvcnfcf(conn, &cf.make_query(false, ""))?;
+    transact(conn, || {
+        let mut vr = Vec::new();
+        for cn in vcn {
+            if vcdec.contains(cn) {
+                continue;
+            }
+            for c in ictodc(conn, &rcfn(conn, cn, None)?, did)? {
+                vcdec.push(c.name.clone());
+                vr.push(c);
+            }
+        }
+        Ok(vr)
+    })
+}
+
+// Removes each named card and its other faces from a deck, leaving the commanders.
+// Returns how many of the named cards were removed.
+pub fn dvcntodc(conn: &Connection, vcn: &[String], did: i32) -> Result<usize> {
+    let deck = rdfdid(conn, did)?;
+    transact(conn, || {
+        let mut n = 0;
+        for cn in vcn {
+            let c = rcfn(conn, cn, None)?;
+            if deck.commander == c || deck.commander2.as_ref() == Some(&c) {
+                continue;
+            }
+            dcntodc(conn, cn, did)?;
+            match &c.lo {
+                CardLayout::Paired(_, _, other) => dcntodc(conn, other, did)?,
+                CardLayout::Meld(s, other, meld) => {
+                    dcntodc(conn, meld, did)?;
+                    if s == &'b' {
+                        dcntodc(conn, other, did)?;
+                    }
+                }
+                _ => {}
+            }
+            n += 1;
+        }
+        Ok(n)
+    })
+}
+
+// Puts a tag on, or takes it off, each named card in a deck. Returns how many cards changed.
+pub fn utivcndc(conn: &Connection, vcn: &[String], tag: &str, add: bool, did: i32) -> Result<usize> {
+    let tag = tag.to_string();
+    transact(conn, || {
+        let mut n = 0;
+        for cn in vcn {
+            if rcfn(conn, cn, Some(did))?.tags.contains(&tag) != add {
+                ttindc(conn, cn, &tag, did);
+                n += 1;
+            }
+        }
+        Ok(n)
+    })
+}
+
+pub fn ttindc(conn: &Connection, c: &str, t: &String, did: i32) -> Option<Card> {
+    let mut stmt = conn
+        .prepare(
+            "UPDATE deck_contents 
+        SET tags = :tags
+        WHERE card_name = :name
+        AND deck = :did;",
+        )
+        .unwrap();
+
+    let mut card = rcfn(conn, c, Some(did)).unwrap();
+    let cc = if t.eq(&String::from("main")) || t.eq(&String::from("main")) {
+        match &card.lo {
+            CardLayout::Paired(_, _, n) => Some(rcfn(conn, n, Some(did)).unwrap()),
+            _ => None,
+        }
+    } else {
+        None
+    };
+
+    let tags = if card.tags.contains(t) {
+        card.tags
+            .remove(card.tags.iter().position(|x| x == t).unwrap());
+        if card.tags.is_empty() {
+            None
+        } else {
+            Some(card.tags.join("|"))
+        }
+    } else {
+        card.tags.push(t.clone());
+        card.tags.sort();
+        Some(card.tags.join("|"))
+    };
+
+    stmt.execute(named_params! {":tags": tags, ":name": c, ":did": did})
+        .unwrap();
+    if let Some(mut cc) = cc {
+        if !card.tags.contains(t) && cc.tags.contains(t) {
+            cc.tags.remove(cc.tags.iter().position(|x| x == t).unwrap());
+        } else if card.tags.contains(t) && !cc.tags.contains(t) {
+            cc.tags.push(t.clone());
+        }
+        let t = if cc.tags.is_empty() {
+            None
+        } else {
+            Some(cc.tags.join("|"))
+        };
+        stmt.execute(named_params! {":tags": t, ":name": cc.name, ":did": did})
+            .unwrap();
+    }
+    Some(card)
+}
+
+pub fn uprindc(conn: &Connection, c: &Card, did: i32, printing: Option<&CardPrinting>) -> Result<Card> {
+    let mut stmt = conn.prepare(
+        "UPDATE deck_contents
+        SET printing_set = :set_code, printing_number = :number, foil = :foil,
+        printing_price = NULL, printing_price_date = NULL
+        WHERE card_name = :name
+        AND deck = :did;",
+    )?;
+    let (set_code, number, foil) = match printing {
+        Some(p) => (Some(&p.set_code), Some(&p.number), p.foil),
+        None => (None, None, false),
+    };
+
+    stmt.execute(named_params! {":set_code": set_code, ":number": number, ":foil": foil, ":name": c.name, ":did": did})?;
+    if let CardLayout::Paired(_, _, n) = &c.lo {
+        stmt.execute(named_params! {":set_code": set_code, ":number": number, ":foil": foil, ":name": n, ":did": did})?;
+    }
+
+    rcfn(conn, &c.name, Some(did))
+}
+
+pub fn uqindc(conn: &Connection, c: &Card, did: i32, quantity: u32) -> Result<Card> {
+    let mut stmt = conn.prepare(
+        "UPDATE deck_contents
+        SET quantity = :quantity
+        WHERE card_name = :name
+        AND deck = :did;",
+    )?;
+    stmt.execute(named_params! {":quantity": quantity, ":name": c.name, ":did": did})?;
+    if let CardLayout::Paired(_, _, n) = &c.lo {
+        stmt.execute(named_params! {":quantity": quantity, ":name": n, ":did": did})?;
+    }
+
+    rcfn(conn, &c.name, Some(did))
+}
+
+pub fn uzindc(conn: &Connection, c: &Card, did: i32, zone: Zone) -> Result<Card> {
+    let mut stmt = conn.prepare(
+        "UPDATE deck_contents
+        SET zone = :zone
+        WHERE card_name = :name
+        AND deck = :did;",
+    )?;
+    stmt.execute(named_params! {":zone": zone.as_str(), ":name": c.name, ":did": did})?;
+    if let CardLayout::Paired(_, _, n) = &c.lo {
+        stmt.execute(named_params! {":zone": zone.as_str(), ":name": n, ":did": did})?;
+    }
+
+    rcfn(conn, &c.name, Some(did))
+}
+
+pub fn utindc(conn: &Connection, change: TagChange, cf: &CardFilter) {
+    let mut stmt = conn
+        .prepare(
+            "UPDATE deck_contents 
+        SET tags = :tags
+        WHERE card_name = :name
+        AND deck = :did;",
+        )
+        .unwrap();
+    let (old, new) = match change {
+        TagChange::Delete(tag) => (tag, None),
+        TagChange::Change(old, new) => (old, Some(new)),
+        TagChange::Insert(_) => return,
+    };
+    // The filter also matches cards with only a child of the tag, which move along with it.
+    let query = cf.make_query(false, format!("tag:{old}").as_str());
+    transact(conn, || {
+        for c in rvcfcf(conn, &query)? {
+            let vt = vtretag(&c.tags, &old, new.as_deref());
+            let tags = if vt.is_empty() { None } else { Some(vt.join("|")) };
+            stmt.execute(named_params! {":tags": tags, ":name": c.name, ":did": cf.did})?;
+        }
+        Ok(())
+    })
+    .expect("Issue with update");
+}
+
+// Moves a tag and its children under a new name, or drops them all when there is none.
+fn vtretag<S: AsRef<str>>(vt: &[S], old: &str, new: Option<&str>) -> Vec<String> {
+    let mut vt: Vec<String> = vt
+        .iter()
+        .map(|t| t.as_ref())
+        .filter_map(|t| match tag_under(t, old) {
+            Some(rest) => new.map(|n| format!("{}{}", n, rest)),
+            None => Some(t.to_string()),
+        })
+        .collect();
+    vt.sort();
+    vt.dedup();
+    vt
+}
+
+// Each tag used in any deck, with how many cards and decks use it.
+pub fn rvtu(conn: &Connection) -> Result<Vec<(String, u32, u32)>> {
+    let mut stmt = conn.prepare("SELECT deck, tags FROM deck_contents WHERE tags IS NOT NULL;")?;
+    let rows = stmt
+        .query_map([], |row| Ok((row.get::<usize, i32>(0)?, row.get::<usize, String>(1)?)))?
+        .collect::<Result<Vec<(i32, String)>>>()?;
+    let mut usage: HashMap<String, (u32, HashSet<i32>)> = HashMap::new();
+    for (did, tags) in rows {
+        for tag in tags.split('|').filter(|t| !t.is_empty()) {
+            let e = usage.entry(tag.to_string()).or_default();
+            e.0 += 1;
+            e.1.insert(did);
+        }
+    }
+    Ok(usage
+        .into_iter()
+        .map(|(tag, (cards, decks))| (tag, cards, decks.len() as u32))
+        .collect())
+}
+
+// Counts the cards and decks that have a tag or one of its children.
+pub fn rqtiadc(conn: &Connection, tag: &str) -> Result<(u32, u32)> {
+    conn.query_row(
+        "SELECT COUNT(*), COUNT(DISTINCT deck) FROM deck_contents
+        WHERE tags IS NOT NULL AND tags REGEXP :re;",
+        named_params! {":re": format!(r"(?:\||^){}(?:/[^|]*)?(?:$|\|)", regex::escape(tag))},
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+}
+
+// Renames a tag and its children in every deck, merging into the new name if it's already
+// used, or deletes them when there is no new name. Each deck changed gets its own history
+// step. Returns the cards and decks changed.
+pub fn utiadc(conn: &Connection, old: &str, new: Option<&str>) -> Result<(u32, u32)> {
+    let rows = {
+        let mut stmt = conn.prepare(
+            "SELECT deck, card_name, tags FROM deck_contents WHERE tags IS NOT NULL;",
+        )?;
+        let a = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<Vec<(i32, String, String)>>>()?;
+        a
+    };
+    let rows: Vec<(i32, String, String)> = rows
+        .into_iter()
+        .filter(|(_, _, tags)| tags.split('|').any(|t| tag_under(t, old).is_some()))
+        .collect();
+
+    let mut decks: HashMap<i32, Vec<String>> = HashMap::new();
+    for (did, name, _) in &rows {
+        decks.entry(*did).or_default().push(name.clone());
+    }
+    let mut before = Vec::new();
+    for (did, vcn) in &decks {
+        before.push((*did, rvcsfd(conn, vcn, *did)?));
+    }
+
+    transact(conn, || {
+        let mut stmt = conn.prepare(
+            "UPDATE deck_contents SET tags = :tags WHERE deck = :did AND card_name = :name;",
+        )?;
+        for (did, name, tags) in &rows {
+            let vt: Vec<&str> = tags.split('|').collect();
+            let vt = vtretag(&vt, old, new);
+            let tags = if vt.is_empty() { None } else { Some(vt.join("|")) };
+            stmt.execute(named_params! {":tags": tags, ":did": did, ":name": name})?;
+        }
+        Ok(())
+    })?;
+
+    for (did, states) in before {
+        ihfd(conn, states, did)?;
+    }
+    Ok((rows.len() as u32, decks.len() as u32))
+}
+
+pub fn cindid(conn: &Connection, c: &str, did: i32) -> bool {
+    let a = conn.query_row(
+        "SELECT card_name FROM deck_contents WHERE card_name = ? AND deck = ?;",
+        params![c, did],
+        |_| Ok(0),
+    );
+
+    a.is_ok()
 }
 
 pub fn ideck(conn: &Connection, n: &str, c: &str, c2: Option<String>, t: &str) -> Result<i32> {
@@ -999,11 +2067,11 @@ pub fn ideck(conn: &Connection, n: &str, c: &str, c2: Option<String>, t: &str) -
             let rid = conn.last_insert_rowid();
             let com = rcfn(conn, c, None).unwrap();
             ictodc(conn, &com, rid.try_into().unwrap()).unwrap();
-            ttindc(conn, c, &String::from("main"), rid.try_into().unwrap());
+            uzindc(conn, &com, rid.try_into().unwrap(), Zone::Commander).unwrap();
 
             let com = rcfn(conn, &c2, None).unwrap();
             ictodc(conn, &com, rid.try_into().unwrap()).unwrap();
-            ttindc(conn, &c2, &String::from("main"), rid.try_into().unwrap());
+            uzindc(conn, &com, rid.try_into().unwrap(), Zone::Commander).unwrap();
 
             Ok(rid.try_into().unwrap())
         }
@@ -1015,13 +2083,247 @@ pub fn ideck(conn: &Connection, n: &str, c: &str, c2: Option<String>, t: &str) -
             let rid = conn.last_insert_rowid();
             let com = rcfn(conn, c, None).unwrap();
             ictodc(conn, &com, rid.try_into().unwrap()).unwrap();
-            ttindc(conn, c, &String::from("main"), rid.try_into().unwrap());
+            uzindc(conn, &com, rid.try_into().unwrap(), Zone::Commander).unwrap();
 
             Ok(rid.try_into().unwrap())
         }
     }
 }
 
+// Copies a deck with all of its cards and tags under a new name, optionally giving the copy new
+// commanders. Returns the new deck and the cards outside the new commanders' color identity.
+pub fn idfd(conn: &Connection, did: i32, name: &str, coms: Option<(&str, Option<&str>)>) -> Result<(i32, Vec<String>)> {
+    rdfdid(conn, did)?;
+    let new = transact(conn, || {
+        conn.execute(
+            "INSERT INTO decks (name, commander, commander2, deck_type, notes, companion, labels)
+            SELECT :name, commander, commander2, deck_type, notes, companion, labels
+            FROM decks WHERE id = :did;",
+            named_params! {":did": did, ":name": name},
+        )?;
+        let new = conn.last_insert_rowid() as i32;
+        conn.execute(
+            "INSERT INTO deck_contents
+            (card_name, deck, tags, quantity, zone, printing_set, printing_number, foil)
+            SELECT card_name, :new, tags, quantity, zone, printing_set, printing_number, foil
+            FROM deck_contents WHERE deck = :did;",
+            named_params! {":did": did, ":new": new},
+        )?;
+        if let Some((c1, c2)) = coms {
+            ucomdc(conn, new, c1, c2, false)?;
+        }
+        Ok(new)
+    })?;
+
+    let outside = match coms {
+        Some(_) => rvcnoci(conn, new)?,
+        None => Vec::new(),
+    };
+    Ok((new, outside))
+}
+
+// Whether c1 (and c2, as its second commander or signature spell) can lead a deck of this type.
+pub fn vcom(conn: &Connection, dt: DeckType, c1: &Card, c2: Option<&Card>) -> bool {
+    let query = format!(
+        "SELECT name FROM cards WHERE name = :name AND {} AND {}",
+        commander_clause(dt),
+        legality_clause(dt)
+    );
+    if conn
+        .query_row(&query, named_params! {":name": c1.name}, |_| Ok(()))
+        .is_err()
+    {
+        return false;
+    }
+    match c2 {
+        Some(c2) if dt == DeckType::Oathbreaker => {
+            c2.types.contains("Instant") || c2.types.contains("Sorcery")
+        }
+        Some(c2) => c1.can_pair(c2),
+        None => true,
+    }
+}
+
+// Replaces the commanders of a deck. The old commanders stay in the deck unless keep is false.
+// Returns the cards in the deck that fall outside the new color identity.
+pub fn ucom(conn: &Connection, did: i32, c1: &str, c2: Option<&str>, keep: bool) -> Result<Vec<String>> {
+    transact(conn, || ucomdc(conn, did, c1, c2, keep))?;
+    rvcnoci(conn, did)
+}
+
+// The work of ucom, for callers that already hold a transaction.
+fn ucomdc(conn: &Connection, did: i32, c1: &str, c2: Option<&str>, keep: bool) -> Result<()> {
+    let deck = rdfdid(conn, did)?;
+    // A name that isn't a card can't lead the deck either.
+    let rcom = |cn| match rcfn(conn, cn, None) {
+        Err(Error::QueryReturnedNoRows) => Err(Error::InvalidQuery),
+        r => r,
+    };
+    let com = rcom(c1)?;
+    let com2 = match c2 {
+        Some(c2) => Some(rcom(c2)?),
+        None => None,
+    };
+    if !vcom(conn, deck.deck_type, &com, com2.as_ref()) {
+        return Err(Error::InvalidQuery);
+    }
+
+    conn.execute(
+        "UPDATE decks SET commander = :commander, commander2 = :commander2 WHERE id = :did",
+        named_params! {":commander": com.name, ":commander2": com2.as_ref().map(|c| &c.name), ":did": did},
+    )?;
+
+    let vcdec = rvcnfcf(conn, &format!("INNER JOIN deck_contents ON cards.name = deck_contents.card_name WHERE deck_contents.deck = {}", did))?;
+    for c in std::iter::once(&com).chain(com2.iter()) {
+        if !vcdec.contains(&c.name) {
+            ictodc(conn, c, did)?;
+        }
+        uzindc(conn, c, did, Zone::Commander)?;
+    }
+
+    let old = std::iter::once(deck.commander).chain(deck.commander2);
+    for c in old.filter(|c| c.name != com.name && Some(&c.name) != com2.as_ref().map(|c| &c.name)) {
+        if keep {
+            uzindc(conn, &c, did, Zone::Main)?;
+        } else {
+            dcntodc(conn, &c.name, did)?;
+            if let CardLayout::Paired(_, _, n) = &c.lo {
+                dcntodc(conn, n, did)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// The cards in a deck outside its color identity, for formats that have one.
+fn rvcnoci(conn: &Connection, did: i32) -> Result<Vec<String>> {
+    let deck = rdfdid(conn, did)?;
+    if !deck.deck_type.rules().identity {
+        return Ok(Vec::new());
+    }
+    let outside = rvcfdid(conn, did, SortOrder::NameAsc)?
+        .into_iter()
+        .filter(|c| {
+            c.color_identity
+                .iter()
+                .any(|ch| *ch != '\u{0}' && !deck.color.contains(*ch))
+        })
+        .map(|c| c.name)
+        .collect();
+    Ok(outside)
+}
+
+// Checks the commander and main zone cards of a deck against the rules of its format.
+pub fn vdeck(conn: &Connection, did: i32) -> Result<Vec<Violation>> {
+    let deck = rdfdid(conn, did)?;
+    let rules = deck.deck_type.rules();
+    let mut vv = Vec::new();
+    let violation = |card: &str, problem: String| Violation {
+        card: Some(card.to_string()),
+        problem,
+    };
+
+    let cards: Vec<Card> = rvcfdid(conn, did, SortOrder::NameAsc)?
+        .into_iter()
+        .filter(|c| c.zone.is_some_and(|z| z.in_deck()))
+        .filter(|c| deck.companion.as_ref().is_none_or(|cc| cc.name != c.name))
+        .collect();
+    // Back faces share a card with their front face.
+    let fronts: Vec<&Card> = cards
+        .iter()
+        .filter(|c| !matches!(c.lo, CardLayout::Paired('b', _, _) | CardLayout::Meld('b', _, _)))
+        .collect();
+
+    let total: u32 = fronts.iter().map(|c| c.quantity).sum();
+    if total < rules.size || (rules.singleton && total > rules.size) {
+        vv.push(Violation {
+            card: None,
+            problem: format!(
+                "The deck has {} cards; {} decks need {}{}.",
+                total,
+                deck.deck_type,
+                if rules.singleton { "exactly " } else { "at least " },
+                rules.size
+            ),
+        });
+    }
+
+    if !vcom(conn, deck.deck_type, &deck.commander, None) {
+        vv.push(violation(
+            &deck.commander.name,
+            format!("can't lead a {} deck.", deck.deck_type),
+        ));
+    } else if let Some(c2) = &deck.commander2 {
+        if !vcom(conn, deck.deck_type, &deck.commander, Some(c2)) {
+            vv.push(violation(
+                &c2.name,
+                format!("can't be paired with {}.", deck.commander.name),
+            ));
+        }
+    }
+
+    for c in &fronts {
+        if let Some(max) = deck.deck_type.max_copies(c) {
+            if c.quantity > max {
+                vv.push(violation(
+                    &c.name,
+                    format!("{} copies, but only {} are allowed.", c.quantity, max),
+                ));
+            }
+        }
+    }
+
+    // Stored color identities already include hybrid symbols and color indicators.
+    if rules.identity {
+        for c in &cards {
+            let outside: String = c
+                .color_identity
+                .iter()
+                .filter(|ch| **ch != '\u{0}' && !deck.color.contains(**ch))
+                .collect();
+            if !outside.is_empty() {
+                vv.push(violation(
+                    &c.name,
+                    format!("{} is outside the commanders' color identity.", outside),
+                ));
+            }
+        }
+    }
+
+    let query = format!(
+        "SELECT name FROM cards
+        INNER JOIN deck_contents
+        ON cards.name = deck_contents.card_name
+        WHERE deck_contents.deck = :did
+        AND {}
+        AND NOT {}
+        ORDER BY name ASC",
+        DECK_ZONES,
+        legality_clause(deck.deck_type)
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let banned: Vec<String> = stmt
+        .query_map(named_params! {":did": did}, |row| row.get(0))?
+        .collect::<Result<_>>()?;
+    for cn in banned {
+        vv.push(violation(&cn, format!("is banned or not legal in {}.", deck.deck_type)));
+    }
+
+    if let Some(cc) = &deck.companion {
+        for issue in cc.companion_issues(&cards, &rules) {
+            // Deck-wide problems are sentences rather than card names.
+            let (card, problem) = if issue.ends_with('.') {
+                (None, format!("{} ({}'s companion condition)", issue, cc.name))
+            } else {
+                (Some(issue), format!("breaks the companion condition of {}.", cc.name))
+            };
+            vv.push(Violation { card, problem });
+        }
+    }
+
+    Ok(vv)
+}
+
 pub fn import_deck(
     conn: &Connection,
     deck_name: String,
@@ -1037,10 +2339,15 @@ pub fn import_deck(
                     println!("Valid commander found: {}", &c.name);
                     (&cards.first().unwrap().name, None)
                 }
-                CommanderType::Partner => {
+                CommanderType::Partner
+                | CommanderType::FriendsForever
+                | CommanderType::ChooseBackground
+                | CommanderType::DoctorsCompanion
+                | CommanderType::Doctor => {
                     if let Some(cn) = cards.get(1) {
-                        let sc = rcfn(conn, &cn.name, None).unwrap();
-                        if sc.is_commander() == CommanderType::Partner {
+                        let sc = rcfn(conn, &cn.name, None);
+                        if sc.as_ref().is_ok_and(|sc| c.can_pair(sc)) {
+                            let sc = sc.unwrap();
                             println!("Valid commanders found: {} and {}", c.name, sc.name);
                             // This is gross, but we need the owned value
                             (
@@ -1049,7 +2356,7 @@ pub fn import_deck(
                             )
                         } else {
                             println!("Valid commander found: {}", c.name);
-                            println!("This commander has the Partner keyword. To include the partner as a secondary commander, it must be the second card in the file.");
+                            println!("This commander can have a second commander (such as a partner or a Background). To include it, it must be the second card in the file.");
                             (&cards.first().unwrap().name, None)
                         }
                     } else {
@@ -1062,6 +2369,7 @@ pub fn import_deck(
                     let partner = ImportCard {
                         name: ss.clone(),
                         tags: None,
+                        ..Default::default()
                     };
                     if cards.contains(&partner) {
                         println!("Valid commanders found: {} and {}", c.name, &ss);
@@ -1090,44 +2398,81 @@ pub fn import_deck(
 
     if let Ok(deck_id) = ideck(conn, &deck_name, primary, secondary, "Commander") {
         println!("Deck created successfully! Now adding cards...");
-        conn.execute_batch("BEGIN TRANSACTION;")?;
-        let deck = rdfdid(conn, deck_id).unwrap();
-        for ic in cards {
-            let c = ic.name.trim().to_string();
-            if c.is_empty() {
-                continue;
-            }
-            let card = if let Some(i) = c.find(" // ") {
-                let c = c.get(0..i).unwrap();
-                rcfn(conn, c, None).unwrap()
-            } else {
-                match rcfn(conn, &c, None) {
-                    Ok(a) => a,
-                    Err(_) => {
-                        println!("Error on card {}", c);
-                        return Err(rusqlite::Error::InvalidQuery);
-                    }
-                }
-            };
-            let mut disq = "";
-            for c in &card.color_identity {
-                if *c != '\u{0}' && !deck.color.contains(*c) {
-                    disq = "Invalid color identity";
+        transact(conn, || {
+            let deck = rdfdid(conn, deck_id).unwrap();
+            let mut added: HashMap<String, u32> = HashMap::new();
+            for ic in cards {
+                let (quantity, c) = match ic.quantity {
+                    Some(q) => (q, ic.name.trim().to_string()),
+                    None => stoq(conn, ic.name.trim()),
+                };
+                if c.is_empty() {
+                    continue;
                 }
-            }
-            if disq.is_empty() {
-                ictodc(conn, &card, deck_id)?;
-                if let Some(tags) = ic.tags {
-                    for tag in tags.split('|') {
-                        ttindc(conn, &card.name, &tag.to_string(), deck_id);
+                let card = if let Some(i) = c.find(" // ") {
+                    let c = c.get(0..i).unwrap();
+                    rcfn(conn, c, None).unwrap()
+                } else {
+                    match rcfn(conn, &c, None) {
+                        Ok(a) => a,
+                        Err(_) => {
+                            println!("Error on card {}", c);
+                            return Err(rusqlite::Error::InvalidQuery);
+                        }
                     }
                 };
-                num += 1;
-            } else {
-                println!("Card not added: \"{}\" due to: {}", &card.name, disq);
+                let mut disq = "";
+                for c in &card.color_identity {
+                    if deck.deck_type.rules().identity && *c != '\u{0}' && !deck.color.contains(*c) {
+                        disq = "Invalid color identity";
+                    }
+                }
+                if disq.is_empty() {
+                    let mut quantity = quantity + added.get(&card.name).copied().unwrap_or_default();
+                    if let Some(max) = deck.deck_type.max_copies(&card) {
+                        if quantity > max {
+                            println!("A deck can only have {} of \"{}\"; adding {}.", max, &card.name, max);
+                            quantity = max;
+                        }
+                    }
+                    ictodc(conn, &card, deck_id)?;
+                    uqindc(conn, &card, deck_id, quantity)?;
+                    added.insert(card.name.clone(), quantity);
+                    // Older exports marked zones with the main and side tags.
+                    let mut zone = ic.zone.as_deref().map(Zone::from);
+                    if let Some(tags) = ic.tags {
+                        for tag in tags.split('|') {
+                            match tag {
+                                "main" => zone = zone.or(Some(Zone::Main)),
+                                "side" => zone = zone.or(Some(Zone::Sideboard)),
+                                "" => {}
+                                _ => {
+                                    ttindc(conn, &card.name, &tag.to_string(), deck_id);
+                                }
+                            }
+                        }
+                    };
+                    if deck.commander.name == card.name
+                        || deck.commander2.as_ref().is_some_and(|c| c.name == card.name)
+                    {
+                        zone = Some(Zone::Commander);
+                    }
+                    uzindc(conn, &card, deck_id, zone.unwrap_or(Zone::Main))?;
+                    if let (Some(set_code), Some(number)) = (ic.set_code, ic.number) {
+                        let printing = CardPrinting {
+                            set_code,
+                            number,
+                            foil: ic.foil.unwrap_or_default(),
+                        };
+                        uprindc(conn, &card, deck_id, Some(&printing))?;
+                    }
+                    num += 1;
+                } else {
+                    println!("Card not added: \"{}\" due to: {}", &card.name, disq);
+                }
             }
-        }
-        conn.execute_batch("COMMIT TRANSACTION;")?;
+            Ok(())
+        })?;
         println!("Added {} cards to deck {}", num, deck_name);
         return Ok(deck_id);
     };
@@ -1137,7 +2482,10 @@ pub fn import_deck(
 
 pub fn rcfn(conn: &Connection, name: &str, odid: Option<i32>) -> Result<Card> {
     let mut stmt = conn.prepare("SELECT 
-        cmc, color_identity, legalities, loyalty, mana_cost, name, power, card_text, toughness, types, layout, related_cards, side, tags, rarity, price, date_price_retrieved
+        cmc, color_identity, legalities, loyalty, mana_cost, name, power, card_text, toughness, types, layout, related_cards, side, tags, rarity,
+        CASE WHEN printing_set IS NULL THEN price ELSE printing_price END,
+        CASE WHEN printing_set IS NULL THEN date_price_retrieved ELSE printing_price_date END,
+        printing_set, printing_number, foil, quantity, zone
         FROM cards 
         LEFT OUTER JOIN deck_contents
         ON cards.name = deck_contents.card_name
@@ -1171,7 +2519,75 @@ pub fn rcomfdid(conn: &Connection, did: i32, secondary: bool) -> Result<Card> {
         )?
     };
 
-    rcfn(conn, &name, Some(did))
+    rcfn(conn, &name, Some(did))
+}
+
+pub fn rvrfcn(conn: &Connection, name: &str) -> Result<Vec<Ruling>> {
+    let mut stmt = conn.prepare(
+        "SELECT date, text FROM rulings WHERE card_name = :name ORDER BY date ASC;",
+    )?;
+    let a = stmt.query_map(named_params! {":name": name}, |row| {
+        Ok(Ruling {
+            date: row.get(0)?,
+            text: row.get(1)?,
+        })
+    })?;
+    a.collect()
+}
+
+pub fn rvpfcn(conn: &Connection, name: &str) -> Result<Vec<Printing>> {
+    let mut stmt = conn.prepare(
+        "SELECT set_code, number, rarity, date FROM printings WHERE card_name = :name ORDER BY date ASC, set_code ASC;",
+    )?;
+    let a = stmt.query_map(named_params! {":name": name}, |row| {
+        Ok(Printing {
+            set_code: row.get(0)?,
+            number: row.get(1)?,
+            rarity: row.get(2)?,
+            date: row.get(3)?,
+        })
+    })?;
+    a.collect()
+}
+
+// Every deck outside the trash that contains the card, by deck name.
+pub fn rvufcn(conn: &Connection, name: &str) -> Result<Vec<CardUse>> {
+    let mut stmt = conn.prepare(
+        "SELECT decks.id, decks.name, archived, zone, quantity, tags
+        FROM deck_contents
+        INNER JOIN decks
+        ON decks.id = deck_contents.deck
+        WHERE card_name = :name
+        AND deleted IS NULL
+        ORDER BY decks.name ASC;",
+    )?;
+    let a = stmt.query_map(named_params! {":name": name}, |row| {
+        Ok(CardUse {
+            did: row.get(0)?,
+            deck: row.get(1)?,
+            archived: row.get(2)?,
+            zone: Zone::from(&row.get::<usize, String>(3)?),
+            quantity: row.get(4)?,
+            tags: row.get::<usize, Option<String>>(5)?.map(stovs).unwrap_or_default(),
+        })
+    })?;
+    a.collect()
+}
+
+pub fn rvqfdid(conn: &Connection, did: i32) -> Result<HashMap<String, u32>> {
+    let mut stmt = conn.prepare(
+        "SELECT card_name, quantity FROM deck_contents WHERE deck = :did AND quantity > 1;",
+    )?;
+    let a = stmt.query_map(named_params! {":did": did}, |row| Ok((row.get(0)?, row.get(1)?)))?;
+    a.collect()
+}
+
+pub fn rvtfdid(conn: &Connection, did: i32) -> Result<HashMap<String, String>> {
+    let mut stmt = conn.prepare(
+        "SELECT card_name, tags FROM deck_contents WHERE deck = :did AND tags IS NOT NULL;",
+    )?;
+    let a = stmt.query_map(named_params! {":did": did}, |row| Ok((row.get(0)?, row.get(1)?)))?;
+    a.collect()
 }
 
 pub fn rvd(conn: &Connection) -> Result<Vec<Deck>> {
@@ -1201,17 +2617,71 @@ pub fn rvd(conn: &Connection) -> Result<Vec<Deck>> {
             color = String::from("C");
         }
 
+        let companion = match row.get::<&str, Option<String>>("companion")? {
+            Some(cn) => Some(rcfn(conn, &cn, None)?),
+            None => None,
+        };
+
         Ok(Deck {
             id: row.get(0)?,
             name: row.get(1)?,
             commander: com,
             commander2: com2,
             color,
+            notes: row.get::<usize, Option<String>>(5)?.unwrap_or_default(),
+            deck_type: DeckType::from(&row.get::<usize, String>(4)?),
+            companion,
+            labels: row.get::<&str, Option<String>>("labels")?.map(stovs).unwrap_or_default(),
+            modified: row.get::<&str, Option<String>>("modified")?.unwrap_or_default(),
+            archived: row.get("archived")?,
+            deleted: row.get("deleted")?,
         })
     })?;
     a.collect()
 }
 
+// The number of cards in the commander and main zones of each deck, and their total price as far
+// as prices have been fetched.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DeckTotals {
+    pub cards: u32,
+    pub price: f64,
+}
+
+pub fn rvdt(conn: &Connection) -> Result<HashMap<i32, DeckTotals>> {
+    let mut stmt = conn.prepare(
+        "SELECT deck, SUM(quantity),
+        SUM(quantity * COALESCE(CASE WHEN printing_set IS NULL THEN price ELSE printing_price END, 0))
+        FROM deck_contents
+        INNER JOIN cards
+        ON cards.name = deck_contents.card_name
+        WHERE side != 'b'
+        AND zone IN ('commander', 'main')
+        GROUP BY deck;",
+    )?;
+    let a = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                DeckTotals {
+                    cards: row.get(1)?,
+                    price: row.get(2)?,
+                },
+            ))
+        })?
+        .collect();
+    a
+}
+
+pub fn udl(conn: &Connection, did: i32, labels: &[String]) -> Result<()> {
+    let labels = if labels.is_empty() { None } else { Some(labels.join("|")) };
+    conn.execute(
+        "UPDATE decks SET labels = :labels WHERE id = :did",
+        named_params! {":labels": labels, ":did": did},
+    )?;
+    Ok(())
+}
+
 pub fn rdfdid(conn: &Connection, id: i32) -> Result<Deck> {
     let mut stmt = conn.prepare("SELECT * FROM decks WHERE id = ?;")?;
 
@@ -1235,12 +2705,24 @@ pub fn rdfdid(conn: &Connection, id: i32) -> Result<Deck> {
             }
         }
 
+        let companion = match row.get::<&str, Option<String>>("companion")? {
+            Some(cn) => Some(rcfn(conn, &cn, None)?),
+            None => None,
+        };
+
         Ok(Deck {
             id: row.get(0)?,
             name: row.get(1)?,
             commander: com,
             commander2: com2,
             color,
+            notes: row.get::<usize, Option<String>>(5)?.unwrap_or_default(),
+            deck_type: DeckType::from(&row.get::<usize, String>(4)?),
+            companion,
+            labels: row.get::<&str, Option<String>>("labels")?.map(stovs).unwrap_or_default(),
+            modified: row.get::<&str, Option<String>>("modified")?.unwrap_or_default(),
+            archived: row.get("archived")?,
+            deleted: row.get("deleted")?,
         })
     })
 }
@@ -1257,7 +2739,10 @@ pub fn rvcfdid(conn: &Connection, did: i32, sort_order: SortOrder) -> Result<Vec
 
     // For some reason, sqlite doesn't like named parameters in the ORDER BY clause.
     let s = format!("SELECT 
-        cmc, color_identity, legalities, loyalty, mana_cost, name, power, card_text, toughness, types, layout, related_cards, side, tags, rarity, price, date_price_retrieved
+        cmc, color_identity, legalities, loyalty, mana_cost, name, power, card_text, toughness, types, layout, related_cards, side, tags, rarity,
+        CASE WHEN printing_set IS NULL THEN price ELSE printing_price END,
+        CASE WHEN printing_set IS NULL THEN date_price_retrieved ELSE printing_price_date END,
+        printing_set, printing_number, foil, quantity, zone
         FROM cards 
         INNER JOIN deck_contents
         ON cards.name = deck_contents.card_name
@@ -1271,7 +2756,10 @@ pub fn rvcfdid(conn: &Connection, did: i32, sort_order: SortOrder) -> Result<Vec
 }
 
 pub fn rvcfcf(conn: &Connection, query: &str) -> Result<Vec<Card>> {
-    let fields = "cmc, color_identity, legalities, loyalty, mana_cost, name, power, card_text, toughness, types, layout, related_cards, side, tags, rarity, price, date_price_retrieved";
+    let fields = "cmc, color_identity, legalities, loyalty, mana_cost, name, power, card_text, toughness, types, layout, related_cards, side, tags, rarity,
+    CASE WHEN printing_set IS NULL THEN price ELSE printing_price END,
+    CASE WHEN printing_set IS NULL THEN date_price_retrieved ELSE printing_price_date END,
+    printing_set, printing_number, foil, quantity, zone";
     let qs = format!(
         "SELECT {}
 FROM `cards`
@@ -1301,7 +2789,74 @@ FROM cards
     cards
 }
 
-pub fn rvcnfn(conn: &Connection, n: &str) -> Result<Vec<String>> {
+// The legalities column holds the formats a card is legal in, separated by pipes.
+fn legality_clause(dt: DeckType) -> String {
+    format!("('|' || legalities || '|') LIKE '%|{}|%'", dt.rules().legality)
+}
+
+fn commander_clause(dt: DeckType) -> &'static str {
+    match dt {
+        DeckType::Commander => {
+            "types LIKE 'Legendary%' AND (types LIKE '%Creature%' OR card_text LIKE '%can be your commander%')"
+        }
+        DeckType::Brawl | DeckType::HistoricBrawl => {
+            "types LIKE 'Legendary%' AND (types LIKE '%Creature%' OR types LIKE '%Planeswalker%')"
+        }
+        DeckType::Oathbreaker => "types LIKE '%Planeswalker%'",
+        DeckType::PauperEdh => {
+            "types LIKE '%Creature%' AND name IN (SELECT card_name FROM printings WHERE rarity = 'uncommon')"
+        }
+        DeckType::Constructed => "1=1",
+    }
+}
+
+pub fn rvcnfn(conn: &Connection, n: &str, dt: DeckType) -> Result<Vec<String>> {
+    if n.is_empty() {
+        return Ok(Vec::new());
+    }
+    let query = format!(
+        "
+SELECT name
+FROM cards
+WHERE name LIKE \"%{}%\"
+AND {}
+AND {}
+ORDER BY name ASC;",
+        n,
+        commander_clause(dt),
+        legality_clause(dt)
+    );
+    let mut stmt = conn.prepare(query.as_str())?;
+
+    let a = stmt.query_map([], |row| row.get(0))?;
+    a.collect()
+}
+
+// Candidates for the second commander of a deck led by a commander of the given type.
+fn partner_clause(ct: &CommanderType, dt: DeckType) -> String {
+    let (clause, commander) = match ct {
+        CommanderType::Partner => (
+            String::from("card_text LIKE '%Partner%' AND card_text NOT LIKE '%Partner with%'"),
+            true,
+        ),
+        CommanderType::PartnerWith(name) => (format!("name = \"{}\"", name), true),
+        CommanderType::FriendsForever => (String::from("card_text LIKE '%Friends forever%'"), true),
+        CommanderType::ChooseBackground => (
+            String::from("types LIKE 'Legendary Enchantment%' AND types LIKE '%Background%'"),
+            false,
+        ),
+        CommanderType::DoctorsCompanion => (String::from("types LIKE '%Time Lord Doctor%'"), true),
+        CommanderType::Doctor => (String::from("card_text LIKE '%Doctor''s companion%'"), true),
+        CommanderType::Default | CommanderType::Invalid => (String::from("1=0"), false),
+    };
+    if commander {
+        format!("{} AND {}", clause, commander_clause(dt))
+    } else {
+        clause
+    }
+}
+
+pub fn rvcnfnp(conn: &Connection, n: &str, dt: DeckType, ct: &CommanderType) -> Result<Vec<String>> {
     if n.is_empty() {
         return Ok(Vec::new());
     }
@@ -1310,10 +2865,12 @@ pub fn rvcnfn(conn: &Connection, n: &str) -> Result<Vec<String>> {
 SELECT name
 FROM cards
 WHERE name LIKE \"%{}%\"
-AND types LIKE \'Legendary%\'
-AND (types LIKE \'%Creature%\' OR card_text LIKE \'%can be your commander%\')
+AND {}
+AND {}
 ORDER BY name ASC;",
-        n
+        n,
+        partner_clause(ct, dt),
+        legality_clause(dt)
     );
     let mut stmt = conn.prepare(query.as_str())?;
 
@@ -1321,20 +2878,31 @@ ORDER BY name ASC;",
     a.collect()
 }
 
-pub fn rvcnfnp(conn: &Connection, n: &str) -> Result<Vec<String>> {
+// Signature spells for an Oathbreaker must be instants or sorceries within its color identity.
+pub fn rvssfn(conn: &Connection, n: &str, color: &[char]) -> Result<Vec<String>> {
     if n.is_empty() {
         return Ok(Vec::new());
     }
+    let mut colors = String::from("WUBRG");
+    for c in color {
+        colors = colors.replace(*c, "");
+    }
+    let ci = match colors.len() {
+        0 => String::from("1=1"),
+        _ => format!("color_identity REGEXP '^[^{}]*$'", &colors),
+    };
     let query = format!(
         "
 SELECT name
 FROM cards
 WHERE name LIKE \"%{}%\"
-AND types LIKE 'Legendary%'
-AND card_text LIKE '%Partner%'
-AND (types LIKE '%Creature%' OR card_text LIKE '%can be your commander%')
+AND (types LIKE '%Instant%' OR types LIKE '%Sorcery%')
+AND {}
+AND {}
 ORDER BY name ASC;",
-        n
+        n,
+        ci,
+        legality_clause(DeckType::Oathbreaker)
     );
     let mut stmt = conn.prepare(query.as_str())?;
 
@@ -1342,6 +2910,23 @@ ORDER BY name ASC;",
     a.collect()
 }
 
+// Splits a decklist line such as "10 Island" or "4x Relentless Rats" into its count and name.
+// Splits a quantity off the front of a list line, as in "12 Island" or "2x Opt". A bare number
+// only counts when the rest is a card and the whole line isn't, so "1996 World Champion" stays whole.
+fn stoq(conn: &Connection, s: &str) -> (u32, String) {
+    let re = Regex::new(r"^(\d+)(x?)\s+(.+)$").unwrap();
+    let cap = match re.captures(s) {
+        Some(cap) => cap,
+        None => return (1, s.to_string()),
+    };
+    let rest = cap[3].trim();
+    let is_card = |cn: &str| rcfn(conn, cn.split(" // ").next().unwrap_or(cn), None).is_ok();
+    if cap[2].is_empty() && (is_card(s) || !is_card(rest)) {
+        return (1, s.to_string());
+    }
+    (cap[1].parse().unwrap_or(1), rest.to_string())
+}
+
 fn stovs(ss: String) -> Vec<String> {
     let mut vs = Vec::new();
 
@@ -1450,6 +3035,15 @@ fn cfr(row: &Row) -> Result<Card> {
         Err(_) => true,
     };
 
+    let printing = match row.get::<usize, Option<String>>(17)? {
+        Some(set_code) => Some(CardPrinting {
+            set_code,
+            number: row.get::<usize, Option<String>>(18)?.unwrap_or_default(),
+            foil: row.get::<usize, Option<bool>>(19)?.unwrap_or_default(),
+        }),
+        None => None,
+    };
+
     Ok(Card {
         cmc: row.get(0)?,
         color_identity: stovch(row.get(1)?),
@@ -1466,6 +3060,9 @@ fn cfr(row: &Row) -> Result<Card> {
         rarity: row.get(14)?,
         price,
         stale,
+        printing,
+        quantity: row.get::<usize, Option<u32>>(20)?.unwrap_or_default(),
+        zone: row.get::<usize, Option<String>>(21)?.map(|s| Zone::from(&s)),
     })
 }
 
@@ -1473,7 +3070,7 @@ pub fn rvicfdid(conn: &Connection, did: i32) -> Result<Vec<ImportCard>> {
     let mut stmt = conn
         .prepare(
             r#"SELECT
-        card_name, tags
+        card_name, tags, printing_set, printing_number, foil, quantity, zone
         FROM deck_contents
         WHERE deck = :did;"#,
         )
@@ -1481,9 +3078,16 @@ pub fn rvicfdid(conn: &Connection, did: i32) -> Result<Vec<ImportCard>> {
 
     let r = stmt
         .query_map(named_params! {":did": did}, |row| {
+            let set_code: Option<String> = row.get(2)?;
+            let foil = if set_code.is_some() { Some(row.get(4)?) } else { None };
             Ok(ImportCard {
                 name: row.get(0)?,
                 tags: row.get(1)?,
+                set_code,
+                number: row.get(3)?,
+                foil,
+                quantity: row.get(5)?,
+                zone: row.get(6)?,
             })
         })?
         .collect();
@@ -1495,14 +3099,16 @@ pub fn rvmcfd(conn: &Connection, did: i32) -> Result<Vec<CardStat>> {
     let mut stmt = conn
         .prepare(
             r#"SELECT
-        cmc, color_identity, mana_cost, name, tags, types, price, date_price_retrieved
+        cmc, color_identity, mana_cost, name, tags, types,
+        CASE WHEN printing_set IS NULL THEN price ELSE printing_price END,
+        CASE WHEN printing_set IS NULL THEN date_price_retrieved ELSE printing_price_date END,
+        quantity
         FROM cards
         INNER JOIN deck_contents
         ON cards.name = deck_contents.card_name
         WHERE deck_contents.deck = :did
         AND (side != 'b' OR layout == 'split' OR layout == 'modal_dfc')
-        AND tags IS NOT NULL 
-        AND tags REGEXP '\|?main(?:$|\|)';"#,
+        AND zone IN ('commander', 'main');"#,
         )
         .unwrap();
     let date = Utc::today();
@@ -1523,16 +3129,78 @@ pub fn rvmcfd(conn: &Connection, did: i32) -> Result<Vec<CardStat>> {
                 color_identity: stovs(row.get(1)?),
                 mana_cost: row.get(2)?,
                 name: row.get(3)?,
-                tags: stovs(row.get(4)?),
+                tags: row.get::<usize, Option<String>>(4)?.map(stovs).unwrap_or_default(),
                 types: row.get(5)?,
                 price: if let Ok(i) = row.get(6) { i } else { 0.0 },
                 stale,
+                quantity: row.get(8)?,
             })
         })?
         .collect();
     a
 }
 
+// Counts the copies in a deck carrying a tag or one of its children, leaving out the companion.
+pub fn rqtfd(conn: &Connection, did: i32, tag: &str) -> Result<u32> {
+    conn.query_row(
+        &format!(
+            r#"SELECT COALESCE(SUM(quantity), 0)
+            FROM cards
+            INNER JOIN deck_contents
+            ON cards.name = deck_contents.card_name
+            WHERE deck_contents.deck = :did
+            AND (side != 'b' OR layout == 'split' OR layout == 'modal_dfc')
+            AND {DECK_ZONES}
+            AND card_name IS NOT (SELECT companion FROM decks WHERE id = :did)
+            AND tags IS NOT NULL AND tags REGEXP :re;"#
+        ),
+        named_params! {":did": did, ":re": format!(r"(?:\||^){}(?:/[^|]*)?(?:$|\|)", regex::escape(tag))},
+        |row| row.get(0),
+    )
+}
+
+// Whether an omni query parses in full, so that a rule never falls back to a name search.
+pub fn vomni(omni: &str) -> bool {
+    OmniParser::parse(Rule::input, omni).is_ok_and(|p| p.as_str() == omni)
+}
+
+// The (card, tag) pairs that auto-tag rules would add to a deck, skipping rules that don't parse.
+// Cards that already carry the tag or one of its children are left out.
+pub fn rvatfd(conn: &Connection, did: i32, rules: &[AutoTagRule]) -> Result<Vec<(String, String)>> {
+    let mut cf = CardFilter::new();
+    cf.did = did;
+    let mut vr: Vec<(String, String)> = Vec::new();
+    for rule in rules.iter().filter(|r| vomni(&r.query)) {
+        let child = format!("{}/", rule.tag);
+        for c in rvcfcf(conn, &cf.make_query(false, &rule.query))? {
+            let tagged = c.tags.iter().any(|t| t == &rule.tag || t.starts_with(&child));
+            if !tagged && !vr.iter().any(|(cn, t)| cn == &c.name && t == &rule.tag) {
+                vr.push((c.name, rule.tag.clone()));
+            }
+        }
+    }
+    Ok(vr)
+}
+
+// Adds tags to cards in a deck, leaving cards that already have them alone.
+pub fn uatindc(conn: &Connection, did: i32, vct: &[(String, String)]) -> Result<()> {
+    let mut stmt = conn.prepare(
+        "UPDATE deck_contents
+        SET tags = :tags
+        WHERE card_name = :name
+        AND deck = :did;",
+    )?;
+    for (cn, tag) in vct {
+        let mut card = rcfn(conn, cn, Some(did))?;
+        if !card.tags.contains(tag) {
+            card.tags.push(tag.clone());
+            card.tags.sort();
+            stmt.execute(named_params! {":tags": card.tags.join("|"), ":name": cn, ":did": did})?;
+        }
+    }
+    Ok(())
+}
+
 pub fn ucfd(rwl_conn: &Mutex<Connection>, did: i32) -> Result<()> {
     let unpriced: Result<Vec<(String, String, String)>> = {
         let conn = rwl_conn.lock().unwrap();
@@ -1545,9 +3213,9 @@ pub fn ucfd(rwl_conn: &Mutex<Connection>, did: i32) -> Result<()> {
                 ON cards.name = deck_contents.card_name
                 WHERE deck_contents.deck = :did
                 AND side != 'b'
+                AND printing_set IS NULL
                 AND (date_price_retrieved ISNULL OR date_price_retrieved < date('now','-6 day'))
-                AND tags IS NOT NULL 
-                AND tags REGEXP '\|?main(?:$|\|)';"#,
+                AND zone IN ('commander', 'main');"#,
             )
             .unwrap();
         let a = stmt
@@ -1576,11 +3244,51 @@ pub fn ucfd(rwl_conn: &Mutex<Connection>, did: i32) -> Result<()> {
         )?;
     }
 
+    let unpriced: Vec<Card> = {
+        let conn = rwl_conn.lock().unwrap();
+        let query = format!(
+            "INNER JOIN deck_contents
+            ON cards.name = deck_contents.card_name
+            WHERE deck_contents.deck = {did}
+            AND side != 'b'
+            AND printing_set IS NOT NULL
+            AND (printing_price_date ISNULL OR printing_price_date < date('now','-6 day'))
+            AND {DECK_ZONES};"
+        );
+        rvcfcf(&conn, &query)?
+    };
+
+    for c in unpriced {
+        thread::sleep(delay);
+        upfpr(&rwl_conn.lock().unwrap(), &c, did)?;
+    }
+
     Ok(())
 }
 
+fn upfpr(conn: &Connection, c: &Card, did: i32) -> Result<Card> {
+    let p = c.printing.as_ref().unwrap();
+    let price = rcostfpr(&p.set_code, &p.number, p.foil).unwrap_or_default();
+    let mut stmt = conn.prepare(
+        "UPDATE deck_contents
+        SET printing_price = :price,
+        printing_price_date = date()
+        WHERE card_name = :name
+        AND deck = :did;",
+    )?;
+    stmt.execute(named_params! {":price": price, ":name": c.name, ":did": did})?;
+    if let CardLayout::Paired(_, _, n) = &c.lo {
+        stmt.execute(named_params! {":price": price, ":name": n, ":did": did})?;
+    }
+
+    rcfn(conn, &c.name, Some(did))
+}
+
 pub fn upfcn_quick(conn: &Connection, cn: &str, odid: Option<i32>) -> Result<Card> {
-    let c = rcfn(conn, cn, None).unwrap();
+    let c = rcfn(conn, cn, odid).unwrap();
+    if let (Some(did), Some(_)) = (odid, &c.printing) {
+        return upfpr(conn, &c, did);
+    }
     let s = match &c.lo {
         CardLayout::Paired(_, _, rel) => format!("{} // {}", cn, rel),
         _ => cn.to_string(),
@@ -1620,6 +3328,9 @@ pub fn upfcn_quick(conn: &Connection, cn: &str, odid: Option<i32>) -> Result<Car
 }
 
 pub fn upfcn_detailed(conn: &Connection, c: &Card, odid: Option<i32>) -> Result<Card> {
+    if let (Some(did), Some(_)) = (odid, &c.printing) {
+        return upfpr(conn, c, did);
+    }
     let cn = match &c.lo {
         CardLayout::Paired('a', _, rel) => format!("{} // {}", c.name, rel),
         CardLayout::Paired('b', _, rel) => format!("{} // {}", rel, c.name),
@@ -1680,14 +3391,86 @@ pub fn upfcn_detailed(conn: &Connection, c: &Card, odid: Option<i32>) -> Result<
 //     rcfn(conn, cn, odid)
 // }
 
+pub fn udn(conn: &Connection, did: i32, notes: &str) -> Result<()> {
+    // Empty notes are stored as NULL, as they were before the notes screen existed.
+    let notes = if notes.trim().is_empty() { None } else { Some(notes) };
+    conn.execute(
+        "UPDATE decks SET notes = :notes WHERE id = :did",
+        named_params! {":notes": notes, ":did": did},
+    )?;
+    Ok(())
+}
+
+pub fn ucomp(conn: &Connection, did: i32, companion: Option<&str>) -> Result<()> {
+    conn.execute(
+        "UPDATE decks SET companion = :companion WHERE id = :did",
+        named_params! {":companion": companion, ":did": did},
+    )?;
+    Ok(())
+}
+
 pub fn dd(conn: &Connection, did: i32) -> Result<()> {
     conn.execute(
         "DELETE FROM decks WHERE id = :did",
         named_params! {":did": did},
     )?;
+    conn.execute(
+        "DELETE FROM deck_contents WHERE deck = :did",
+        named_params! {":did": did},
+    )?;
+    conn.execute(
+        "DELETE FROM deck_history WHERE deck = :did",
+        named_params! {":did": did},
+    )?;
+    conn.execute(
+        "DELETE FROM deck_row_history WHERE deck = :did",
+        named_params! {":did": did},
+    )?;
+    conn.execute(
+        "DELETE FROM snapshot_contents WHERE snapshot IN (SELECT id FROM snapshots WHERE deck = :did)",
+        named_params! {":did": did},
+    )?;
+    conn.execute(
+        "DELETE FROM snapshots WHERE deck = :did",
+        named_params! {":did": did},
+    )?;
+    Ok(())
+}
+
+pub fn uda(conn: &Connection, did: i32, archived: bool) -> Result<()> {
+    conn.execute(
+        "UPDATE decks SET archived = :archived WHERE id = :did",
+        named_params! {":archived": archived, ":did": did},
+    )?;
     Ok(())
 }
 
+// Moves a deck to the trash, or back out of it.
+pub fn udt(conn: &Connection, did: i32, trashed: bool) -> Result<()> {
+    conn.execute(
+        "UPDATE decks SET deleted = CASE WHEN :trashed THEN datetime('now', 'localtime') END
+        WHERE id = :did",
+        named_params! {":trashed": trashed, ":did": did},
+    )?;
+    Ok(())
+}
+
+// Deletes the decks that have been in the trash for longer than the given number of days.
+pub fn dvdt(conn: &Connection, days: u32) -> Result<Vec<i32>> {
+    let mut stmt = conn.prepare(
+        "SELECT id FROM decks
+        WHERE deleted IS NOT NULL
+        AND deleted < datetime('now', 'localtime', '-' || :days || ' days');",
+    )?;
+    let vdid: Vec<i32> = stmt
+        .query_map(named_params! {":days": days}, |row| row.get(0))?
+        .collect::<Result<_>>()?;
+    for did in &vdid {
+        dd(conn, *did)?;
+    }
+    Ok(vdid)
+}
+
 pub fn rpfdc(name: &str, layout: &str, related: &str) -> Result<f64> {
     let s = if !related.is_empty() && layout != "meld" {
         format!("{} // {}", name, related)
@@ -1725,6 +3508,10 @@ pub struct JsonCard {
     // pub related_cards: Option<Relation>,
     pub side: Option<char>,
     pub rarity: String,
+    #[serde(default)]
+    pub rulings: Vec<Ruling>,
+    #[serde(default)]
+    pub number: String,
 }
 
 fn zero() -> String {
@@ -1752,12 +3539,18 @@ pub struct Legalities {
     #[serde(default)]
     historic: String,
     #[serde(default)]
+    historicbrawl: String,
+    #[serde(default)]
     legacy: String,
     #[serde(default)]
     modern: String,
     #[serde(default)]
+    oathbreaker: String,
+    #[serde(default)]
     pauper: String,
     #[serde(default)]
+    paupercommander: String,
+    #[serde(default)]
     penny: String,
     #[serde(default)]
     pioneer: String,
@@ -1799,12 +3592,24 @@ impl ToString for Legalities {
         if !b.contains(&self.commander) {
             vs.push("commander");
         }
+        if !b.contains(&self.historicbrawl) {
+            vs.push("historicbrawl");
+        }
         if !b.contains(&self.modern) {
             vs.push("modern");
         }
+        if !b.contains(&self.oathbreaker) {
+            vs.push("oathbreaker");
+        }
+        if !b.contains(&self.paupercommander) {
+            vs.push("paupercommander");
+        }
         if !b.contains(&self.standard) {
             vs.push("standard");
         }
+        if !b.contains(&self.vintage) {
+            vs.push("vintage");
+        }
 
         vs.join("|")
     }
@@ -1813,9 +3618,54 @@ impl ToString for Legalities {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::util::{AutoTagRule, TagTarget};
     // use crate::util::get_local_file;
     use std::env::current_dir;
 
+    fn memdb() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        migratedb(&conn).unwrap();
+        add_regexp_function(&conn).unwrap();
+        conn
+    }
+
+    fn test_set(code: &str) -> Set {
+        Set {
+            code: String::from(code),
+            name: format!("Test set {}", code),
+            date: String::from("2021-04-23"),
+            set_type: String::from("expansion"),
+        }
+    }
+
+    // A database holding Talrand and the given cards, and a Commander deck Talrand leads.
+    fn deck_with(vjc: &[JsonCard]) -> (Connection, i32) {
+        let conn = memdb();
+        let mut vjc = vjc.to_vec();
+        vjc.push(json_card("Talrand, Sky Summoner", "Legendary Creature — Merfolk Wizard", "", &["U"]));
+        ivcfjsmap(&conn, vjc, &test_set("TST")).unwrap();
+        let did = ideck(&conn, "test", "Talrand, Sky Summoner", None, "Commander").unwrap();
+        (conn, did)
+    }
+
+    fn json_card(name: &str, types: &str, text: &str, ci: &[&str]) -> JsonCard {
+        serde_json::from_value(serde_json::json!({
+            "convertedManaCost": 3.0,
+            "colorIdentity": ci,
+            "legalities": { "commander": "Legal" },
+            "manaCost": "{2}{U}",
+            "name": name,
+            "text": text,
+            "type": types,
+            "layout": "normal",
+            "rarity": "rare",
+            "rulings": [
+                { "date": "2020-01-01", "text": format!("A ruling for {}.", name) }
+            ]
+        }))
+        .unwrap()
+    }
+
     #[test]
     fn complex_query() {
         let p = current_dir().unwrap().join("target/debug/lieutenant.db");
@@ -1838,10 +3688,10 @@ mod tests {
             let query = cf.make_query(false, "ty:shaman te:sacrifice r:c");
             let res = rvcfcf(&conn, &query).unwrap();
             assert_eq!(res.len(), 3);
-            let query = cf.make_query(false, "ty:shaman te:sacrifice r:c tag:main");
+            let query = cf.make_query(false, "ty:shaman te:sacrifice r:c zone:main");
             let res = rvcfcf(&conn, &query).unwrap();
             assert_eq!(res.len(), 2);
-            let query = cf.make_query(false, "ty:shaman te:sacrifice r:c tag:main cmc:1");
+            let query = cf.make_query(false, "ty:shaman te:sacrifice r:c zone:main cmc:1");
             let res = rvcfcf(&conn, &query).unwrap();
             assert_eq!(res.len(), 1);
             assert_eq!(res.first().unwrap().name, String::from("Krark-Clan Shaman"));
@@ -1910,4 +3760,704 @@ mod tests {
             assert!(res.is_empty());
         }
     }
+
+    #[test]
+    fn rulings_import() {
+        let conn = memdb();
+        let vjc = vec![
+            json_card("Counterspell", "Instant", "Counter target spell.", &["U"]),
+            json_card("Counterspell", "Instant", "Counter target spell.", &["U"]),
+        ];
+        let summary = ivcfjsmap(&conn, vjc, &test_set("STX")).unwrap();
+        assert_eq!(summary.added, 1);
+        assert_eq!(summary.duplicates, 1);
+
+        let rulings = rvrfcn(&conn, "Counterspell").unwrap();
+        assert_eq!(rulings.len(), 1);
+        assert_eq!(rulings[0].text, "A ruling for Counterspell.");
+
+        // A card that can't be stored is skipped without losing the rest of the set.
+        conn.execute_batch(
+            "CREATE TRIGGER no_opt BEFORE INSERT ON printings WHEN NEW.card_name = 'Opt'
+            BEGIN SELECT RAISE(ABORT, 'bad card'); END;",
+        )
+        .unwrap();
+        let vjc = vec![
+            json_card("Opt", "Instant", "Scry 1.", &["U"]),
+            json_card("Ponder", "Sorcery", "Draw a card.", &["U"]),
+        ];
+        let summary = ivcfjsmap(&conn, vjc, &test_set("M21")).unwrap();
+        assert_eq!((summary.added, summary.failed), (1, 1));
+        assert!(rcfn(&conn, "Opt", None).is_err());
+        assert!(rvrfcn(&conn, "Opt").unwrap().is_empty());
+        assert_eq!(rvrfcn(&conn, "Ponder").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn set_filter() {
+        let conn = memdb();
+        let vjc = vec![json_card("Counterspell", "Instant", "Counter target spell.", &["U"])];
+        ivcfjsmap(&conn, vjc, &test_set("TMP")).unwrap();
+        let vjc = vec![
+            json_card("Counterspell", "Instant", "Counter target spell.", &["U"]),
+            json_card("Opt", "Instant", "Scry 1.", &["U"]),
+        ];
+        ivcfjsmap(&conn, vjc, &test_set("STX")).unwrap();
+
+        let cf = CardFilter::from(1, "U", DeckType::Commander, DefaultFilter::Name, SortOrder::NameAsc);
+        let res = rvcnfcf(&conn, &cf.make_query(true, "e:tmp")).unwrap();
+        assert_eq!(res, vec![String::from("Counterspell")]);
+        let res = rvcnfcf(&conn, &cf.make_query(true, "set:stx")).unwrap();
+        assert_eq!(res.len(), 2);
+        let res = rvcnfcf(&conn, &cf.make_query(true, "set:!tmp")).unwrap();
+        assert_eq!(res, vec![String::from("Opt")]);
+        assert_eq!(rvpfcn(&conn, "Counterspell").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn import_quantities() {
+        let conn = memdb();
+        let vjc = vec![
+            json_card("Talrand, Sky Summoner", "Legendary Creature — Merfolk Wizard", "", &["U"]),
+            json_card("Island", "Basic Land — Island", "({T}: Add {U}.)", &["U"]),
+            json_card("Opt", "Instant", "Scry 1.", &["U"]),
+            json_card("1996 World Champion", "Legendary Creature — Legend", "", &["W", "U", "B", "R", "G"]),
+            json_card(
+                "Relentless Rats",
+                "Creature — Rat",
+                "A deck can have any number of cards named Relentless Rats.",
+                &["B"],
+            ),
+        ];
+        ivcfjsmap(&conn, vjc, &test_set("TST")).unwrap();
+
+        let cards = ["Talrand, Sky Summoner", "12 Island", "2x Opt", "Island"]
+            .iter()
+            .map(|s| ImportCard {
+                name: String::from(*s),
+                ..Default::default()
+            })
+            .collect();
+        let did = import_deck(&conn, String::from("quantities"), Vec::new(), cards).unwrap();
+        assert_eq!(stoq(&conn, "1996 World Champion"), (1, String::from("1996 World Champion")));
+        assert_eq!(stoq(&conn, "3 Unknown Card"), (1, String::from("3 Unknown Card")));
+        assert_eq!(stoq(&conn, "3x Unknown Card"), (3, String::from("Unknown Card")));
+
+        let quantities = rvqfdid(&conn, did).unwrap();
+        assert_eq!(quantities.get("Island"), Some(&13));
+        assert_eq!(quantities.get("Opt"), None);
+        assert_eq!(rcfn(&conn, "Opt", Some(did)).unwrap().quantity, 1);
+        assert_eq!(rcfn(&conn, "Relentless Rats", None).unwrap().max_copies(), None);
+    }
+
+    #[test]
+    fn deck_notes() {
+        let (conn, did) = deck_with(&[]);
+        assert_eq!(rdfdid(&conn, did).unwrap().notes, "");
+
+        udn(&conn, did, "Spellslinger\nCut Opt").unwrap();
+        assert_eq!(rdfdid(&conn, did).unwrap().notes, "Spellslinger\nCut Opt");
+
+        udn(&conn, did, "  ").unwrap();
+        let notes: Option<String> = conn
+            .query_row("SELECT notes FROM decks WHERE id = ?", params![did], |row| row.get(0))
+            .unwrap();
+        assert_eq!(notes, None);
+    }
+
+    #[test]
+    fn deck_formats() {
+        let conn = memdb();
+        let mut opt = json_card("Opt", "Instant", "Scry 1.", &["U"]);
+        opt.legalities = Legalities {
+            brawl: String::from("Legal"),
+            ..Default::default()
+        };
+        let vjc = vec![
+            json_card("Talrand, Sky Summoner", "Legendary Creature — Merfolk Wizard", "", &["U"]),
+            opt,
+        ];
+        ivcfjsmap(&conn, vjc, &test_set("TST")).unwrap();
+
+        let cf = CardFilter::from(1, "U", DeckType::Commander, DefaultFilter::Name, SortOrder::NameAsc);
+        let res = rvcnfcf(&conn, &cf.make_query(true, "")).unwrap();
+        assert_eq!(res, vec![String::from("Talrand, Sky Summoner")]);
+        let cf = CardFilter::from(1, "U", DeckType::Brawl, DefaultFilter::Name, SortOrder::NameAsc);
+        let res = rvcnfcf(&conn, &cf.make_query(true, "")).unwrap();
+        assert_eq!(res, vec![String::from("Opt")]);
+
+        let did = ideck(&conn, "brawl", "Opt", None, &DeckType::Brawl.to_string()).unwrap();
+        assert_eq!(rdfdid(&conn, did).unwrap().deck_type, DeckType::Brawl);
+        assert_eq!(DeckType::from("Vintage Cube"), DeckType::Commander);
+
+        let opt = rcfn(&conn, "Opt", None).unwrap();
+        assert_eq!(DeckType::Commander.max_copies(&opt), Some(1));
+        assert_eq!(DeckType::Constructed.max_copies(&opt), Some(4));
+    }
+
+    #[test]
+    fn commander_pairs() {
+        let conn = memdb();
+        let vjc = vec![
+            json_card("Wilson, Refined Grizzly", "Legendary Creature — Bear Warrior", "Choose a Background", &["G"]),
+            json_card("Acolyte of Bahamut", "Legendary Enchantment — Background", "", &["G"]),
+            json_card("Rose Tyler", "Legendary Creature — Human", "Doctor's companion", &["W"]),
+            json_card("The Tenth Doctor", "Legendary Creature — Time Lord Doctor", "", &["R"]),
+        ];
+        ivcfjsmap(&conn, vjc, &test_set("TST")).unwrap();
+
+        let wilson = rcfn(&conn, "Wilson, Refined Grizzly", None).unwrap();
+        let res = rvcnfnp(&conn, "a", DeckType::Commander, &wilson.is_commander()).unwrap();
+        assert_eq!(res, vec![String::from("Acolyte of Bahamut")]);
+        assert!(wilson.can_pair(&rcfn(&conn, "Acolyte of Bahamut", None).unwrap()));
+
+        let rose = rcfn(&conn, "Rose Tyler", None).unwrap();
+        let res = rvcnfnp(&conn, "e", DeckType::Commander, &rose.is_commander()).unwrap();
+        assert_eq!(res, vec![String::from("The Tenth Doctor")]);
+        let doctor = rcfn(&conn, "The Tenth Doctor", None).unwrap();
+        assert!(doctor.can_pair(&rose));
+        assert!(!doctor.can_pair(&wilson));
+    }
+
+    #[test]
+    fn companions() {
+        let (conn, did) = deck_with(&[
+            json_card("Lurrus of the Dream-Den", "Legendary Creature — Cat Nightmare", "Companion — Each permanent card in your starting deck has mana value 2 or less.", &["W", "B"]),
+        ]);
+        assert!(rdfdid(&conn, did).unwrap().companion.is_none());
+        ucomp(&conn, did, Some("Lurrus of the Dream-Den")).unwrap();
+        let lurrus = rdfdid(&conn, did).unwrap().companion.unwrap();
+
+        let card = |name: &str, types: &str, cmc: f64| Card {
+            name: String::from(name),
+            types: String::from(types),
+            cmc,
+            quantity: 1,
+            ..Default::default()
+        };
+        let deck = vec![
+            card("Opt", "Instant", 1.0),
+            card("Sol Ring", "Artifact", 1.0),
+            card("Talrand, Sky Summoner", "Legendary Creature — Merfolk Wizard", 4.0),
+            card("Island", "Basic Land — Island", 0.0),
+        ];
+        let rules = DeckType::Commander.rules();
+        assert_eq!(lurrus.companion_issues(&deck, &rules), vec![String::from("Talrand, Sky Summoner")]);
+        assert!(lurrus.companion_issues(&deck[..2], &rules).is_empty());
+    }
+
+    #[test]
+    fn change_commander() {
+        let (conn, did) = deck_with(&[
+            json_card("Krenko, Mob Boss", "Legendary Creature — Goblin Warrior", "", &["R"]),
+            json_card("Opt", "Instant", "Scry 1.", &["U"]),
+        ]);
+        ictodc(&conn, &rcfn(&conn, "Opt", None).unwrap(), did).unwrap();
+
+        assert!(ucom(&conn, did, "Opt", None, true).is_err());
+        let vcn = vec![String::from("Krenko, Mob Boss"), String::from("Talrand, Sky Summoner")];
+        let before = rvcsfd(&conn, &vcn, did).unwrap();
+        let outside = ucom(&conn, did, "Krenko, Mob Boss", None, false).unwrap();
+        assert_eq!(outside, vec![String::from("Opt")]);
+
+        let deck = rdfdid(&conn, did).unwrap();
+        assert_eq!(deck.commander.name, "Krenko, Mob Boss");
+        assert_eq!(deck.color, "R");
+        let krenko = rcfn(&conn, "Krenko, Mob Boss", Some(did)).unwrap();
+        assert_eq!(krenko.zone, Some(Zone::Commander));
+        let names: Vec<String> = rvcfdid(&conn, did, SortOrder::NameAsc)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(names, vec![String::from("Krenko, Mob Boss"), String::from("Opt")]);
+
+        // Undoing the swap puts back the deck's commander along with the cards' zones.
+        ihfd(&conn, before, did).unwrap();
+        assert_eq!(uhfd(&conn, did, false).unwrap(), 3);
+        let deck = rdfdid(&conn, did).unwrap();
+        assert_eq!(deck.commander.name, "Talrand, Sky Summoner");
+        assert_eq!(rcfn(&conn, "Talrand, Sky Summoner", Some(did)).unwrap().zone, Some(Zone::Commander));
+        assert!(rcfn(&conn, "Krenko, Mob Boss", Some(did)).unwrap().zone.is_none());
+        assert_eq!(uhfd(&conn, did, true).unwrap(), 3);
+        assert_eq!(rdfdid(&conn, did).unwrap().commander.name, "Krenko, Mob Boss");
+    }
+
+    #[test]
+    fn validate_deck() {
+        let mut banned = json_card("Time Walk", "Sorcery", "Take an extra turn.", &["U"]);
+        banned.legalities = Legalities::default();
+        banned.legalities.vintage = String::from("Restricted");
+        let (conn, did) = deck_with(&[
+            json_card("Opt", "Instant", "Scry 1.", &["U"]),
+            json_card("Shock", "Instant", "Shock deals 2 damage to any target.", &["R"]),
+            banned,
+        ]);
+        for cn in ["Opt", "Shock", "Time Walk"] {
+            ictodc(&conn, &rcfn(&conn, cn, None).unwrap(), did).unwrap();
+        }
+        uqindc(&conn, &rcfn(&conn, "Opt", Some(did)).unwrap(), did, 2).unwrap();
+
+        let vv: Vec<Option<String>> = vdeck(&conn, did).unwrap().into_iter().map(|v| v.card).collect();
+        assert_eq!(
+            vv,
+            vec![
+                None,
+                Some(String::from("Opt")),
+                Some(String::from("Shock")),
+                Some(String::from("Time Walk")),
+            ]
+        );
+    }
+
+    #[test]
+    fn deck_zones() {
+        let (conn, did) = deck_with(&[
+            json_card("Opt", "Instant", "Scry 1.", &["U"]),
+            json_card("Shock", "Instant", "Shock deals 2 damage to any target.", &["R"]),
+        ]);
+        for cn in ["Opt", "Shock"] {
+            ictodc(&conn, &rcfn(&conn, cn, None).unwrap(), did).unwrap();
+        }
+        let talrand = rcfn(&conn, "Talrand, Sky Summoner", Some(did)).unwrap();
+        assert_eq!(talrand.zone, Some(Zone::Commander));
+        let shock = rcfn(&conn, "Shock", Some(did)).unwrap();
+        assert_eq!(shock.zone, Some(Zone::Main));
+
+        let shock = uzindc(&conn, &shock, did, Zone::Sideboard).unwrap();
+        assert_eq!(shock.zone, Some(Zone::Sideboard));
+        let names: Vec<String> = rvmcfd(&conn, did).unwrap().into_iter().map(|c| c.name).collect();
+        assert_eq!(names, vec![String::from("Opt"), String::from("Talrand, Sky Summoner")]);
+        assert!(vdeck(&conn, did).unwrap().iter().all(|v| v.card.as_deref() != Some("Shock")));
+
+        let cf = CardFilter::from(did, "U", DeckType::Commander, DefaultFilter::Name, SortOrder::NameAsc);
+        let zoned = rvcnfcf(&conn, &cf.make_query(false, "zone:side")).unwrap();
+        assert_eq!(zoned, vec![String::from("Shock")]);
+        let opt = rcfn(&conn, "Opt", Some(did)).unwrap();
+        uzindc(&conn, &opt, did, Zone::Maybeboard).unwrap();
+        assert!(rvcnfcf(&conn, &cf.make_query(false, "z:m")).unwrap().is_empty());
+        assert!(rvcnfcf(&conn, &cf.make_query(false, "z:mai")).unwrap().is_empty());
+        let zoned = rvcnfcf(&conn, &cf.make_query(false, "z:maybe")).unwrap();
+        assert_eq!(zoned, vec![String::from("Opt")]);
+    }
+
+    #[test]
+    fn nested_tags() {
+        let (conn, did) = deck_with(&[
+            json_card("Opt", "Instant", "Scry 1.", &["U"]),
+            json_card("Pongify", "Instant", "Destroy target creature.", &["U"]),
+            json_card("Annul", "Instant", "Counter target artifact or enchantment spell.", &["U"]),
+        ]);
+        for (cn, tag) in [("Opt", "draw"), ("Pongify", "removal/creature"), ("Annul", "removal")] {
+            ictodc(&conn, &rcfn(&conn, cn, None).unwrap(), did).unwrap();
+            ttindc(&conn, cn, &String::from(tag), did);
+        }
+
+        let cf = CardFilter::from(did, "U", DeckType::Commander, DefaultFilter::Name, SortOrder::NameAsc);
+        let res = rvcnfcf(&conn, &cf.make_query(false, "tag:removal")).unwrap();
+        assert_eq!(res, vec![String::from("Annul"), String::from("Pongify")]);
+        let res = rvcnfcf(&conn, &cf.make_query(false, "tag:removal/creature")).unwrap();
+        assert_eq!(res, vec![String::from("Pongify")]);
+        let res = rvcnfcf(&conn, &cf.make_query(false, "tag:draw|removal/creature")).unwrap();
+        assert_eq!(res, vec![String::from("Opt"), String::from("Pongify")]);
+        let res = rvcnfcf(&conn, &cf.make_query(false, "tag:!removal")).unwrap();
+        assert_eq!(res, vec![String::from("Opt")]);
+
+        // Renaming or deleting a tag in a deck's settings takes its children with it.
+        utindc(&conn, TagChange::Change(String::from("removal"), String::from("answers")), &cf);
+        assert_eq!(rcfn(&conn, "Pongify", Some(did)).unwrap().tags, vec![String::from("answers/creature")]);
+        assert_eq!(rcfn(&conn, "Annul", Some(did)).unwrap().tags, vec![String::from("answers")]);
+        utindc(&conn, TagChange::Delete(String::from("answers")), &cf);
+        assert!(rcfn(&conn, "Pongify", Some(did)).unwrap().tags.is_empty());
+        assert!(rcfn(&conn, "Annul", Some(did)).unwrap().tags.is_empty());
+    }
+
+    #[test]
+    fn tag_targets() {
+        let (conn, did) = deck_with(&[
+            json_card("Opt", "Instant", "Scry 1.", &["U"]),
+            json_card("Pongify", "Instant", "Destroy target creature.", &["U"]),
+            json_card("Annul", "Instant", "Counter target artifact or enchantment spell.", &["U"]),
+        ]);
+        for (cn, tag) in [("Opt", "draw"), ("Pongify", "removal/creature"), ("Annul", "removal")] {
+            ictodc(&conn, &rcfn(&conn, cn, None).unwrap(), did).unwrap();
+            ttindc(&conn, cn, &String::from(tag), did);
+        }
+        uqindc(&conn, &rcfn(&conn, "Opt", Some(did)).unwrap(), did, 3).unwrap();
+        uzindc(&conn, &rcfn(&conn, "Annul", Some(did)).unwrap(), did, Zone::Maybeboard).unwrap();
+
+        assert_eq!(rqtfd(&conn, did, "draw").unwrap(), 3);
+        assert_eq!(rqtfd(&conn, did, "removal").unwrap(), 1);
+        assert_eq!(rqtfd(&conn, did, "ramp").unwrap(), 0);
+    }
+
+    #[test]
+    fn nested_target_stats() {
+        let (conn, did) = deck_with(&[json_card("Opt", "Instant", "Scry 1.", &["U"])]);
+        ictodc(&conn, &rcfn(&conn, "Opt", None).unwrap(), did).unwrap();
+        ttindc(&conn, "Opt", &String::from("draw"), did);
+
+        // Nothing in the deck is tagged removal or one of its children.
+        let mut targets = HashMap::new();
+        targets.insert(String::from("removal/creature"), TagTarget::from("3").unwrap());
+        let dbc = std::sync::Arc::new(std::sync::Mutex::new(conn));
+        let stats = crate::util::views::DeckStatView::new(dbc, did, &targets);
+        assert_eq!(stats.tag_count("removal"), Some(0));
+        assert_eq!(stats.tag_count("removal/creature"), Some(0));
+        assert_eq!(stats.tag_count("draw"), Some(1));
+    }
+
+    #[test]
+    fn tag_info() {
+        let (conn, did) = deck_with(&[json_card("Opt", "Instant", "Scry 1.", &["U"])]);
+        ictodc(&conn, &rcfn(&conn, "Opt", None).unwrap(), did).unwrap();
+        ttindc(&conn, "Opt", &String::from("draw"), did);
+        ttindc(&conn, "Opt", &String::from("cantrip"), did);
+        let tags = rvtfdid(&conn, did).unwrap();
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags["Opt"], "cantrip|draw");
+    }
+
+    #[test]
+    fn global_tags() {
+        let (conn, d1) = deck_with(&[
+            json_card("Opt", "Instant", "Scry 1.", &["U"]),
+            json_card("Pongify", "Instant", "Destroy target creature.", &["U"]),
+        ]);
+        let d2 = ideck(&conn, "two", "Talrand, Sky Summoner", None, "Commander").unwrap();
+        for did in [d1, d2] {
+            for cn in ["Opt", "Pongify"] {
+                ictodc(&conn, &rcfn(&conn, cn, None).unwrap(), did).unwrap();
+            }
+            ttindc(&conn, "Opt", &String::from("draw"), did);
+            ttindc(&conn, "Pongify", &String::from("removal/creature"), did);
+        }
+        ttindc(&conn, "Pongify", &String::from("kill"), d2);
+
+        let mut usage = rvtu(&conn).unwrap();
+        usage.sort();
+        assert_eq!(usage[0], (String::from("draw"), 2, 2));
+        assert_eq!(usage[1], (String::from("kill"), 1, 1));
+        assert_eq!(rqtiadc(&conn, "removal").unwrap(), (2, 2));
+
+        assert_eq!(utiadc(&conn, "removal", Some("kill")).unwrap(), (2, 2));
+        let pongify = rcfn(&conn, "Pongify", Some(d2)).unwrap();
+        assert_eq!(pongify.tags, vec![String::from("kill"), String::from("kill/creature")]);
+        assert_eq!(uhfd(&conn, d2, false).unwrap(), 1);
+        let pongify = rcfn(&conn, "Pongify", Some(d2)).unwrap();
+        assert_eq!(pongify.tags, vec![String::from("kill"), String::from("removal/creature")]);
+        assert_eq!(uhfd(&conn, d2, true).unwrap(), 1);
+
+        assert_eq!(utiadc(&conn, "kill", None).unwrap(), (2, 2));
+        assert!(rcfn(&conn, "Pongify", Some(d1)).unwrap().tags.is_empty());
+        assert_eq!(rqtiadc(&conn, "draw").unwrap(), (2, 2));
+    }
+
+    #[test]
+    fn autotag_rules() {
+        let (conn, did) = deck_with(&[
+            json_card("Divination", "Sorcery", "Draw two cards.", &["U"]),
+            json_card("Pongify", "Instant", "Destroy target creature.", &["U"]),
+            json_card("Rapid Hybridization", "Instant", "Destroy target creature.", &["U"]),
+            json_card("Field of Ruin", "Land", "Destroy target nonbasic land.", &[]),
+        ]);
+        for cn in ["Divination", "Pongify", "Rapid Hybridization", "Field of Ruin"] {
+            ictodc(&conn, &rcfn(&conn, cn, None).unwrap(), did).unwrap();
+        }
+        ttindc(&conn, "Rapid Hybridization", &String::from("removal/creature"), did);
+
+        let rules: Vec<AutoTagRule> = [
+            "draw;te:\"draw a card\"|\"draw two cards\"",
+            "removal;te:\"destroy target\"|\"exile target\" ty:!land",
+            "broken;te:(draw",
+        ]
+        .iter()
+        .filter_map(|s| AutoTagRule::from(s))
+        .collect();
+        let vct = rvatfd(&conn, did, &rules).unwrap();
+        assert_eq!(
+            vct,
+            vec![
+                (String::from("Divination"), String::from("draw")),
+                (String::from("Pongify"), String::from("removal")),
+            ]
+        );
+        assert!(!vomni(&rules[2].query));
+
+        uatindc(&conn, did, &vct).unwrap();
+        assert_eq!(rcfn(&conn, "Pongify", Some(did)).unwrap().tags, vec![String::from("removal")]);
+        assert!(rvatfd(&conn, did, &rules).unwrap().is_empty());
+    }
+
+    #[test]
+    fn bulk_operations() {
+        let (conn, did) = deck_with(&[
+            json_card("Opt", "Instant", "Scry 1.", &["U"]),
+            json_card("Ponder", "Sorcery", "Draw a card.", &["U"]),
+            json_card("Preordain", "Sorcery", "Scry 2.", &["U"]),
+        ]);
+        let vcn: Vec<String> = ["Opt", "Ponder", "Preordain"].iter().map(|s| s.to_string()).collect();
+
+        assert_eq!(ivctodc(&conn, &vcn, did).unwrap().len(), 3);
+        assert!(ivctodc(&conn, &vcn, did).unwrap().is_empty());
+
+        ttindc(&conn, "Opt", &String::from("cantrip"), did);
+        assert_eq!(utivcndc(&conn, &vcn, "cantrip", true, did).unwrap(), 2);
+        assert_eq!(rcfn(&conn, "Ponder", Some(did)).unwrap().tags, vec![String::from("cantrip")]);
+        assert_eq!(utivcndc(&conn, &vcn[..2], "cantrip", false, did).unwrap(), 2);
+        assert_eq!(rcfn(&conn, "Preordain", Some(did)).unwrap().tags, vec![String::from("cantrip")]);
+
+        let mut all = vcn.clone();
+        all.push(String::from("Talrand, Sky Summoner"));
+        assert_eq!(dvcntodc(&conn, &all, did).unwrap(), 3);
+        let left = rvcfdid(&conn, did, SortOrder::NameAsc).unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].name, "Talrand, Sky Summoner");
+    }
+
+    #[test]
+    fn undo_redo() {
+        let (conn, did) = deck_with(&[
+            json_card("Opt", "Instant", "Scry 1.", &["U"]),
+            json_card("Ponder", "Sorcery", "Draw a card.", &["U"]),
+        ]);
+        let vcn: Vec<String> = ["Opt", "Ponder"].iter().map(|s| s.to_string()).collect();
+        let in_deck = |cn: &str| rcfn(&conn, cn, Some(did)).unwrap().zone.is_some();
+
+        let before = rvcsfd(&conn, &vcn, did).unwrap();
+        ivctodc(&conn, &vcn, did).unwrap();
+        assert!(ihfd(&conn, before, did).unwrap());
+
+        let before = rvcsfd(&conn, &vcn[..1], did).unwrap();
+        ttindc(&conn, "Opt", &String::from("cantrip"), did);
+        ihfd(&conn, before, did).unwrap();
+        let before = rvcsfd(&conn, &vcn[..1], did).unwrap();
+        assert!(!ihfd(&conn, before, did).unwrap());
+
+        let before = rvcsfd(&conn, &vcn[..1], did).unwrap();
+        dvcntodc(&conn, &vcn[..1], did).unwrap();
+        ihfd(&conn, before, did).unwrap();
+        assert!(!in_deck("Opt"));
+
+        assert_eq!(uhfd(&conn, did, false).unwrap(), 1);
+        assert_eq!(rcfn(&conn, "Opt", Some(did)).unwrap().tags, vec![String::from("cantrip")]);
+        assert_eq!(uhfd(&conn, did, false).unwrap(), 1);
+        assert!(rcfn(&conn, "Opt", Some(did)).unwrap().tags.is_empty());
+        assert_eq!(uhfd(&conn, did, false).unwrap(), 2);
+        assert!(!in_deck("Opt") && !in_deck("Ponder"));
+        assert_eq!(uhfd(&conn, did, false).unwrap(), 0);
+
+        assert_eq!(uhfd(&conn, did, true).unwrap(), 2);
+        assert!(in_deck("Opt") && in_deck("Ponder"));
+        assert_eq!(uhfd(&conn, did, true).unwrap(), 1);
+        assert_eq!(rcfn(&conn, "Opt", Some(did)).unwrap().tags, vec![String::from("cantrip")]);
+
+        // A new edit drops whatever was left to redo.
+        let before = rvcsfd(&conn, &vcn[1..], did).unwrap();
+        uqindc(&conn, &rcfn(&conn, "Ponder", None).unwrap(), did, 2).unwrap();
+        ihfd(&conn, before, did).unwrap();
+        assert_eq!(uhfd(&conn, did, true).unwrap(), 0);
+    }
+
+    #[test]
+    fn deck_snapshots() {
+        let (conn, did) = deck_with(&[
+            json_card("Opt", "Instant", "Scry 1.", &["U"]),
+            json_card("Ponder", "Sorcery", "Draw a card.", &["U"]),
+            json_card("Preordain", "Sorcery", "Scry 2.", &["U"]),
+        ]);
+        let vcn: Vec<String> = ["Opt", "Ponder"].iter().map(|s| s.to_string()).collect();
+        ivctodc(&conn, &vcn, did).unwrap();
+        ttindc(&conn, "Opt", &String::from("cantrip"), did);
+        let sid = isfd(&conn, did, "first").unwrap();
+        assert!(rdiff(&conn, did, Some(sid), None).unwrap().is_empty());
+
+        dvcntodc(&conn, &vcn[1..], did).unwrap();
+        ivctodc(&conn, &[String::from("Preordain")], did).unwrap();
+        ttindc(&conn, "Opt", &String::from("cantrip"), did);
+        ttindc(&conn, "Opt", &String::from("draw"), did);
+        udn(&conn, did, "Keep it cheap.").unwrap();
+        let diff = rdiff(&conn, did, Some(sid), None).unwrap();
+        assert_eq!(diff.added, vec![String::from("Preordain")]);
+        assert_eq!(diff.removed, vec![String::from("Ponder")]);
+        assert_eq!(
+            diff.retagged,
+            vec![(String::from("Opt"), vec![String::from("cantrip")], vec![String::from("draw")])]
+        );
+        assert!(diff.notes && diff.commanders.is_none());
+        uqindc(&conn, &rcfn(&conn, "Opt", None).unwrap(), did, 3).unwrap();
+        let diff = rdiff(&conn, did, Some(sid), None).unwrap();
+        assert_eq!(diff.recounted, vec![(String::from("Opt"), 1, 3)]);
+        uqindc(&conn, &rcfn(&conn, "Opt", None).unwrap(), did, 1).unwrap();
+
+        let second = isfd(&conn, did, "second").unwrap();
+        let names: Vec<String> = rvsfd(&conn, did).unwrap().into_iter().map(|s| s.name).collect();
+        assert_eq!(names, vec![String::from("second"), String::from("first")]);
+        assert_eq!(rdiff(&conn, did, Some(second), Some(sid)).unwrap().added, vec![String::from("Ponder")]);
+
+        usfs(&conn, did, sid).unwrap();
+        assert!(rdiff(&conn, did, Some(sid), None).unwrap().is_empty());
+        assert_eq!(rcfn(&conn, "Opt", Some(did)).unwrap().tags, vec![String::from("cantrip")]);
+        assert!(rcfn(&conn, "Preordain", Some(did)).unwrap().zone.is_none());
+        assert!(rdfdid(&conn, did).unwrap().notes.is_empty());
+
+        // Restoring can be undone like any other change to the deck, notes included.
+        uhfd(&conn, did, false).unwrap();
+        assert!(rcfn(&conn, "Preordain", Some(did)).unwrap().zone.is_some());
+        assert_eq!(rdfdid(&conn, did).unwrap().notes, "Keep it cheap.");
+
+        dsfd(&conn, sid).unwrap();
+        assert_eq!(rvsfd(&conn, did).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn copy_deck() {
+        let (conn, did) = deck_with(&[
+            json_card("Krenko, Mob Boss", "Legendary Creature — Goblin Warrior", "", &["R"]),
+            json_card("Opt", "Instant", "Scry 1.", &["U"]),
+        ]);
+        ictodc(&conn, &rcfn(&conn, "Opt", None).unwrap(), did).unwrap();
+        ttindc(&conn, "Opt", &String::from("cantrip"), did);
+        udn(&conn, did, "Spells matter.").unwrap();
+
+        let (copy, outside) = idfd(&conn, did, "variant", None).unwrap();
+        assert!(outside.is_empty());
+        let deck = rdfdid(&conn, copy).unwrap();
+        assert_eq!(deck.name, "variant");
+        assert_eq!(deck.commander.name, "Talrand, Sky Summoner");
+        assert_eq!(deck.notes, "Spells matter.");
+        assert_eq!(rcfn(&conn, "Opt", Some(copy)).unwrap().tags, vec![String::from("cantrip")]);
+
+        // The copy is its own deck.
+        ttindc(&conn, "Opt", &String::from("cantrip"), copy);
+        assert_eq!(rcfn(&conn, "Opt", Some(did)).unwrap().tags, vec![String::from("cantrip")]);
+
+        assert!(matches!(idfd(&conn, did, "bad", Some(("Opt", None))), Err(Error::InvalidQuery)));
+        assert!(matches!(idfd(&conn, did, "bad", Some(("Nobody", None))), Err(Error::InvalidQuery)));
+        assert!(idfd(&conn, did + 10, "bad", None).is_err());
+        assert_eq!(rvd(&conn).unwrap().len(), 2);
+        let (fork, outside) = idfd(&conn, did, "goblins", Some(("Krenko, Mob Boss", None))).unwrap();
+        assert_eq!(outside, vec![String::from("Opt")]);
+        assert_eq!(rdfdid(&conn, fork).unwrap().commander.name, "Krenko, Mob Boss");
+        assert!(rcfn(&conn, "Talrand, Sky Summoner", Some(fork)).unwrap().zone.is_none());
+        assert_eq!(rdfdid(&conn, did).unwrap().commander.name, "Talrand, Sky Summoner");
+    }
+
+    #[test]
+    fn deck_metadata() {
+        let (conn, did) = deck_with(&[
+            json_card("Opt", "Instant", "Scry 1.", &["U"]),
+            json_card("Ponder", "Sorcery", "Draw a card.", &["U"]),
+        ]);
+        let opt = rcfn(&conn, "Opt", None).unwrap();
+        ictodc(&conn, &opt, did).unwrap();
+        uqindc(&conn, &opt, did, 3).unwrap();
+        let ponder = rcfn(&conn, "Ponder", None).unwrap();
+        ictodc(&conn, &ponder, did).unwrap();
+        uzindc(&conn, &ponder, did, Zone::Maybeboard).unwrap();
+        conn.execute("UPDATE cards SET price = 0.5 WHERE name = 'Opt'", []).unwrap();
+
+        let totals = rvdt(&conn).unwrap()[&did];
+        assert_eq!(totals, DeckTotals { cards: 4, price: 1.5 });
+
+        let labels = vec![String::from("cedh"), String::from("to build")];
+        udl(&conn, did, &labels).unwrap();
+        assert_eq!(rdfdid(&conn, did).unwrap().labels, labels);
+        udl(&conn, did, &[]).unwrap();
+        assert!(rdfdid(&conn, did).unwrap().labels.is_empty());
+
+        // Changing the deck's cards or notes marks it as modified.
+        assert!(!rdfdid(&conn, did).unwrap().modified.is_empty());
+        conn.execute("UPDATE decks SET modified = '2000-01-01 00:00:00'", []).unwrap();
+        dcntodc(&conn, "Ponder", did).unwrap();
+        assert_ne!(rdfdid(&conn, did).unwrap().modified, "2000-01-01 00:00:00");
+        conn.execute("UPDATE decks SET modified = '2000-01-01 00:00:00'", []).unwrap();
+        udn(&conn, did, "Notes.").unwrap();
+        assert_ne!(rdfdid(&conn, did).unwrap().modified, "2000-01-01 00:00:00");
+
+        // Refreshing a cached price doesn't.
+        conn.execute("UPDATE decks SET modified = '2000-01-01 00:00:00'", []).unwrap();
+        conn.execute("UPDATE deck_contents SET printing_price = 1.5", []).unwrap();
+        assert_eq!(rdfdid(&conn, did).unwrap().modified, "2000-01-01 00:00:00");
+
+        // Decks from before labels get a modified date when the columns are added.
+        conn.execute_batch(
+            "DROP TRIGGER deck_contents_inserted;
+            DROP TRIGGER deck_contents_updated;
+            DROP TRIGGER deck_contents_deleted;
+            DROP TRIGGER decks_updated;
+            ALTER TABLE decks DROP COLUMN labels;
+            ALTER TABLE decks DROP COLUMN modified;",
+        )
+        .unwrap();
+        migratedb(&conn).unwrap();
+        assert!(!rdfdid(&conn, did).unwrap().modified.is_empty());
+    }
+
+    #[test]
+    fn archive_and_trash() {
+        let (conn, did) = deck_with(&[json_card("Opt", "Instant", "Scry 1.", &["U"])]);
+        ictodc(&conn, &rcfn(&conn, "Opt", None).unwrap(), did).unwrap();
+
+        uda(&conn, did, true).unwrap();
+        assert!(rdfdid(&conn, did).unwrap().archived);
+        uda(&conn, did, false).unwrap();
+        assert!(!rdfdid(&conn, did).unwrap().archived);
+
+        udt(&conn, did, true).unwrap();
+        assert!(rdfdid(&conn, did).unwrap().deleted.is_some());
+        assert!(dvdt(&conn, 30).unwrap().is_empty());
+        udt(&conn, did, false).unwrap();
+        assert!(rdfdid(&conn, did).unwrap().deleted.is_none());
+
+        // Only decks trashed longer ago than the retention period are deleted.
+        udt(&conn, did, true).unwrap();
+        conn.execute("UPDATE decks SET deleted = datetime('now', 'localtime', '-31 days')", []).unwrap();
+        assert_eq!(dvdt(&conn, 30).unwrap(), vec![did]);
+        assert!(rdfdid(&conn, did).is_err());
+        let left: u32 = conn
+            .query_row("SELECT COUNT(*) FROM deck_contents WHERE deck = ?", [did], |row| row.get(0))
+            .unwrap();
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn card_uses() {
+        let (conn, spells) = deck_with(&[json_card("Opt", "Instant", "Scry 1.", &["U"])]);
+        let opt = rcfn(&conn, "Opt", None).unwrap();
+        ictodc(&conn, &opt, spells).unwrap();
+        ttindc(&conn, "Opt", &String::from("cantrip"), spells);
+        let archived = ideck(&conn, "archived", "Talrand, Sky Summoner", None, "Commander").unwrap();
+        ictodc(&conn, &opt, archived).unwrap();
+        uzindc(&conn, &opt, archived, Zone::Sideboard).unwrap();
+        uda(&conn, archived, true).unwrap();
+        let trashed = ideck(&conn, "trashed", "Talrand, Sky Summoner", None, "Commander").unwrap();
+        ictodc(&conn, &opt, trashed).unwrap();
+        udt(&conn, trashed, true).unwrap();
+
+        let uses = rvufcn(&conn, "Opt").unwrap();
+        assert_eq!(
+            uses,
+            vec![
+                CardUse {
+                    did: archived,
+                    deck: String::from("archived"),
+                    archived: true,
+                    zone: Zone::Sideboard,
+                    quantity: 1,
+                    tags: Vec::new(),
+                },
+                CardUse {
+                    did: spells,
+                    deck: String::from("test"),
+                    archived: false,
+                    zone: Zone::Main,
+                    quantity: 1,
+                    tags: vec![String::from("cantrip")],
+                },
+            ]
+        );
+        assert_eq!(rvufcn(&conn, "Talrand, Sky Summoner").unwrap().len(), 2);
+        assert!(rvufcn(&conn, "Ponder").unwrap().is_empty());
+    }
 }
\ No newline at end of file
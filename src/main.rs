@@ -7,9 +7,9 @@ mod util;
 use chrono::Datelike;
 use crate::db::CardFilter;
 use crate::network::rvjc;
-use crate::util::{get_local_file, Settings, FileSettings};
+use crate::util::{get_local_file, Settings, FileSettings, Zone};
 
-use std::{fs::File, path::PathBuf, io::{BufReader, BufRead}};
+use std::{fs::File, path::PathBuf, io::{BufReader, BufRead, IsTerminal, Write}};
 use rusqlite::Connection;
 use clap::{App, arg};
 use anyhow::Result;
@@ -21,8 +21,11 @@ pub enum Command {
     RetrieveCard(String),
     Update,
     Draw,
-    ImportDeck(String, Vec<String>, PathBuf),
+    ImportDeck(String, Vec<String>, PathBuf, bool),
     ExportDeck(i32, Option<PathBuf>),
+    ValidateDeck(String),
+    CopyDeck(String, String, Option<String>),
+    WhereIs(String),
 }
 
 pub fn run(command: Command) -> Result<()> {
@@ -49,6 +52,7 @@ pub fn run(command: Command) -> Result<()> {
                 let sets = network::rvs().unwrap();
                 let p = get_local_file("lieutenant.db", true);
                 let conn = Connection::open(p).unwrap();
+                db::migratedb(&conn).unwrap();
                 let n = db::updatedb(&conn, sets).unwrap();
                 println!("Imported {} cards in {} ms.", n, now.elapsed().as_millis());
             }
@@ -57,15 +61,17 @@ pub fn run(command: Command) -> Result<()> {
         Command::Draw => { 
             let _a = ui::run();
         },
-        Command::ImportDeck(deck_name, commanders, filename) => {
+        Command::ImportDeck(deck_name, commanders, filename, autotag_yes) => {
             let p = get_local_file("lieutenant.db", true);
             let conn = Connection::open(p).unwrap();
+            db::migratedb(&conn).unwrap();
             let p = get_local_file("settings.toml", true);
             let file_settings = FileSettings::new(&p).unwrap();
             let mut settings = Settings::from(file_settings);
 
             let mut cards = Vec::new();
             let mut tags = Vec::new();
+            let mut notes = Vec::new();
 
             if let Some(ext) = filename.extension() {
                 match ext.to_str().unwrap() {
@@ -73,12 +79,25 @@ pub fn run(command: Command) -> Result<()> {
                         let file =  File::open(&filename).unwrap();
                         let buf = BufReader::new(file);
                         for a in buf.lines() {
-                            let ic = db::ImportCard { name: a.unwrap(), tags: None };
+                            let a = a.unwrap();
+                            // Exported notes are written as comment lines.
+                            if let Some(note) = a.strip_prefix('#') {
+                                notes.push(note.strip_prefix(' ').unwrap_or(note).to_string());
+                                continue;
+                            }
+                            let ic = db::ImportCard { name: a, tags: None, ..Default::default() };
                             cards.push(ic);
                         }
                     }
                     "csv" => {
-                        let mut rdr = csv::ReaderBuilder::new().flexible(true).from_path(&filename)?;
+                        let file =  File::open(&filename).unwrap();
+                        let buf = BufReader::new(file);
+                        for a in buf.lines() {
+                            if let Some(note) = a.unwrap().strip_prefix('#') {
+                                notes.push(note.strip_prefix(' ').unwrap_or(note).to_string());
+                            }
+                        }
+                        let mut rdr = csv::ReaderBuilder::new().flexible(true).comment(Some(b'#')).from_path(&filename)?;
                         for result in rdr.records() {
                             let record = result?;
                             match record.deserialize::<db::ImportCard>(None) {
@@ -89,9 +108,9 @@ pub fn run(command: Command) -> Result<()> {
                                         csv::ErrorKind::Deserialize { pos: _ , err } => {
                                             if let csv::DeserializeErrorKind::Message(s) = err.kind() {
                                                 if err.field() == None 
-                                                && s == &String::from("invalid length 1, expected struct ImportCard with 2 elements") {
+                                                && s == &String::from("invalid length 1, expected struct ImportCard with 7 elements") {
                                                     // println!("Problem with the card: {:?}", record.get(0).unwrap());
-                                                    let ic = db::ImportCard { name: String::from(record.get(0).unwrap()), tags: None };
+                                                    let ic = db::ImportCard { name: String::from(record.get(0).unwrap()), tags: None, ..Default::default() };
                                                     cards.push(ic);
 
                                                 }
@@ -106,7 +125,8 @@ pub fn run(command: Command) -> Result<()> {
                             if let Some(s) = &ic.tags {
                                 let vs = s.split('|');
                                 for tag in vs {
-                                    if !tags.contains(&String::from(tag)) && !tag.is_empty() {
+                                    // The main and side tags of older exports become zones.
+                                    if !tags.contains(&String::from(tag)) && !tag.is_empty() && tag != "main" && tag != "side" {
                                         tags.push(String::from(tag));
                                     }
                                 }
@@ -119,14 +139,46 @@ pub fn run(command: Command) -> Result<()> {
                 }
                 
                 let did = db::import_deck(&conn, deck_name, commanders, cards)?;
+                db::udn(&conn, did, &notes.join("\n"))?;
                 settings.id(did);
                 for tag in tags { settings.it(Some(did), tag); }
+
+                db::add_regexp_function(&conn)?;
+                let autotag = settings.autotag();
+                let vct = db::rvatfd(&conn, did, &autotag.rules)?;
+                for rule in autotag.rules.iter().filter(|r| !db::vomni(&r.query)) {
+                    println!("Skipped the {} auto-tag rule; its query doesn't parse: {}", rule.tag, rule.query);
+                }
+                if !vct.is_empty() {
+                    println!("The auto-tag rules would add these tags:");
+                    for (cn, tag) in &vct {
+                        println!("\t{}: {}", tag, cn);
+                    }
+                    // Without --yes, only ask when someone is there to answer.
+                    let add = if autotag_yes {
+                        true
+                    } else if std::io::stdin().is_terminal() {
+                        print!("Add them? [y/N] ");
+                        std::io::stdout().flush()?;
+                        let mut answer = String::new();
+                        std::io::stdin().read_line(&mut answer)?;
+                        answer.trim().eq_ignore_ascii_case("y")
+                    } else {
+                        println!("Left them off; import with --yes to add them.");
+                        false
+                    };
+                    if add {
+                        db::uatindc(&conn, did, &vct)?;
+                        for (_, tag) in vct { settings.rds(did).borrow_mut().add_tag(tag); }
+                    }
+                }
                 std::fs::write(p, settings.to_toml()).unwrap();
             };
         }
         Command::ExportDeck(did, path) => {
             let p = get_local_file("lieutenant.db", false);
             let conn = Connection::open(p).unwrap();
+            db::migratedb(&conn).unwrap();
             let deck = db::rdfdid(&conn, did).expect("Deck could not be retrieved. Ensure Deck ID is correct.");
             let mut cards = db::rvicfdid(&conn, did).unwrap();
             let c = deck.commander;
@@ -147,14 +199,103 @@ pub fn run(command: Command) -> Result<()> {
                 }
             };
 
-            let mut wtr = csv::Writer::from_path(p).unwrap();
-            wtr.write_record(&["Card Name","Tags"]).unwrap();
+            let mut file = File::create(&p).unwrap();
+            for line in deck.notes.lines() {
+                writeln!(file, "# {}", line).unwrap();
+            }
+
+            if p.extension().and_then(|ext| ext.to_str()) == Some("txt") {
+                for card in cards.into_iter().filter(|ic| ic.zone.as_deref().map(Zone::from).is_none_or(|z| z.in_deck())) {
+                    match card.quantity {
+                        Some(q) if q > 1 => { writeln!(file, "{} {}", q, card.name).unwrap(); }
+                        _ => { writeln!(file, "{}", card.name).unwrap(); }
+                    }
+                }
+                return Ok(());
+            }
+
+            let mut wtr = csv::Writer::from_writer(file);
+            wtr.write_record(&["Card Name","Tags","Set","Number","Foil","Quantity","Zone"]).unwrap();
             for card in cards {
                 let tags = match card.tags { Some(s) => {s} None => {String::new()}};
-                wtr.write_record(&[card.name, tags]).unwrap();
+                let foil = card.foil.map(|f| f.to_string()).unwrap_or_default();
+                let quantity = card.quantity.unwrap_or(1).to_string();
+                let zone = card.zone.unwrap_or_else(|| String::from("main"));
+                wtr.write_record(&[card.name, tags, card.set_code.unwrap_or_default(), card.number.unwrap_or_default(), foil, quantity, zone]).unwrap();
 
             }
         }
+        Command::CopyDeck(deck, name, coms) => {
+            let p = get_local_file("lieutenant.db", false);
+            let conn = Connection::open(p).unwrap();
+            db::migratedb(&conn).unwrap();
+            db::add_regexp_function(&conn).unwrap();
+            let did = match deck.parse::<i32>() {
+                Ok(did) => did,
+                Err(_) => match db::rvd(&conn)?.into_iter().find(|d| d.name == deck) {
+                    Some(d) => d.id,
+                    None => { println!("No deck named {} was found.", deck); return Ok(()); }
+                }
+            };
+            let vcn: Vec<&str> = coms.iter().flat_map(|s| s.split('|')).map(|s| s.trim()).collect();
+            let pair = vcn.first().map(|c1| (*c1, vcn.get(1).copied()));
+            match db::idfd(&conn, did, &name, pair) {
+                Ok((new, outside)) => {
+                    let p = get_local_file("settings.toml", true);
+                    let mut settings = Settings::from(FileSettings::new(&p).unwrap());
+                    settings.copy_deck(did, new);
+                    std::fs::write(p, settings.to_toml()).unwrap();
+                    println!("Copied the deck as {} (ID {}).", name, new);
+                    if !outside.is_empty() {
+                        println!("These cards are outside the new color identity:");
+                        for cn in outside { println!("  {}", cn); }
+                    }
+                }
+                Err(rusqlite::Error::InvalidQuery) if coms.is_some() => {
+                    println!("Could not make {} the commander(s) of the copy.", coms.unwrap_or_default().replace('|', " and "));
+                }
+                Err(e) => println!("Could not copy the deck: {}", e),
+            }
+        }
+        Command::WhereIs(card) => {
+            let p = get_local_file("lieutenant.db", false);
+            let conn = Connection::open(p).unwrap();
+            db::migratedb(&conn).unwrap();
+            let uses = db::rvufcn(&conn, &card)?;
+            if uses.is_empty() {
+                println!("{} isn't in any deck.", card);
+            } else {
+                println!("{} is in {} deck(s):", card, uses.len());
+                for u in uses {
+                    let archived = if u.archived { " [archived]" } else { "" };
+                    let copies = if u.quantity > 1 { format!(", {} copies", u.quantity) } else { String::new() };
+                    let tags = if u.tags.is_empty() { String::new() } else { format!(": {}", u.tags.join(", ")) };
+                    println!("  {} (ID {}){} ({}{}){}", u.deck, u.did, archived, u.zone.as_str(), copies, tags);
+                }
+            }
+        }
+        Command::ValidateDeck(deck) => {
+            let p = get_local_file("lieutenant.db", false);
+            let conn = Connection::open(p).unwrap();
+            db::migratedb(&conn).unwrap();
+            db::add_regexp_function(&conn).unwrap();
+            // Decks can be given by id or by name.
+            let did = match deck.parse::<i32>() {
+                Ok(did) => did,
+                Err(_) => match db::rvd(&conn)?.into_iter().find(|d| d.name == deck) {
+                    Some(d) => d.id,
+                    None => { println!("No deck named {} was found.", deck); return Ok(()); }
+                }
+            };
+            let deck = db::rdfdid(&conn, did).expect("Deck could not be retrieved. Ensure Deck ID is correct.");
+            let violations = db::vdeck(&conn, did)?;
+            if violations.is_empty() {
+                println!("{} is a legal {} deck.", deck.name, deck.deck_type);
+            } else {
+                println!("{} has {} problem(s) as a {} deck:", deck.name, violations.len(), deck.deck_type);
+                for v in violations { println!("  {}", v); }
+            }
+        }
     }
 
     Ok(())
@@ -173,6 +314,7 @@ fn main() {
                 arg!(<deckname> "Desired name of the deck"), //TODO: make this optional
                 arg!(<filename> "Source file to import"),
                 arg!([commander] "Name of commander (if not first row of the deck)"),
+                arg!(-y --yes "Adds the tags from the auto-tag rules without asking."),
             ])
         ).subcommand(
             App::new("update")
@@ -181,9 +323,25 @@ fn main() {
             App::new("debug")
             .about("For testing various features as developed.")
             .arg(arg!(<module> "Specific part of the program to be tested."))
+        ).subcommand(
+            App::new("validate")
+            .about("Checks a deck against the rules of its format and lists every problem found.")
+            .arg(arg!(<deck> "ID or name of the deck to validate."))
+        ).subcommand(
+            App::new("whereis")
+            .about("Lists every deck that contains a card, with the card's tags in each.")
+            .arg(arg!(<card> "Exact name of the card."))
+        ).subcommand(
+            App::new("copy")
+            .about("Copies a deck with its cards, tags and settings, optionally under new commanders.")
+            .args(&[
+                arg!(<deck> "ID or name of the deck to copy."),
+                arg!(<name> "Name of the copy."),
+                arg!([commander] "New commander(s) for the copy, separated by |. Optional."),
+            ])
         ).subcommand(
             App::new("export")
-            .about("Exports a deck from a given deck id. If no output file is given, the csv will be generated in the same directory as the executable.")
+            .about("Exports a deck from a given deck id. If no output file is given, the csv will be generated in the same directory as the executable. Files ending in .txt are written as a plain card list. Deck notes are included as lines starting with #.")
             .args(&[
                 arg!(<deck_id> "ID of the deck to export. Can be seen in the Open Deck screen."),
                 arg!([file] "Output file. Optional."),
@@ -205,13 +363,26 @@ fn main() {
             let _a = run(Command::ImportDeck(
                     sub_m.value_of("deckname").unwrap().to_string(),
                     commanders,
-                    PathBuf::from(sub_m.value_of("filename").unwrap())));
+                    PathBuf::from(sub_m.value_of("filename").unwrap()),
+                    sub_m.is_present("yes")));
         }
         Some(("export", sub_m)) => {
             let did: i32 = sub_m.value_of("deck_id").unwrap().parse().unwrap();
             let p = sub_m.value_of("file").map(PathBuf::from);
             run(Command::ExportDeck(did, p)).unwrap();
         }
+        Some(("copy", sub_m)) => {
+            run(Command::CopyDeck(
+                sub_m.value_of("deck").unwrap().to_string(),
+                sub_m.value_of("name").unwrap().to_string(),
+                sub_m.value_of("commander").map(String::from))).unwrap();
+        }
+        Some(("whereis", sub_m)) => {
+            run(Command::WhereIs(sub_m.value_of("card").unwrap().to_string())).unwrap();
+        }
+        Some(("validate", sub_m)) => {
+            run(Command::ValidateDeck(sub_m.value_of("deck").unwrap().to_string())).unwrap();
+        }
         Some(("update", _sub_m)) => {
             println!("Updating the application and database.");
             run(Command::Update).unwrap();
@@ -250,7 +421,7 @@ fn debug_parse_args() -> Result<()> {
     }
 
     let deck = db::rdfdid(&conn, 3).unwrap();
-    let cf = CardFilter::from(deck.id, &deck.color, util::DefaultFilter::Name, util::SortOrder::NameAsc);
+    let cf = CardFilter::from(deck.id, &deck.color, deck.deck_type, util::DefaultFilter::Name, util::SortOrder::NameAsc);
 
     let s = String::from("na:elesh|norn");
     println!("For \"{}\", Cardfilter produces: \n{}", &s, cf.make_query(false, &s));
@@ -1,5 +1,5 @@
 use reqwest::blocking::get;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use serde_json::Value;
 use crate::db::{JsonCard, Set};
 
@@ -35,19 +35,34 @@ pub fn rvs() -> Result<Vec<Set>> {
 pub fn rvjc(set_code: &str) -> Result<Vec<JsonCard>> {
     let mut vjc = Vec::new();
     let url = format!("https://mtgjson.com/api/v5/{}.json", set_code);
-    let res: serde_json::Value = get(&url)?.json().unwrap();
+    let res: serde_json::Value = get(&url)?.error_for_status()?.json()?;
     let cards = match &res["data"]["cards"] {
         serde_json::Value::Array(i) => { i }
-        _ => { panic!(); }
+        _ => { return Err(anyhow!("No card list found in the data for set {}.", set_code)); }
     };
     
     for value in cards {
-        let d: JsonCard = serde_json::from_value(value.clone()).unwrap();
+        let d: JsonCard = serde_json::from_value(value.clone())?;
         vjc.push(d);
     }
     Ok(vjc)
 }
 
+pub fn rvjc_retry(set_code: &str, attempts: u32) -> Result<Vec<JsonCard>> {
+    let mut attempt = 1;
+    loop {
+        match rvjc(set_code) {
+            Ok(vjc) => return Ok(vjc),
+            Err(e) if attempt < attempts => {
+                println!("Could not retrieve {} (attempt {} of {}): {}. Retrying...", set_code, attempt, attempts, e);
+                std::thread::sleep(std::time::Duration::from_secs(2 * attempt as u64));
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 pub fn rcostfcn(cn: &str, prev: Option<f64>) -> Result<f64> {
     let api = format!("https://api.scryfall.com/cards/search?q=name=%22{}%22", cn);
     let res_json: Value = get(api).unwrap().json().unwrap();
@@ -85,6 +100,19 @@ pub fn rcostfcn(cn: &str, prev: Option<f64>) -> Result<f64> {
     Ok(price)
 }
 
+pub fn rcostfpr(set_code: &str, number: &str, foil: bool) -> Result<f64> {
+    let api = format!("https://api.scryfall.com/cards/{}/{}", set_code.to_lowercase(), number);
+    let res_json: Value = get(api)?.json()?;
+    let (first, second) = if foil { ("usd_foil", "usd") } else { ("usd", "usd_foil") };
+    for key in [first, second, "usd_etched"] {
+        if let Value::String(s) = &res_json["prices"][key] {
+            return Ok(s.parse()?);
+        }
+    }
+
+    Err(anyhow!("No price found for {} #{}.", set_code, number))
+}
+
 pub fn rextcostfcn(cn: &str) -> Result<f64> {
     let api = format!("https://api.scryfall.com/cards/search?q=name=%22{}%22", cn);
     let res_json: Value = get(api).unwrap().json().unwrap();
@@ -118,3 +146,4 @@ pub fn rextcostfcn(cn: &str) -> Result<f64> {
 
     Ok(*median)
 }
+
@@ -9,7 +9,7 @@ use crate::util::views::*;
 use crate::util::*;
 use anyhow::Result;
 use crossterm::{
-    event::{poll, read, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent},
+    event::{poll, read, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
@@ -25,6 +25,9 @@ struct AppState {
     deck_view: Option<DeckView>,
     settings_view: Option<SettingsView>,
     deck_stat_view: Option<DeckStatView>,
+    notes_view: Option<NotesView>,
+    tag_manager_view: Option<TagManagerView>,
+    snapshot_view: Option<SnapshotView>,
     create_deck_view: CreateDeckView,
     open_deck_view: OpenDeckView,
     slmm: StatefulList<MainMenuItem>,
@@ -37,19 +40,27 @@ impl AppState {
     fn new() -> AppState {
         let p = get_local_file("settings.toml", true);
         let file_settings = FileSettings::new(&p).unwrap();
-        let settings = Settings::from(file_settings);
+        let mut settings = Settings::from(file_settings);
         let p = get_local_file("lieutenant.db", true);
         let conn = Connection::open(p).unwrap();
 
+        migratedb(&conn).unwrap();
         add_regexp_function(&conn).unwrap();
+        for did in dvdt(&conn, settings.trash_days()).unwrap_or_default() {
+            settings.dd(did);
+        }
+        let templates = settings.template_names();
         let mut app = AppState {
             mode: Screen::MainMenu,
             mode_p: Screen::MainMenu,
             deck_view: None,
             settings_view: None,
             deck_stat_view: None,
-            create_deck_view: CreateDeckView::new(),
-            open_deck_view: OpenDeckView::new(),
+            notes_view: None,
+            tag_manager_view: None,
+            snapshot_view: None,
+            create_deck_view: CreateDeckView::new(templates),
+            open_deck_view: OpenDeckView::new(settings.trash_days()),
             slmm: StatefulList::new(),
             dbc: Arc::new(Mutex::new(conn)),
             settings,
@@ -65,6 +76,26 @@ impl AppState {
         app
     }
 
+    // Ctrl-Z and Ctrl-Y undo and redo deck edits; other keys act as if Ctrl wasn't held.
+    fn handle_control(&mut self, c: KeyCode) -> Result<()> {
+        match (self.mode, c, self.deck_view.as_mut()) {
+            (Screen::DeckView, KeyCode::Char('z'), Some(dv)) => dv.undo(false),
+            (Screen::DeckView, KeyCode::Char('y'), Some(dv)) => dv.undo(true),
+            _ => return self.handle_input(c),
+        }
+        Ok(())
+    }
+
+    // Shift-Up and Shift-Down mark cards in the deck view; other keys act as if Shift wasn't held.
+    fn handle_shift(&mut self, c: KeyCode) -> Result<()> {
+        match (self.mode, c, self.deck_view.as_mut()) {
+            (Screen::DeckView, KeyCode::Up, Some(dv)) => dv.shift_mark(true),
+            (Screen::DeckView, KeyCode::Down, Some(dv)) => dv.shift_mark(false),
+            _ => return self.handle_input(c),
+        }
+        Ok(())
+    }
+
     fn handle_input(&mut self, c: KeyCode) -> Result<()> {
         match self.mode {
             Screen::MainMenu => match c {
@@ -90,10 +121,59 @@ impl AppState {
                     OpenDeckViewExit::OpenDeck(did) => {
                         self.init_deck_view(did);
                     }
+                    OpenDeckViewExit::TrashDeck(did) => {
+                        let conn = self.dbc.lock().unwrap();
+                        udt(&conn, did, true)?;
+                        if self.settings.rr() == did {
+                            self.settings.sr(-1);
+                        }
+                        self.open_deck_view.init(&conn);
+                    }
+                    OpenDeckViewExit::RestoreDeck(did) => {
+                        let conn = self.dbc.lock().unwrap();
+                        udt(&conn, did, false)?;
+                        self.open_deck_view.init(&conn);
+                    }
+                    OpenDeckViewExit::ArchiveDeck(did, archived) => {
+                        let conn = self.dbc.lock().unwrap();
+                        uda(&conn, did, archived)?;
+                        self.open_deck_view.init(&conn);
+                    }
                     OpenDeckViewExit::DeleteDeck(did) => {
                         dd(&self.dbc.lock().unwrap(), did).unwrap();
                         self.settings.dd(did);
                     }
+                    OpenDeckViewExit::Labels(did, labels) => {
+                        let conn = self.dbc.lock().unwrap();
+                        udl(&conn, did, &labels)?;
+                        self.open_deck_view.init(&conn);
+                    }
+                    OpenDeckViewExit::CopyDeck(did, name, coms) => {
+                        let conn = self.dbc.lock().unwrap();
+                        let vcn: Vec<&str> = coms.iter().flat_map(|s| s.split('|')).map(|s| s.trim()).collect();
+                        let pair = vcn.first().map(|c1| (*c1, vcn.get(1).copied()));
+                        let message = match idfd(&conn, did, &name, pair) {
+                            Ok((new, outside)) => {
+                                self.settings.copy_deck(did, new);
+                                self.open_deck_view.init(&conn);
+                                if outside.is_empty() {
+                                    format!("Copied the deck as {}.", name)
+                                } else {
+                                    format!(
+                                        "Copied the deck as {}. These cards are outside the new color identity:\n{}",
+                                        name,
+                                        outside.join("\n")
+                                    )
+                                }
+                            }
+                            Err(rusqlite::Error::InvalidQuery) if coms.is_some() => format!(
+                                "Could not make {} the commander(s) of the copy.",
+                                coms.unwrap_or_default().replace('|', " and ")
+                            ),
+                            Err(e) => format!("Could not copy the deck: {}", e),
+                        };
+                        self.open_deck_view.smessage(message);
+                    }
                 }
             }
             Screen::MakeDeck => {
@@ -102,13 +182,17 @@ impl AppState {
                     .handle_input(c, &self.dbc.lock().unwrap());
                 match res {
                     ViewExit::NewDeck(did) => {
-                        self.settings.id(did);
-                        self.create_deck_view = CreateDeckView::new();
+                        match self.create_deck_view.template() {
+                            Some(name) => self.settings.id_template(did, &name),
+                            None => self.settings.id(did),
+                        }
+                        self.create_deck_view = CreateDeckView::new(self.settings.template_names());
                         self.init_deck_view(did);
                     }
                     ViewExit::Cancel => {
                         self.mode = {
-                            self.create_deck_view = CreateDeckView::new();
+                            self.create_deck_view =
+                                CreateDeckView::new(self.settings.template_names());
                             Screen::MainMenu
                         }
                     }
@@ -118,6 +202,67 @@ impl AppState {
             Screen::DeckStat => {
                 self.mode = Screen::DeckView;
             }
+            Screen::Notes => {
+                if let Some(nv) = &mut self.notes_view {
+                    if let ViewExit::Cancel = nv.handle_input(c, &self.dbc.lock().unwrap()) {
+                        self.notes_view = None;
+                        self.mode = Screen::DeckView;
+                    }
+                }
+            }
+            Screen::TagManager => {
+                let conn = self.dbc.lock().unwrap();
+                if let Some(tmv) = &mut self.tag_manager_view {
+                    match tmv.handle_input(c, &conn) {
+                        TagManagerExit::Hold => {}
+                        TagManagerExit::Cancel => {
+                            self.tag_manager_view = None;
+                            self.mode = Screen::MainMenu;
+                        }
+                        TagManagerExit::Apply(old, new) => {
+                            let (cards, decks) = utiadc(&conn, &old, new.as_deref())?;
+                            self.settings.retag(&old, new.as_deref());
+                            if let Some(dv) = &mut self.deck_view {
+                                dv.uct(Vec::new());
+                            }
+                            let message = match &new {
+                                Some(new) => format!(
+                                    "Moved {} to {} in {} card(s) across {} deck(s).",
+                                    old, new, cards, decks
+                                ),
+                                None => format!(
+                                    "Deleted {} from {} card(s) across {} deck(s).",
+                                    old, cards, decks
+                                ),
+                            };
+                            self.tag_manager_view =
+                                Some(TagManagerView::new(&conn, self.settings.all_tags(), message));
+                        }
+                    }
+                }
+            }
+            Screen::Snapshots => {
+                let res = match &mut self.snapshot_view {
+                    Some(sv) => sv.handle_input(c, &self.dbc.lock().unwrap()),
+                    None => SnapshotExit::Cancel,
+                };
+                match res {
+                    SnapshotExit::Hold => {}
+                    SnapshotExit::Cancel => {
+                        self.snapshot_view = None;
+                        self.mode = Screen::DeckView;
+                    }
+                    SnapshotExit::Restored(name) => {
+                        self.snapshot_view = None;
+                        let did = self.deck_view.as_ref().unwrap().rdid();
+                        self.init_deck_view(did);
+                        self.deck_view.as_mut().unwrap().spopup(format!(
+                            "Restored {}. Press Ctrl-Z to undo the change to the deck's cards.",
+                            name
+                        ));
+                    }
+                }
+            }
             Screen::Settings => {
                 if let Some(sv) = &mut self.settings_view {
                     match sv.handle_input(c) {
@@ -153,9 +298,11 @@ impl AppState {
                     DeckViewExit::Hold => {}
                     DeckViewExit::MainMenu => self.mode = Screen::MainMenu,
                     DeckViewExit::Stats => {
+                        let did = self.deck_view.as_ref().unwrap().rdid();
                         self.deck_stat_view = Some(DeckStatView::new(
                             self.dbc.clone(),
-                            self.deck_view.as_ref().unwrap().rdid(),
+                            did,
+                            self.settings.rds(did).borrow().targets(),
                         ));
                         self.mode = Screen::DeckStat
                     }
@@ -164,6 +311,39 @@ impl AppState {
                         self.init_settings(Some(did));
                     }
                     DeckViewExit::NewTag(s, did) => self.settings.it(Some(did), s),
+                    DeckViewExit::Template(name, did) => {
+                        let dv = self.deck_view.as_mut().unwrap();
+                        if self.settings.apply_template(did, &name) {
+                            dv.uct(Vec::new());
+                        } else {
+                            let names = self.settings.template_names();
+                            dv.spopup(match names.is_empty() {
+                                true => format!("There is no template named {}.", name),
+                                false => format!(
+                                    "There is no template named {}. Try one of: {}",
+                                    name,
+                                    names.join(", ")
+                                ),
+                            });
+                        }
+                    }
+                    DeckViewExit::SaveTemplate(name, did) => {
+                        self.settings.save_template(did, &name);
+                        self.create_deck_view = CreateDeckView::new(self.settings.template_names());
+                        self.deck_view
+                            .as_mut()
+                            .unwrap()
+                            .spopup(format!("Saved this deck's tags as the {} template.", name));
+                    }
+                    DeckViewExit::Notes(did) => {
+                        let d = rdfdid(&self.dbc.lock().unwrap(), did).unwrap();
+                        self.notes_view = Some(NotesView::new(&d));
+                        self.mode = Screen::Notes;
+                    }
+                    DeckViewExit::Snapshots(did) => {
+                        self.snapshot_view = Some(SnapshotView::new(&self.dbc.lock().unwrap(), did));
+                        self.mode = Screen::Snapshots;
+                    }
                 }
             }
         }
@@ -182,6 +362,14 @@ impl AppState {
             Some(Screen::Settings) => {
                 self.init_settings(None);
             }
+            Some(Screen::TagManager) => {
+                self.tag_manager_view = Some(TagManagerView::new(
+                    &self.dbc.lock().unwrap(),
+                    self.settings.all_tags(),
+                    String::new(),
+                ));
+                self.mode = Screen::TagManager;
+            }
             Some(Screen::MainMenu) => {
                 self.mode = Screen::MainMenu;
             }
@@ -199,7 +387,12 @@ impl AppState {
     fn init_deck_view(&mut self, did: i32) {
         self.settings.sr(did);
 
-        self.deck_view = Some(DeckView::new(did, self.settings.rds(did), self.dbc.clone()));
+        self.deck_view = Some(DeckView::new(
+            did,
+            self.settings.rds(did),
+            self.settings.autotag(),
+            self.dbc.clone(),
+        ));
         self.mode = Screen::DeckView;
     }
 
@@ -249,6 +442,10 @@ impl AppState {
             String::from("Load a deck"),
             Screen::OpenDeck,
         ));
+        items.push(MainMenuItem::from_with_screen(
+            String::from("Manage tags"),
+            Screen::TagManager,
+        ));
         items.push(MainMenuItem::from_with_screen(
             String::from("Settings"),
             Screen::Settings,
@@ -279,6 +476,9 @@ impl AppState {
                 Screen::Settings => self.settings_view.as_ref().unwrap().render(frame),
                 Screen::MakeDeck => self.create_deck_view.render(frame),
                 Screen::OpenDeck => self.open_deck_view.render(frame),
+                Screen::Notes => self.notes_view.as_ref().unwrap().render(frame),
+                Screen::TagManager => self.tag_manager_view.as_ref().unwrap().render(frame),
+                Screen::Snapshots => self.snapshot_view.as_ref().unwrap().render(frame),
                 Screen::DeckStat => {
                     if let Some(dsv) = &mut self.deck_stat_view {
                         dsv.recalc();
@@ -308,8 +508,14 @@ pub fn run() -> Result<()> {
         state.render(&mut terminal);
 
         if state.mode != Screen::DeckStat {
-            if let Event::Key(KeyEvent { code, .. }) = read()? {
-                let _a = state.handle_input(code);
+            if let Event::Key(KeyEvent { code, modifiers }) = read()? {
+                let _a = if modifiers.contains(KeyModifiers::CONTROL) {
+                    state.handle_control(code)
+                } else if modifiers.contains(KeyModifiers::SHIFT) {
+                    state.handle_shift(code)
+                } else {
+                    state.handle_input(code)
+                };
                 if state.mode == Screen::DeckStat {
                     let did = state.deck_view.as_ref().unwrap().rdid();
                     let arc = Arc::clone(&state.dbc);
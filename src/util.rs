@@ -19,6 +19,7 @@ use std::{
 };
 
 use self::views::Changes;
+use crate::db::{CardUse, Printing, Ruling};
 
 pub fn get_local_file(name: &str, file_must_exist: bool) -> PathBuf {
     let mut p = env::current_exe().unwrap();
@@ -34,6 +35,243 @@ pub fn get_local_file(name: &str, file_must_exist: bool) -> PathBuf {
     p
 }
 
+// Tags nest with '/', so "removal/creature" is a child of "removal".
+pub fn tag_ancestors(tag: &str) -> Vec<&str> {
+    tag.match_indices('/')
+        .map(|(i, _)| &tag[..i])
+        .chain(std::iter::once(tag))
+        .collect()
+}
+
+// The last part of a tag, indented by its depth in the tree. Tags whose parent isn't listed
+// keep their full name.
+pub fn tag_label(tag: &str, tags: &[String]) -> String {
+    match tag.rsplit_once('/') {
+        Some((parent, leaf)) if tags.iter().any(|t| t == parent) => {
+            format!("{}└{}", "  ".repeat(parent.matches('/').count()), leaf)
+        }
+        _ => tag.to_string(),
+    }
+}
+
+// What is left of a tag after its parent's name: "" for the parent itself, "/creature" for
+// removal/creature under removal, and None for unrelated tags.
+pub fn tag_under<'a>(tag: &'a str, parent: &str) -> Option<&'a str> {
+    tag.strip_prefix(parent).filter(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+// Tags are /-separated names without spaces, semicolons or pipes, which separate them elsewhere.
+pub fn valid_tag(tag: &str) -> bool {
+    tag.split('/').all(|s| !s.is_empty() && s.chars().all(tag_char))
+}
+
+pub fn tag_char(c: char) -> bool {
+    !c.is_whitespace() && c != ';' && c != '|'
+}
+
+// Quotes a string for the hand-written settings file.
+fn toml_str(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Sorts so that children come straight after their parent.
+pub fn sort_tags(vt: &mut [String]) {
+    vt.sort_by(|a, b| a.split('/').cmp(b.split('/')));
+}
+
+// Where a tag ends up when old is renamed to new, or None when old is deleted. Children of old
+// move along with it.
+fn moved_tag(t: &str, old: &str, new: Option<&str>) -> Option<String> {
+    match tag_under(t, old) {
+        Some(rest) => new.map(|n| format!("{}{}", n, rest)),
+        None => Some(t.to_string()),
+    }
+}
+
+fn retag_list(vt: &mut Vec<String>, old: &str, new: Option<&str>) {
+    *vt = vt.iter().filter_map(|t| moved_tag(t, old, new)).unique().collect();
+    sort_tags(vt);
+}
+
+fn retag_map<T>(hm: &mut HashMap<String, T>, old: &str, new: Option<&str>) {
+    *hm = hm.drain().filter_map(|(t, v)| Some((moved_tag(&t, old, new)?, v))).collect();
+}
+
+// A deck-building goal for how many cards carry a tag. Written as "10" (at least 10), "8-12",
+// "-3" (at most 3) or "10-".
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct TagTarget {
+    pub min: Option<u32>,
+    pub max: Option<u32>,
+}
+
+impl TagTarget {
+    pub fn from(s: &str) -> Option<TagTarget> {
+        let parse = |s: &str| match s.trim() {
+            "" => Ok(None),
+            n => n.parse::<u32>().map(Some),
+        };
+        let target = match s.split_once('-') {
+            Some((min, max)) => TagTarget {
+                min: parse(min).ok()?,
+                max: parse(max).ok()?,
+            },
+            None => TagTarget {
+                min: Some(s.trim().parse().ok()?),
+                max: None,
+            },
+        };
+        match target {
+            TagTarget { min: None, max: None } => None,
+            TagTarget { min: Some(a), max: Some(b) } if a > b => None,
+            t => Some(t),
+        }
+    }
+
+    pub fn under(&self, n: u32) -> bool {
+        self.min.is_some_and(|min| n < min)
+    }
+
+    pub fn over(&self, n: u32) -> bool {
+        self.max.is_some_and(|max| n > max)
+    }
+
+    // A text progress bar towards the minimum, or the maximum if there is none.
+    pub fn bar(&self, n: u32, width: usize) -> String {
+        let goal = self.min.or(self.max).unwrap_or_default().max(1);
+        let filled = (n as usize * width / goal as usize).min(width);
+        format!("[{}{}] {}/{}", "#".repeat(filled), "-".repeat(width - filled), n, self)
+    }
+}
+
+// Reads targets stored as a tag and its target separated by a space.
+fn parse_targets(vs: &[String]) -> HashMap<String, TagTarget> {
+    vs.iter()
+        .filter_map(|s| s.split_once(' '))
+        .filter_map(|(tag, t)| Some((tag.to_string(), TagTarget::from(t)?)))
+        .collect()
+}
+
+impl std::fmt::Display for TagTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (self.min, self.max) {
+            (Some(min), None) => write!(f, "{}", min),
+            (min, max) => write!(
+                f,
+                "{}-{}",
+                min.map(|n| n.to_string()).unwrap_or_default(),
+                max.map(|n| n.to_string()).unwrap_or_default()
+            ),
+        }
+    }
+}
+
+// Optional extras for a tag. Written as "tag;color;hotkey;description", with empty fields
+// left out, since tags can't contain ';'.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TagInfo {
+    pub description: String,
+    pub color: Option<Color>,
+    pub hotkey: Option<u8>,
+}
+
+impl TagInfo {
+    pub fn from(s: &str) -> Option<(String, TagInfo)> {
+        let mut parts = s.splitn(4, ';');
+        let tag = parts.next().filter(|t| !t.is_empty())?.to_string();
+        let color = parts.next().and_then(TagInfo::color_from);
+        let hotkey = parts.next().and_then(|k| k.parse().ok()).filter(|k| (1..=9).contains(k));
+        let description = parts.next().unwrap_or_default().to_string();
+        Some((tag, TagInfo { description, color, hotkey }))
+    }
+
+    pub fn to_line(&self, tag: &str) -> String {
+        format!(
+            "{};{};{};{}",
+            tag,
+            self.color.map(TagInfo::color_name).unwrap_or_default(),
+            self.hotkey.map(|k| k.to_string()).unwrap_or_default(),
+            self.description
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.description.is_empty() && self.color.is_none() && self.hotkey.is_none()
+    }
+
+    pub fn color_from(s: &str) -> Option<Color> {
+        match s.trim().to_lowercase().as_str() {
+            "red" => Some(Color::Red),
+            "green" => Some(Color::Green),
+            "yellow" => Some(Color::Yellow),
+            "blue" => Some(Color::Blue),
+            "magenta" => Some(Color::Magenta),
+            "cyan" => Some(Color::Cyan),
+            "gray" => Some(Color::Gray),
+            "white" => Some(Color::White),
+            "lightred" => Some(Color::LightRed),
+            "lightgreen" => Some(Color::LightGreen),
+            "lightyellow" => Some(Color::LightYellow),
+            "lightblue" => Some(Color::LightBlue),
+            "lightmagenta" => Some(Color::LightMagenta),
+            "lightcyan" => Some(Color::LightCyan),
+            _ => None,
+        }
+    }
+
+    pub fn color_name(c: Color) -> &'static str {
+        match c {
+            Color::Red => "red",
+            Color::Green => "green",
+            Color::Yellow => "yellow",
+            Color::Blue => "blue",
+            Color::Magenta => "magenta",
+            Color::Cyan => "cyan",
+            Color::Gray => "gray",
+            Color::LightRed => "lightred",
+            Color::LightGreen => "lightgreen",
+            Color::LightYellow => "lightyellow",
+            Color::LightBlue => "lightblue",
+            Color::LightMagenta => "lightmagenta",
+            Color::LightCyan => "lightcyan",
+            _ => "white",
+        }
+    }
+}
+
+// Tags every card in a deck that matches an omni query. Written as "tag;query", e.g.
+// removal;te:"destroy target"|"exile target" ty:!land
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoTagRule {
+    pub tag: String,
+    pub query: String,
+}
+
+impl AutoTagRule {
+    pub fn from(s: &str) -> Option<AutoTagRule> {
+        let (tag, query) = s.split_once(';')?;
+        let (tag, query) = (tag.trim(), query.trim());
+        if tag.is_empty() || query.is_empty() {
+            return None;
+        }
+        Some(AutoTagRule {
+            tag: tag.to_string(),
+            query: query.to_string(),
+        })
+    }
+
+    pub fn to_line(&self) -> String {
+        format!("{};{}", self.tag, self.query)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AutoTagSettings {
+    pub rules: Vec<AutoTagRule>,
+    // Also run the rules on each card as it's added to a deck.
+    pub on_add: bool,
+}
+
 #[derive(Debug, Default, Copy, Clone, PartialEq)]
 pub enum SortOrder {
     #[default]
@@ -50,9 +288,152 @@ pub enum CommanderType {
     Default,
     Partner,
     PartnerWith(String),
+    FriendsForever,
+    ChooseBackground,
+    DoctorsCompanion,
+    Doctor,
     Invalid,
 }
 
+// Stored in the deck_type column of the decks table.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum DeckType {
+    #[default]
+    Commander,
+    Brawl,
+    HistoricBrawl,
+    Oathbreaker,
+    PauperEdh,
+    Constructed,
+}
+
+// Stored in the zone column of deck_contents.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Zone {
+    Commander,
+    #[default]
+    Main,
+    Maybeboard,
+    Sideboard,
+    Considering,
+}
+
+impl Zone {
+    pub fn from(s: &str) -> Zone {
+        match s {
+            "commander" => Zone::Commander,
+            "maybeboard" => Zone::Maybeboard,
+            "sideboard" => Zone::Sideboard,
+            "considering" => Zone::Considering,
+            _ => Zone::Main,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Zone::Commander => "commander",
+            Zone::Main => "main",
+            Zone::Maybeboard => "maybeboard",
+            Zone::Sideboard => "sideboard",
+            Zone::Considering => "considering",
+        }
+    }
+
+    // The zone a card moves to when cycled in the deck view. Commanders only change through
+    // the change commander flow.
+    pub fn next(&self) -> Zone {
+        match self {
+            Zone::Commander => Zone::Commander,
+            Zone::Main => Zone::Maybeboard,
+            Zone::Maybeboard => Zone::Sideboard,
+            Zone::Sideboard => Zone::Considering,
+            Zone::Considering => Zone::Main,
+        }
+    }
+
+    // Commanders and the main zone make up the deck that gets played.
+    pub fn in_deck(&self) -> bool {
+        matches!(self, Zone::Commander | Zone::Main)
+    }
+}
+
+pub struct FormatRules {
+    // Exact deck size for singleton formats, minimum size otherwise.
+    pub size: u32,
+    pub singleton: bool,
+    // Format name as stored in the legalities column of the cards table.
+    pub legality: &'static str,
+    // Whether cards must fit within the color identity of the commander(s).
+    pub identity: bool,
+}
+
+impl DeckType {
+    pub const ALL: [DeckType; 6] = [
+        DeckType::Commander,
+        DeckType::Brawl,
+        DeckType::HistoricBrawl,
+        DeckType::Oathbreaker,
+        DeckType::PauperEdh,
+        DeckType::Constructed,
+    ];
+
+    pub fn from(s: &str) -> DeckType {
+        DeckType::ALL
+            .iter()
+            .copied()
+            .find(|dt| dt.to_string() == s)
+            .unwrap_or_default()
+    }
+
+    pub fn rules(&self) -> FormatRules {
+        let (size, singleton, legality, identity) = match self {
+            DeckType::Commander => (100, true, "commander", true),
+            DeckType::Brawl => (60, true, "brawl", true),
+            DeckType::HistoricBrawl => (100, true, "historicbrawl", true),
+            DeckType::Oathbreaker => (60, true, "oathbreaker", true),
+            DeckType::PauperEdh => (100, true, "paupercommander", true),
+            DeckType::Constructed => (60, false, "vintage", false),
+        };
+        FormatRules {
+            size,
+            singleton,
+            legality,
+            identity,
+        }
+    }
+
+    pub fn max_copies(&self, card: &Card) -> Option<u32> {
+        match card.max_copies() {
+            Some(1) if !self.rules().singleton => Some(4),
+            max => max,
+        }
+    }
+
+    // Labels for the two commander slots; Oathbreaker uses the second for its signature spell
+    // and constructed decks pick a card to represent them instead of a commander.
+    pub fn labels(&self) -> (&'static str, &'static str) {
+        match self {
+            DeckType::Oathbreaker => ("Oathbreaker", "Signature Spell"),
+            DeckType::Constructed => ("Featured Card", ""),
+            _ => ("Commander", "Secondary Commander"),
+        }
+    }
+}
+
+impl std::fmt::Display for DeckType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = match self {
+            DeckType::Commander => "Commander",
+            DeckType::Brawl => "Brawl",
+            DeckType::HistoricBrawl => "Historic Brawl",
+            DeckType::Oathbreaker => "Oathbreaker",
+            DeckType::PauperEdh => "Pauper EDH",
+            DeckType::Constructed => "Constructed",
+        };
+        write!(f, "{}", s)
+    }
+}
+
 #[derive(Default, Copy, Clone, Debug, PartialEq)]
 pub enum DefaultFilter {
     #[default]
@@ -68,6 +449,9 @@ pub enum Screen {
     Settings,
     DeckView,
     DeckStat,
+    Notes,
+    TagManager,
+    Snapshots,
     // Error(&'static str),
 }
 
@@ -108,6 +492,55 @@ impl ToString for DefaultFilter {
 pub struct FileSettings {
     global: FileGlobalSettings,
     decks: HashMap<i32, FileDeckSettings>,
+    #[serde(default = "default_templates")]
+    templates: HashMap<String, FileTagTemplate>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FileTagTemplate {
+    tags: Vec<String>,
+    #[serde(default)]
+    targets: Vec<String>,
+}
+
+// Examples for settings files written before templates existed.
+fn default_templates() -> HashMap<String, FileTagTemplate> {
+    let template = |tags: &[&str], targets: &[&str]| FileTagTemplate {
+        tags: tags.iter().map(|s| s.to_string()).collect(),
+        targets: targets.iter().map(|s| s.to_string()).collect(),
+    };
+    HashMap::from([
+        (
+            String::from("spellslinger"),
+            template(
+                &["board_wipe", "cantrip", "counterspell", "draw", "payoff", "ramp", "removal"],
+                &["board_wipe 2-4", "counterspell 5", "draw 10", "payoff 6", "ramp 10", "removal 8"],
+            ),
+        ),
+        (
+            String::from("aristocrats"),
+            template(
+                &["board_wipe", "draw", "fodder", "outlet", "payoff", "ramp", "recursion", "removal"],
+                &["draw 10", "fodder 15", "outlet 5", "payoff 8", "ramp 10", "removal 8"],
+            ),
+        ),
+        (
+            String::from("voltron"),
+            template(
+                &[
+                    "attachment",
+                    "attachment/aura",
+                    "attachment/equipment",
+                    "draw",
+                    "evasion",
+                    "protection",
+                    "ramp",
+                    "removal",
+                ],
+                &["attachment 15", "draw 8", "protection 8", "ramp 10", "removal 6"],
+            ),
+        ),
+    ])
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -119,6 +552,27 @@ pub struct FileGlobalSettings {
     version: f64,
     recent: i32,
     open_into_recent: bool,
+    #[serde(default)]
+    tag_info: Vec<String>,
+    #[serde(default = "default_autotag")]
+    autotag: Vec<String>,
+    #[serde(default)]
+    autotag_on_add: bool,
+    #[serde(default = "default_trash_days")]
+    trash_days: u32,
+}
+
+fn default_trash_days() -> u32 {
+    30
+}
+
+fn default_autotag() -> Vec<String> {
+    Vec::from([
+        String::from("board_wipe;te:\"destroy all\"|\"exile all\"|\"all creatures get -\""),
+        String::from("draw;te:\"draw a card\"|\"draw two cards\"|\"draw three cards\"|\"draws a card\""),
+        String::from("ramp;ty:!land te:\"add {\"|\"search your library for a basic land\""),
+        String::from("removal;ty:!land te:\"destroy target\"|\"exile target\""),
+    ])
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -127,12 +581,25 @@ pub struct FileDeckSettings {
     ordering: String,
     #[serde(rename = "default_filter")]
     df: String,
+    // Each entry is a tag and its target, separated by a space.
+    #[serde(default)]
+    targets: Vec<String>,
+    #[serde(default)]
+    tag_info: Vec<String>,
 }
 
 #[derive(Debug)]
 pub struct Settings {
     global: GlobalSettings,
     decks: HashMap<i32, Rc<RefCell<DeckSettings>>>,
+    templates: HashMap<String, TagTemplate>,
+}
+
+// A named set of tags and targets for a deck archetype.
+#[derive(Debug, Clone)]
+pub struct TagTemplate {
+    tags: Vec<String>,
+    targets: HashMap<String, TagTarget>,
 }
 
 #[derive(Debug)]
@@ -143,25 +610,29 @@ pub struct GlobalSettings {
     version: f64,
     recent: i32,
     open_into_recent: bool,
+    info: HashMap<String, TagInfo>,
+    autotag: Rc<AutoTagSettings>,
+    // How long deleted decks stay in the trash.
+    trash_days: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DeckSettings {
     tags: Vec<String>,
     ordering: SortOrder,
     df: DefaultFilter,
+    targets: HashMap<String, TagTarget>,
+    info: HashMap<String, TagInfo>,
 }
 
 impl FileSettings {
     pub fn new(path: &Path) -> Result<Self, ConfigError> {
         let mut s = Config::default();
         let tags = Vec::from([
-            String::from("main"),
             String::from("board_wipe"),
             String::from("draw"),
             String::from("ramp"),
             String::from("removal"),
-            String::from("side"),
         ]);
         let ds: HashMap<String, String> = HashMap::new();
         s.set_default("global.version", 0).unwrap();
@@ -185,16 +656,30 @@ impl FileSettings {
         vr.push(format!("version = {}", self.global.version));
         vr.push(String::from("tags = ["));
         for t in &self.global.tags {
-            vr.push(format!("\t\"{}\",", t));
+            vr.push(format!("\t{},", toml_str(t)));
         }
         vr.push(String::from("]"));
-        vr.push(format!("ordering = \"{}\"", self.global.ordering));
-        vr.push(format!("default_filter = \"{}\"", self.global.df));
+        vr.push(format!("ordering = {}", toml_str(&self.global.ordering)));
+        vr.push(format!("default_filter = {}", toml_str(&self.global.df)));
         vr.push(format!("recent = {}", self.global.recent));
         vr.push(format!(
             "open_into_recent = {}",
             self.global.open_into_recent
         ));
+        if !self.global.tag_info.is_empty() {
+            vr.push(String::from("tag_info = ["));
+            for t in &self.global.tag_info {
+                vr.push(format!("\t{},", toml_str(t)));
+            }
+            vr.push(String::from("]"));
+        }
+        vr.push(String::from("autotag = ["));
+        for r in &self.global.autotag {
+            vr.push(format!("\t{},", toml_str(r)));
+        }
+        vr.push(String::from("]"));
+        vr.push(format!("autotag_on_add = {}", self.global.autotag_on_add));
+        vr.push(format!("trash_days = {}", self.global.trash_days));
         vr.push(String::from("\n[decks]"));
 
         // TODO: Explore using a BTreeMap instead to lose dependence on itertools
@@ -205,14 +690,44 @@ impl FileSettings {
             vr.push(format!("\t[decks.{}]", k));
             vr.push(String::from("\ttags = ["));
             for t in &v.tags {
-                vr.push(format!("\t\t\"{}\",", t));
+                vr.push(format!("\t\t{},", toml_str(t)));
             }
             vr.push(String::from("\t]"));
-            vr.push(format!("\tordering = \"{}\"", &v.ordering));
-            vr.push(format!("\tdefault_filter = \"{}\"", &v.df));
+            vr.push(format!("\tordering = {}", toml_str(&v.ordering)));
+            vr.push(format!("\tdefault_filter = {}", toml_str(&v.df)));
+            if !v.targets.is_empty() {
+                vr.push(String::from("\ttargets = ["));
+                for t in &v.targets {
+                    vr.push(format!("\t\t{},", toml_str(t)));
+                }
+                vr.push(String::from("\t]"));
+            }
+            if !v.tag_info.is_empty() {
+                vr.push(String::from("\ttag_info = ["));
+                for t in &v.tag_info {
+                    vr.push(format!("\t\t{},", toml_str(t)));
+                }
+                vr.push(String::from("\t]"));
+            }
             vr.push(String::new());
         }
 
+        vr.push(String::from("\n[templates]"));
+        for k in self.templates.keys().sorted() {
+            let v = self.templates.get(k).unwrap();
+            vr.push(format!("\t[templates.{}]", toml_str(k)));
+            vr.push(String::from("\ttags = ["));
+            for t in &v.tags {
+                vr.push(format!("\t\t{},", toml_str(t)));
+            }
+            vr.push(String::from("\t]"));
+            vr.push(String::from("\ttargets = ["));
+            for t in &v.targets {
+                vr.push(format!("\t\t{},", toml_str(t)));
+            }
+            vr.push(String::from("\t]"));
+            vr.push(String::new());
+        }
         vr.join("\n")
     }
 }
@@ -222,6 +737,14 @@ impl Settings {
         self.global.tags.clone()
     }
 
+    pub fn autotag(&self) -> Rc<AutoTagSettings> {
+        self.global.autotag.clone()
+    }
+
+    pub fn trash_days(&self) -> u32 {
+        self.global.trash_days
+    }
+
     pub fn get_oir(&self) -> bool {
         self.global.open_into_recent
     }
@@ -274,21 +797,18 @@ impl Settings {
                     deck.df = changes.df;
                     deck.ordering = changes.so;
                     for tch in &changes.vtch {
-                        match tch {
-                            views::TagChange::Delete(old) => {
-                                if let Some(i) = deck.tags.iter().position(|s| s == old) {
-                                    deck.tags.remove(i);
-                                }
-                            }
-                            views::TagChange::Change(old, new) => {
-                                if let Some(i) = deck.tags.iter().position(|s| s == old) {
-                                    deck.tags.remove(i);
-                                    deck.tags.push(new.clone());
-                                }
+                        let (old, new) = match tch {
+                            views::TagChange::Delete(old) => (old, None),
+                            views::TagChange::Change(old, new) => (old, Some(new.as_str())),
+                            views::TagChange::Insert(new) => {
+                                deck.tags.push(new.clone());
+                                sort_tags(&mut deck.tags);
+                                continue;
                             }
-                            views::TagChange::Insert(new) => deck.tags.push(new.clone()),
-                        }
-                        deck.tags.sort();
+                        };
+                        retag_list(&mut deck.tags, old, new);
+                        retag_map(&mut deck.targets, old, new);
+                        retag_map(&mut deck.info, old, new);
                     }
                 }
             }
@@ -297,26 +817,51 @@ impl Settings {
                 self.global.ordering = changes.so;
                 self.global.open_into_recent = changes.oir.unwrap();
                 for tch in &changes.vtch {
-                    match tch {
-                        views::TagChange::Delete(old) => {
-                            if let Some(i) = self.global.tags.iter().position(|s| s == old) {
-                                self.global.tags.remove(i);
-                            }
-                        }
-                        views::TagChange::Change(old, new) => {
-                            if let Some(i) = self.global.tags.iter().position(|s| s == old) {
-                                self.global.tags.remove(i);
-                                self.global.tags.push(new.clone());
-                            }
+                    let (old, new) = match tch {
+                        views::TagChange::Delete(old) => (old, None),
+                        views::TagChange::Change(old, new) => (old, Some(new.as_str())),
+                        views::TagChange::Insert(new) => {
+                            self.global.tags.push(new.clone());
+                            sort_tags(&mut self.global.tags);
+                            continue;
                         }
-                        views::TagChange::Insert(new) => self.global.tags.push(new.clone()),
-                    }
-                    self.global.tags.sort();
+                    };
+                    retag_list(&mut self.global.tags, old, new);
+                    retag_map(&mut self.global.info, old, new);
                 }
             }
         }
     }
 
+    // Applies a tag manager rename, merge or delete to the global tags and every deck's.
+    pub fn retag(&mut self, old: &str, new: Option<&str>) {
+        retag_list(&mut self.global.tags, old, new);
+        retag_map(&mut self.global.info, old, new);
+        for ds in self.decks.values() {
+            let mut ds = ds.borrow_mut();
+            retag_list(&mut ds.tags, old, new);
+            retag_map(&mut ds.targets, old, new);
+            retag_map(&mut ds.info, old, new);
+        }
+        for t in self.templates.values_mut() {
+            retag_list(&mut t.tags, old, new);
+            retag_map(&mut t.targets, old, new);
+        }
+    }
+
+    // Every tag named in the settings, so unused ones show up in the tag manager too.
+    pub fn all_tags(&self) -> Vec<String> {
+        let mut vt: Vec<String> = self
+            .decks
+            .values()
+            .flat_map(|ds| ds.borrow().tags.clone())
+            .chain(self.global.tags.iter().cloned())
+            .unique()
+            .collect();
+        sort_tags(&mut vt);
+        vt
+    }
+
     pub fn rr(&self) -> i32 {
         self.global.recent
     }
@@ -335,7 +880,7 @@ impl Settings {
             },
             None => {
                 self.global.tags.push(tag);
-                self.global.tags.sort();
+                sort_tags(&mut self.global.tags);
             }
         };
     }
@@ -345,6 +890,60 @@ impl Settings {
         self.decks.insert(did, Rc::from(RefCell::from(ds)));
     }
 
+    // Gives a copied deck the same tags, targets and view settings as the original.
+    pub fn copy_deck(&mut self, from: i32, to: i32) {
+        match self.decks.get(&from) {
+            Some(ds) => {
+                let ds = ds.borrow().clone();
+                self.decks.insert(to, Rc::from(RefCell::from(ds)));
+            }
+            None => self.id(to),
+        }
+    }
+
+    pub fn template_names(&self) -> Vec<String> {
+        self.templates.keys().cloned().sorted().collect()
+    }
+
+    // Starts a new deck from a template's tags instead of the global ones.
+    pub fn id_template(&mut self, did: i32, name: &str) {
+        let mut ds = DeckSettings::duplicate(&self.global);
+        if let Some(t) = self.templates.get(name) {
+            ds.tags = t.tags.clone();
+            ds.targets = t.targets.clone();
+        }
+        self.decks.insert(did, Rc::from(RefCell::from(ds)));
+    }
+
+    // Adds a template's tags and targets to an existing deck, keeping its other tags.
+    pub fn apply_template(&mut self, did: i32, name: &str) -> bool {
+        match (self.templates.get(name), self.decks.get(&did)) {
+            (Some(t), Some(ds)) => {
+                let mut ds = ds.borrow_mut();
+                for tag in &t.tags {
+                    ds.add_tag(tag.clone());
+                }
+                for (tag, target) in &t.targets {
+                    ds.targets.insert(tag.clone(), *target);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Saves a deck's tags and targets as a template, replacing any of the same name.
+    pub fn save_template(&mut self, did: i32, name: &str) {
+        if let Some(ds) = self.decks.get(&did) {
+            let ds = ds.borrow();
+            let t = TagTemplate {
+                tags: ds.tags.clone(),
+                targets: ds.targets.clone(),
+            };
+            self.templates.insert(name.to_string(), t);
+        }
+    }
+
     pub fn dd(&mut self, deck: i32) {
         self.decks.remove(&deck);
         if self.global.recent == deck {
@@ -361,9 +960,22 @@ impl Settings {
             dhash.insert(did, Rc::from(RefCell::from(ds)));
         }
 
+        let templates = fs
+            .templates
+            .into_iter()
+            .map(|(name, ft)| {
+                let t = TagTemplate {
+                    tags: ft.tags,
+                    targets: parse_targets(&ft.targets),
+                };
+                (name, t)
+            })
+            .collect();
+
         Self {
             global: gs,
             decks: dhash,
+            templates,
         }
     }
 
@@ -373,22 +985,32 @@ impl Settings {
         vr.push(format!("version = {}", self.global.version));
         vr.push(String::from("tags = ["));
         for t in &self.global.tags {
-            vr.push(format!("\t\"{}\",", t));
+            vr.push(format!("\t{},", toml_str(t)));
         }
         vr.push(String::from("]"));
-        vr.push(format!(
-            "ordering = \"{}\"",
-            self.global.ordering.to_string()
-        ));
-        vr.push(format!(
-            "default_filter = \"{}\"",
-            self.global.df.to_string()
-        ));
+        vr.push(format!("ordering = {}", toml_str(&self.global.ordering.to_string())));
+        vr.push(format!("default_filter = {}", toml_str(&self.global.df.to_string())));
         vr.push(format!("recent = {}", self.global.recent));
         vr.push(format!(
             "open_into_recent = {}",
             self.global.open_into_recent
         ));
+        if !self.global.info.is_empty() {
+            vr.push(String::from("tag_info = ["));
+            for (tag, ti) in self.global.info.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+                let line = ti.to_line(tag);
+                vr.push(format!("\t{},", toml_str(&line)));
+            }
+            vr.push(String::from("]"));
+        }
+        vr.push(String::from("autotag = ["));
+        for r in &self.global.autotag.rules {
+            let line = r.to_line();
+            vr.push(format!("\t{},", toml_str(&line)));
+        }
+        vr.push(String::from("]"));
+        vr.push(format!("autotag_on_add = {}", self.global.autotag.on_add));
+        vr.push(format!("trash_days = {}", self.global.trash_days));
         vr.push(String::from("\n[decks]"));
 
         // TODO: Explore using a BTreeMap instead to lose dependence on itertools
@@ -399,14 +1021,45 @@ impl Settings {
             vr.push(format!("\t[decks.{}]", k));
             vr.push(String::from("\ttags = ["));
             for t in &v.tags {
-                vr.push(format!("\t\t\"{}\",", t));
+                vr.push(format!("\t\t{},", toml_str(t)));
             }
             vr.push(String::from("\t]"));
-            vr.push(format!("\tordering = \"{}\"", &v.ordering.to_string()));
-            vr.push(format!("\tdefault_filter = \"{}\"", &v.df.to_string()));
+            vr.push(format!("\tordering = {}", toml_str(&v.ordering.to_string())));
+            vr.push(format!("\tdefault_filter = {}", toml_str(&v.df.to_string())));
+            if !v.targets.is_empty() {
+                vr.push(String::from("\ttargets = ["));
+                for (tag, t) in v.targets.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+                    vr.push(format!("\t\t{},", toml_str(&format!("{} {}", tag, t))));
+                }
+                vr.push(String::from("\t]"));
+            }
+            if !v.info.is_empty() {
+                vr.push(String::from("\ttag_info = ["));
+                for (tag, ti) in v.info.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+                    let line = ti.to_line(tag);
+                    vr.push(format!("\t\t{},", toml_str(&line)));
+                }
+                vr.push(String::from("\t]"));
+            }
             vr.push(String::new());
         }
 
+        vr.push(String::from("\n[templates]"));
+        for k in self.templates.keys().sorted() {
+            let v = self.templates.get(k).unwrap();
+            vr.push(format!("\t[templates.{}]", toml_str(k)));
+            vr.push(String::from("\ttags = ["));
+            for t in &v.tags {
+                vr.push(format!("\t\t{},", toml_str(t)));
+            }
+            vr.push(String::from("\t]"));
+            vr.push(String::from("\ttargets = ["));
+            for (tag, t) in v.targets.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+                vr.push(format!("\t\t{},", toml_str(&format!("{} {}", tag, t))));
+            }
+            vr.push(String::from("\t]"));
+            vr.push(String::new());
+        }
         vr.join("\n")
     }
 }
@@ -427,13 +1080,22 @@ impl GlobalSettings {
             _ => SortOrder::NameAsc,
         };
 
+        // The main and side tags were replaced by deck zones.
+        let tags = fgs.tags.into_iter().filter(|t| t != "main" && t != "side").collect();
+
         Self {
-            tags: fgs.tags,
+            tags,
             ordering,
             df,
             version: fgs.version,
             recent: fgs.recent,
             open_into_recent: fgs.open_into_recent,
+            info: fgs.tag_info.iter().filter_map(|s| TagInfo::from(s)).collect(),
+            autotag: Rc::from(AutoTagSettings {
+                rules: fgs.autotag.iter().filter_map(|s| AutoTagRule::from(s)).collect(),
+                on_add: fgs.autotag_on_add,
+            }),
+            trash_days: fgs.trash_days,
         }
     }
 
@@ -462,10 +1124,17 @@ impl DeckSettings {
             _ => SortOrder::NameAsc,
         };
 
+        let targets = parse_targets(&fds.targets);
+
+        // The main and side tags were replaced by deck zones.
+        let tags = fds.tags.into_iter().filter(|t| t != "main" && t != "side").collect();
+
         Self {
-            tags: fds.tags,
+            tags,
             ordering,
             df,
+            targets,
+            info: fds.tag_info.iter().filter_map(|s| TagInfo::from(s)).collect(),
         }
     }
 
@@ -474,13 +1143,72 @@ impl DeckSettings {
             tags: gs.tags.clone(),
             ordering: gs.ordering,
             df: gs.df,
+            targets: HashMap::new(),
+            info: gs.info.clone(),
+        }
+    }
+
+    pub fn info(&self, tag: &str) -> TagInfo {
+        self.info.get(tag).cloned().unwrap_or_default()
+    }
+
+    pub fn set_info(&mut self, tag: &str, info: TagInfo) {
+        // A hotkey belongs to one tag at a time.
+        if info.hotkey.is_some() {
+            for other in self.info.values_mut() {
+                if other.hotkey == info.hotkey {
+                    other.hotkey = None;
+                }
+            }
+        }
+        if info.is_empty() {
+            self.info.remove(tag);
+        } else {
+            self.info.insert(tag.to_string(), info);
         }
+        self.info.retain(|_, ti| !ti.is_empty());
+    }
+
+    pub fn tag_for_key(&self, key: u8) -> Option<String> {
+        self.info
+            .iter()
+            .find(|(_, ti)| ti.hotkey == Some(key))
+            .map(|(tag, _)| tag.clone())
+    }
+
+    // The display color of each tag, which nested tags inherit from their nearest parent.
+    pub fn tag_colors(&self) -> HashMap<String, Color> {
+        self.tags
+            .iter()
+            .filter_map(|tag| {
+                let color = tag_ancestors(tag)
+                    .into_iter()
+                    .rev()
+                    .find_map(|t| self.info.get(t).and_then(|ti| ti.color))?;
+                Some((tag.clone(), color))
+            })
+            .collect()
+    }
+
+    pub fn target(&self, tag: &str) -> Option<TagTarget> {
+        self.targets.get(tag).copied()
+    }
+
+    pub fn targets(&self) -> &HashMap<String, TagTarget> {
+        &self.targets
+    }
+
+    pub fn set_target(&mut self, tag: &str, target: Option<TagTarget>) {
+        match target {
+            Some(t) => self.targets.insert(tag.to_string(), t),
+            None => self.targets.remove(tag),
+        };
     }
 
     pub fn add_tag(&mut self, tag: String) {
         if !self.tags.contains(&tag) {
             self.tags.push(tag);
-            self.tags.sort();
+            sort_tags(&mut self.tags);
         }
     }
 
@@ -642,18 +1370,46 @@ impl<T: ToString + PartialEq + Clone> StatefulList<T> {
             .collect()
     }
 
-    pub fn rvlis(&self, vcn: &[String]) -> Vec<ListItem> {
+    pub fn rvliq(
+        &self,
+        quantities: &HashMap<String, u32>,
+        colors: &HashMap<String, Color>,
+        marked: &[String],
+    ) -> Vec<ListItem<'_>> {
+        self.items
+            .iter()
+            .map(|f| {
+                let s = f.to_string();
+                let style = match colors.get(&s) {
+                    Some(c) => Style::default().fg(*c),
+                    None => Style::default(),
+                };
+                let mark = if marked.contains(&s) { "* " } else { "" };
+                match quantities.get(&s) {
+                    Some(q) => ListItem::new(format!("{}{}x {}", mark, q, s)).style(style),
+                    None => ListItem::new(format!("{}{}", mark, s)).style(style),
+                }
+            })
+            .collect()
+    }
+
+    pub fn rvlis(&self, vcn: &[String], marked: &[String]) -> Vec<ListItem> {
         self.items
             .iter()
             .map(|f| {
-                if vcn.contains(&f.to_string()) {
-                    ListItem::new(f.to_string()).style(
+                let s = f.to_string();
+                let item = match marked.contains(&s) {
+                    true => ListItem::new(format!("* {}", s)),
+                    false => ListItem::new(s.clone()),
+                };
+                if vcn.contains(&s) {
+                    item.style(
                         Style::default()
                             .fg(Color::Yellow)
                             .add_modifier(Modifier::ITALIC),
                     )
                 } else {
-                    ListItem::new(f.to_string())
+                    item
                 }
             })
             .collect()
@@ -711,7 +1467,16 @@ pub struct Card {
     pub rarity: String,
     pub price: Option<f64>,
     pub stale: bool,
-    //TODO: Add sets?
+    pub printing: Option<CardPrinting>,
+    pub quantity: u32,
+    pub zone: Option<Zone>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CardPrinting {
+    pub set_code: String,
+    pub number: String,
+    pub foil: bool,
 }
 
 impl ToString for Card {
@@ -720,7 +1485,14 @@ impl ToString for Card {
     }
 }
 impl Card {
-    pub fn display(&self) -> Paragraph {
+    pub fn display(
+        &self,
+        rulings: &[Ruling],
+        printings: &[Printing],
+        all_printings: bool,
+        uses: &[CardUse],
+        tag_colors: &HashMap<String, Color>,
+    ) -> Paragraph {
         let mut v = Vec::new();
         v.push(Spans::from(self.name.clone()));
         v.push(Spans::from(format!("{}, ({})", self.mana_cost, self.cmc)));
@@ -781,16 +1553,125 @@ impl Card {
         }
 
         if !self.tags.is_empty() {
-            v.push(Spans::from(format!("Tags: {}", self.tags.join(" "))));
+            let mut vsp = vec![Span::from("Tags:")];
+            for t in &self.tags {
+                vsp.push(Span::from(" "));
+                vsp.push(match tag_colors.get(t) {
+                    Some(c) => Span::styled(t.clone(), Style::default().fg(*c)),
+                    None => Span::from(t.clone()),
+                });
+            }
+            v.push(Spans::from(vsp));
         }
 
-        Paragraph::new(v).wrap(tui::widgets::Wrap { trim: false })
-    }
+        if let Some(z) = self.zone {
+            v.push(Spans::from(format!("Zone: {}", z.as_str())));
+        }
 
-    pub fn is_commander(&self) -> CommanderType {
-        if (self.types.contains("Legendary") && self.types.contains("Creature"))
-            || (self.types.contains("Planeswalker") && self.text.contains("can be your commander"))
-        {
+        if self.quantity > 1 {
+            v.push(Spans::from(format!("Copies in deck: {}", self.quantity)));
+        }
+
+        if let Some(p) = &self.printing {
+            let foil = if p.foil { ", foil" } else { "" };
+            v.push(Spans::from(format!("Using printing: {} #{}{}", p.set_code, p.number, foil)));
+        }
+
+        if let (Some(first), Some(latest)) = (printings.first(), printings.last()) {
+            v.push(Spans::from(format!(
+                "First printed {} ({}), latest {} ({})",
+                first.date, first.set_code, latest.date, latest.set_code
+            )));
+        }
+
+        v.push(Spans::from(String::new()));
+        if uses.is_empty() {
+            v.push(Spans::from("Not in any other deck."));
+        } else {
+            v.push(Spans::from(Span::styled(
+                format!("In other decks ({})", uses.len()),
+                Style::default().add_modifier(Modifier::BOLD),
+            )));
+        }
+        for u in uses {
+            let mut s = u.deck.clone();
+            if u.archived {
+                s.push_str(" [archived]");
+            }
+            s.push_str(&format!(" ({}", u.zone.as_str()));
+            if u.quantity > 1 {
+                s.push_str(&format!(", {} copies", u.quantity));
+            }
+            s.push(')');
+            let mut vsp = vec![Span::from(s)];
+            for (i, t) in u.tags.iter().enumerate() {
+                vsp.push(Span::from(if i == 0 { ": " } else { " " }));
+                vsp.push(match tag_colors.get(t) {
+                    Some(c) => Span::styled(t.clone(), Style::default().fg(*c)),
+                    None => Span::from(t.clone()),
+                });
+            }
+            v.push(Spans::from(vsp));
+        }
+
+        if all_printings && !printings.is_empty() {
+            v.push(Spans::from(String::new()));
+            v.push(Spans::from(Span::styled(
+                format!("Printings ({})", printings.len()),
+                Style::default().add_modifier(Modifier::BOLD),
+            )));
+            for p in printings {
+                v.push(Spans::from(format!(
+                    "{} {} #{} ({})",
+                    p.date, p.set_code, p.number, p.rarity
+                )));
+            }
+        }
+
+        if !rulings.is_empty() {
+            v.push(Spans::from(String::new()));
+            v.push(Spans::from(Span::styled(
+                "Rulings",
+                Style::default().add_modifier(Modifier::BOLD),
+            )));
+            for r in rulings {
+                v.push(Spans::from(format!("{}: {}", r.date, r.text)));
+            }
+        }
+
+        Paragraph::new(v).wrap(tui::widgets::Wrap { trim: false })
+    }
+
+    // None means the deck may run any number of copies.
+    pub fn max_copies(&self) -> Option<u32> {
+        if self.types.contains("Basic")
+            || self.text.contains("A deck can have any number of cards named")
+        {
+            return None;
+        }
+        let re = Regex::new(r"A deck can have up to (\w+) cards named").unwrap();
+        if let Some(cap) = re.captures(self.text.as_str()) {
+            let max = match &cap[1] {
+                "two" => 2,
+                "three" => 3,
+                "four" => 4,
+                "five" => 5,
+                "six" => 6,
+                "seven" => 7,
+                "eight" => 8,
+                "nine" => 9,
+                "ten" => 10,
+                s => s.parse().unwrap_or(1),
+            };
+            return Some(max);
+        }
+        Some(1)
+    }
+
+    pub fn is_commander(&self) -> CommanderType {
+        if (self.types.contains("Legendary") && self.types.contains("Creature"))
+            || (self.types.contains("Planeswalker") && self.text.contains("can be your commander"))
+        {
             let re = Regex::new(r"Partner with ([\w, ]+)(?:\n| \()").unwrap();
             if let Some(cap) = re.captures(self.text.as_str()) {
                 return CommanderType::PartnerWith((&cap[1]).to_string());
@@ -798,10 +1679,134 @@ impl Card {
             if self.text.contains("Partner") {
                 return CommanderType::Partner;
             }
+            if self.text.contains("Friends forever") {
+                return CommanderType::FriendsForever;
+            }
+            if self.text.contains("Choose a Background") {
+                return CommanderType::ChooseBackground;
+            }
+            if self.text.contains("Doctor's companion") {
+                return CommanderType::DoctorsCompanion;
+            }
+            if self.types.contains("Time Lord Doctor") {
+                return CommanderType::Doctor;
+            }
             return CommanderType::Default;
         }
         CommanderType::Invalid
     }
+
+    // Checks the companion's deckbuilding condition against the starting deck. Returns the names
+    // of the cards that break it, or a description of the problem for deck-wide conditions.
+    pub fn companion_issues(&self, deck: &[Card], rules: &FormatRules) -> Vec<String> {
+        let nonland = |c: &&Card| !c.types.contains("Land");
+        let permanent = |c: &Card| {
+            ["Artifact", "Creature", "Enchantment", "Land", "Planeswalker", "Battle"]
+                .iter()
+                .any(|t| c.types.contains(t))
+        };
+        let names = |vc: Vec<&Card>| vc.iter().map(|c| c.name.clone()).collect();
+
+        match self.name.as_str() {
+            "Gyruda, Doom of Depths" => {
+                names(deck.iter().filter(nonland).filter(|c| !(c.cmc as u32).is_multiple_of(2)).collect())
+            }
+            "Obosh, the Preypiercer" => {
+                names(deck.iter().filter(nonland).filter(|c| (c.cmc as u32).is_multiple_of(2)).collect())
+            }
+            "Keruga, the Macrosage" => {
+                names(deck.iter().filter(nonland).filter(|c| c.cmc < 3.0).collect())
+            }
+            "Lurrus of the Dream-Den" => names(
+                deck.iter()
+                    .filter(nonland)
+                    .filter(|c| permanent(c) && c.cmc > 2.0)
+                    .collect(),
+            ),
+            "Kaheera, the Orphanguard" => {
+                let types = ["Cat", "Elemental", "Nightmare", "Dinosaur", "Beast"];
+                names(
+                    deck.iter()
+                        .filter(|c| c.types.contains("Creature"))
+                        .filter(|c| {
+                            let subtypes = c.types.split(" — ").nth(1).unwrap_or_default();
+                            !subtypes.split(' ').any(|t| types.contains(&t))
+                        })
+                        .collect(),
+                )
+            }
+            "Jegantha, the Wellspring" => {
+                let re = Regex::new(r"\{([^}]+)\}").unwrap();
+                names(
+                    deck.iter()
+                        .filter(|c| {
+                            let symbols: Vec<&str> = re
+                                .captures_iter(&c.mana_cost)
+                                .map(|cap| cap.get(1).unwrap().as_str())
+                                .collect();
+                            symbols.iter().unique().count() < symbols.len()
+                        })
+                        .collect(),
+                )
+            }
+            "Umori, the Collector" => {
+                let types = [
+                    "Artifact",
+                    "Creature",
+                    "Enchantment",
+                    "Instant",
+                    "Planeswalker",
+                    "Sorcery",
+                    "Battle",
+                ];
+                // Measure against the most common card type among the nonland cards.
+                let shared = types
+                    .iter()
+                    .max_by_key(|t| deck.iter().filter(nonland).filter(|c| c.types.contains(*t)).count())
+                    .unwrap();
+                names(
+                    deck.iter()
+                        .filter(nonland)
+                        .filter(|c| !c.types.contains(shared))
+                        .collect(),
+                )
+            }
+            "Lutri, the Spellchaser" => {
+                names(deck.iter().filter(nonland).filter(|c| c.quantity > 1).collect())
+            }
+            "Zirda, the Dawnwaker" => {
+                names(deck.iter().filter(|c| permanent(c) && !c.text.contains(':')).collect())
+            }
+            "Yorion, Sky Nomad" => {
+                let total: u32 = deck.iter().map(|c| c.quantity).sum();
+                if rules.singleton || total < rules.size + 20 {
+                    vec![format!(
+                        "The deck needs at least {} cards; it has {}.",
+                        rules.size + 20,
+                        total
+                    )]
+                } else {
+                    Vec::new()
+                }
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // Whether other can be this card's second commander.
+    pub fn can_pair(&self, other: &Card) -> bool {
+        match (self.is_commander(), other.is_commander()) {
+            (CommanderType::PartnerWith(name), _) => name == other.name,
+            (CommanderType::ChooseBackground, _) => {
+                other.types.starts_with("Legendary Enchantment") && other.types.contains("Background")
+            }
+            (CommanderType::Partner, CommanderType::Partner)
+            | (CommanderType::FriendsForever, CommanderType::FriendsForever)
+            | (CommanderType::DoctorsCompanion, CommanderType::Doctor)
+            | (CommanderType::Doctor, CommanderType::DoctorsCompanion) => true,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -814,6 +1819,18 @@ pub struct CardStat {
     pub types: String,
     pub price: f64,
     pub stale: bool,
+    pub quantity: u32,
+}
+
+impl CardStat {
+    // Name shown in the price list, prefixed with the number of copies when there are several.
+    fn rpn(&self) -> String {
+        if self.quantity > 1 {
+            format!("{}x {}", self.quantity, self.name)
+        } else {
+            self.name.clone()
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -823,6 +1840,15 @@ pub struct Deck {
     pub commander2: Option<Card>,
     pub color: String,
     pub id: i32,
+    pub notes: String,
+    pub deck_type: DeckType,
+    pub companion: Option<Card>,
+    // Folders or labels used to group decks on the Open Deck screen.
+    pub labels: Vec<String>,
+    pub modified: String,
+    pub archived: bool,
+    // When the deck was moved to the trash.
+    pub deleted: Option<String>,
 }
 
 impl ToString for Deck {
@@ -834,7 +1860,7 @@ impl ToString for Deck {
 pub mod views {
     use crossterm::event::KeyCode;
     use rusqlite::Connection;
-    use std::cmp::Ordering;
+    use std::cmp::Reverse;
     use std::rc::Rc;
     use std::{
         cell::RefCell,
@@ -920,10 +1946,29 @@ pub mod views {
     #[derive(Copy, Clone, PartialEq)]
     enum CreateDeckSection {
         Title,
+        Format,
+        Template,
         PrimaryCommander,
         SecondaryCommander,
     }
 
+    // A bulk change waiting on the popup that previews it.
+    enum Pending {
+        AutoTags(Vec<(String, String)>),
+        Add(Vec<String>),
+        Remove(Vec<String>),
+        Tag(Vec<String>, String, bool),
+    }
+
+    // The popup text asking to confirm a bulk change to the listed cards.
+    fn rpending(question: String, vcn: &[String]) -> String {
+        let mut s = format!("{}\n{}", question, vcn.iter().take(20).join("\n"));
+        if vcn.len() > 20 {
+            s += &format!("\n...and {} more", vcn.len() - 20);
+        }
+        s
+    }
+
     #[derive(Copy, Clone, PartialEq)]
     enum DeckViewSection {
         DeckOmni,
@@ -938,13 +1983,38 @@ pub mod views {
         Stats,
         Settings(i32),
         NewTag(String, i32),
+        Notes(i32),
+        Template(String, i32),
+        SaveTemplate(String, i32),
+        Snapshots(i32),
     }
 
     pub enum OpenDeckViewExit {
         Hold,
         Cancel,
         OpenDeck(i32),
+        // Moves a deck to the trash; DeleteDeck removes a deck in the trash for good.
+        TrashDeck(i32),
+        RestoreDeck(i32),
         DeleteDeck(i32),
+        ArchiveDeck(i32, bool),
+        // The deck to copy, the copy's name and any new commanders, separated by '|'.
+        CopyDeck(i32, String, Option<String>),
+        Labels(i32, Vec<String>),
+    }
+
+    pub enum TagManagerExit {
+        Hold,
+        Cancel,
+        // A tag to rename or merge into the new name, or to delete when there is none.
+        Apply(String, Option<String>),
+    }
+
+    pub enum SnapshotExit {
+        Hold,
+        Cancel,
+        // The name of the snapshot the deck was put back to.
+        Restored(String),
     }
 
     pub struct Changes {
@@ -972,20 +2042,47 @@ pub mod views {
         omniprev: String,
         omnipos: usize,
         vsomni: Vec<String>,
+        // The deck filter last run with Enter. Bulk commands act on its results, since the
+        // deck list follows the omni as it's edited.
+        omnirun: String,
         slde: StatefulList<String>,
         sldb: StatefulList<String>,
         vcdec: Vec<String>,
+        // Copies of each deck card with more than one, refreshed along with vcdec.
+        quantities: HashMap<String, u32>,
+        // The deck cards breaking the companion's condition, refreshed after each edit.
+        companion_issues: Vec<String>,
+        // Marked cards in the deck and database lists, for acting on several at once.
+        vmde: Vec<String>,
+        vmdb: Vec<String>,
         st: usize,
         ac: Option<Card>,
+        rulings: bool,
+        printings: bool,
+        coms: Vec<String>,
+        companion: Option<Card>,
+        popup: Option<String>,
+        pending: Option<Pending>,
+        // Each deck card's color from its first colored tag, kept here so rendering needn't query.
+        card_colors: HashMap<String, Color>,
+        // The active card's rulings, printings and the other decks running it.
+        card_rulings: Vec<Ruling>,
+        card_printings: Vec<Printing>,
+        uses: Vec<CardUse>,
         cf: CardFilter,
         dvs: DeckViewSection,
         settings: Rc<RefCell<DeckSettings>>,
+        autotag: Rc<AutoTagSettings>,
         dbc: Arc<Mutex<Connection>>,
     }
 
     pub struct CreateDeckView {
         section: CreateDeckSection,
         title: String,
+        dt: DeckType,
+        templates: Vec<String>,
+        // Index into templates, or None for the global tags.
+        template: Option<usize>,
         com1: String,
         com2: String,
         vcn: Vec<String>,
@@ -993,10 +2090,99 @@ pub mod views {
         tpos: usize,
     }
 
+    #[derive(Clone, PartialEq)]
+    enum CopyStep {
+        Name,
+        // Holds the name chosen for the copy.
+        Commander(String),
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum DeckList {
+        Decks,
+        Archived,
+        Trash,
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum DeckInput {
+        Filter,
+        Labels(i32),
+    }
+
+    const DECK_COLUMNS: [&str; 9] = [
+        "ID",
+        "Deck Name",
+        "Commander(s)",
+        "Color",
+        "Format",
+        "Cards",
+        "Price",
+        "Modified",
+        "Labels",
+    ];
+
     pub struct OpenDeckView {
+        // Every deck, and the ones shown after filtering and sorting.
+        all: Vec<Deck>,
         decks: Vec<Deck>,
+        totals: HashMap<i32, DeckTotals>,
         state: TableState,
         deleting: bool,
+        copy: Option<CopyStep>,
+        typing: Option<DeckInput>,
+        input: String,
+        message: String,
+        filter: String,
+        // Index into DECK_COLUMNS.
+        sort: usize,
+        desc: bool,
+        list: DeckList,
+        trash_days: u32,
+    }
+
+    #[derive(Clone, PartialEq)]
+    enum TagManagerMode {
+        Browse,
+        Rename,
+        Merge,
+        Confirm(String, Option<String>),
+    }
+
+    pub struct TagManagerView {
+        sl: StatefulList<String>,
+        usage: HashMap<String, (u32, u32)>,
+        mode: TagManagerMode,
+        input: String,
+        message: String,
+    }
+
+    #[derive(Clone, PartialEq)]
+    enum SnapshotMode {
+        Browse,
+        Restore(i32, String),
+        Delete(i32, String),
+    }
+
+    pub struct SnapshotView {
+        did: i32,
+        title: String,
+        // The snapshots, newest first, after an entry for the deck as it is now.
+        vs: Vec<Option<Snapshot>>,
+        state: ListState,
+        // The version the diff is taken from, by index into vs.
+        base: usize,
+        diff: DeckDiff,
+        mode: SnapshotMode,
+        message: String,
+    }
+
+    pub struct NotesView {
+        did: i32,
+        title: String,
+        lines: Vec<String>,
+        row: usize,
+        col: usize,
     }
 
     pub struct DeckStatView {
@@ -1004,8 +2190,10 @@ pub mod views {
         price_data: Vec<(String, f64)>,
         type_data: Vec<u64>,
         tag_data: Vec<(String, u64)>,
+        targets: HashMap<String, TagTarget>,
         color_data: HashMap<String, usize>,
         recommendations: Vec<String>,
+        companion: Option<String>,
         dbc: Arc<Mutex<Connection>>,
         did: i32,
         fresh: bool,
@@ -1019,7 +2207,7 @@ pub mod views {
             n: String,
             oir: Option<bool>,
         ) -> SettingsView {
-            vt.sort();
+            sort_tags(&mut vt);
             vt.push(String::from("{Add new tag}"));
 
             SettingsView {
@@ -1037,7 +2225,6 @@ pub mod views {
         }
 
         pub fn handle_input(&mut self, c: KeyCode) -> ViewExit {
-            const BANNED_CHARS: [char; 2] = [' ', ';'];
             match c {
                 KeyCode::Esc => ViewExit::Cancel,
                 KeyCode::Right => {
@@ -1159,44 +2346,40 @@ pub mod views {
                 }
                 KeyCode::Enter => match self.section {
                     SettingsSection::Tags => {
-                        if self.vt[self.vpos] != *"main"
-                            || self
-                                .vt
-                                .iter()
-                                .filter(|&s| s == &String::from("main"))
-                                .count()
-                                > 1
-                        {
-                            self.section = SettingsSection::TagText;
-                            if self.vpos == self.vt.len() - 1 {
-                                self.vt[self.vpos] = String::new();
-                                self.wt = String::new();
-                            } else {
-                                self.wt = self.vt[self.vpos].clone();
-                            }
+                        self.section = SettingsSection::TagText;
+                        if self.vpos == self.vt.len() - 1 {
+                            self.vt[self.vpos] = String::new();
+                            self.wt = String::new();
+                        } else {
+                            self.wt = self.vt[self.vpos].clone();
                         }
                         ViewExit::Hold
                     }
+                    SettingsSection::TagText if !self.vt[self.vpos].is_empty() && !valid_tag(&self.vt[self.vpos]) => {
+                        ViewExit::Hold
+                    }
                     SettingsSection::TagText => {
                         self.tpos = 0;
                         self.section = SettingsSection::Tags;
-                        if self.vt[self.vpos].is_empty() {
-                            self.vt.remove(self.vpos);
-                            self.vch.push(TagChange::Delete(self.wt.clone()));
-                        }
+                        let new = std::mem::take(&mut self.vt[self.vpos]);
                         if self.wt.is_empty() {
-                            self.vch.push(TagChange::Insert(self.vt[self.vpos].clone()));
-                            self.vt.sort();
-                            self.vt.push(String::from("{Add new tag}"));
+                            if new.is_empty() {
+                                self.vt[self.vpos] = String::from("{Add new tag}");
+                            } else {
+                                self.vt[self.vpos] = new.clone();
+                                self.vch.push(TagChange::Insert(new));
+                                sort_tags(&mut self.vt);
+                                self.vt.push(String::from("{Add new tag}"));
+                            }
                         } else {
-                            self.vch.push(TagChange::Change(
-                                self.wt.clone(),
-                                self.vt[self.vpos].clone(),
-                            ));
-                            //This is the {Add new tag} temporary tag
-                            let s = self.vt.pop().unwrap();
-                            self.vt.sort();
-                            self.vt.push(s);
+                            self.vt[self.vpos] = self.wt.clone();
+                            if new.is_empty() {
+                                self.retag(None);
+                                self.vch.push(TagChange::Delete(self.wt.clone()));
+                            } else {
+                                self.retag(Some(&new));
+                                self.vch.push(TagChange::Change(self.wt.clone(), new));
+                            }
                         }
                         ViewExit::Hold
                     }
@@ -1217,17 +2400,10 @@ pub mod views {
                 KeyCode::Delete => {
                     match self.section {
                         SettingsSection::Tags => {
-                            if self.vpos < self.vt.len() - 1
-                                && (self.vt[self.vpos] != *"main"
-                                    || self
-                                        .vt
-                                        .iter()
-                                        .filter(|&s| s == &String::from("main"))
-                                        .count()
-                                        > 1)
-                            {
-                                self.vch.push(TagChange::Delete(self.vt[self.vpos].clone()));
-                                self.vt.remove(self.vpos);
+                            if self.vpos < self.vt.len() - 1 {
+                                self.wt = self.vt[self.vpos].clone();
+                                self.retag(None);
+                                self.vch.push(TagChange::Delete(self.wt.clone()));
                             }
                         }
                         SettingsSection::TagText => {
@@ -1248,14 +2424,7 @@ pub mod views {
                     ViewExit::Hold
                 }
                 KeyCode::Char(c) => {
-                    if self.section == SettingsSection::TagText && (self.vt[self.vpos] != *"main"
-                            || self
-                                .vt
-                                .iter()
-                                .filter(|&s| s == &String::from("main"))
-                                .count()
-                                > 1) && !BANNED_CHARS.contains(&c)
-                    {
+                    if self.section == SettingsSection::TagText && tag_char(c) {
                         self.vt[self.vpos].insert(self.tpos, c);
                         self.tpos += 1;
                     }
@@ -1265,11 +2434,19 @@ pub mod views {
             }
         }
 
+        // Renames or deletes the tag being edited and its children, leaving {Add new tag} last.
+        fn retag(&mut self, new: Option<&str>) {
+            let s = self.vt.pop().unwrap();
+            retag_list(&mut self.vt, &self.wt, new);
+            self.vt.push(s);
+            self.vpos = self.vpos.min(self.vt.len() - 1);
+        }
+
         pub fn render(&self, frame: &mut tui::Frame<CrosstermBackend<std::io::Stdout>>) {
             let mut st = self.vt.get(self.vpos).unwrap().clone();
             let mut vsp = Vec::new();
             for i in 0..self.vt.len() {
-                let mut s = self.vt[i].clone();
+                let mut s = tag_label(&self.vt[i], &self.vt);
                 s.push(' ');
                 if i == self.vpos {
                     let span = Span::styled(
@@ -1464,10 +2641,17 @@ pub mod views {
     }
 
     impl CreateDeckView {
-        pub fn new() -> Self {
+        pub fn template(&self) -> Option<String> {
+            self.template.map(|i| self.templates[i].clone())
+        }
+
+        pub fn new(templates: Vec<String>) -> Self {
             Self {
                 section: CreateDeckSection::Title,
                 title: String::new(),
+                dt: DeckType::default(),
+                templates,
+                template: None,
                 com1: String::new(),
                 com2: String::new(),
                 vcn: Vec::new(),
@@ -1477,8 +2661,42 @@ pub mod views {
         }
 
         pub fn handle_input(&mut self, c: KeyCode, conn: &Connection) -> ViewExit {
+            if self.section == CreateDeckSection::Format {
+                let i = DeckType::ALL.iter().position(|dt| dt == &self.dt).unwrap();
+                let n = DeckType::ALL.len();
+                match c {
+                    KeyCode::Esc => return ViewExit::Cancel,
+                    KeyCode::Up | KeyCode::Left => self.dt = DeckType::ALL[(i + n - 1) % n],
+                    KeyCode::Down | KeyCode::Right => self.dt = DeckType::ALL[(i + 1) % n],
+                    KeyCode::Enter if self.templates.is_empty() => {
+                        self.section = CreateDeckSection::PrimaryCommander
+                    }
+                    KeyCode::Enter => self.section = CreateDeckSection::Template,
+                    KeyCode::BackTab => self.section = CreateDeckSection::Title,
+                    _ => {}
+                }
+                return ViewExit::Hold;
+            }
+            if self.section == CreateDeckSection::Template {
+                // Cycles through the templates and a final "no template" entry.
+                let n = self.templates.len() + 1;
+                let i = self.template.unwrap_or(n - 1);
+                let pick = |i: usize| if i == n - 1 { None } else { Some(i) };
+                match c {
+                    KeyCode::Esc => return ViewExit::Cancel,
+                    KeyCode::Up | KeyCode::Left => self.template = pick((i + n - 1) % n),
+                    KeyCode::Down | KeyCode::Right => self.template = pick((i + 1) % n),
+                    KeyCode::Enter => self.section = CreateDeckSection::PrimaryCommander,
+                    KeyCode::BackTab => self.section = CreateDeckSection::Format,
+                    _ => {}
+                }
+                return ViewExit::Hold;
+            }
+
             let active = match self.section {
-                CreateDeckSection::Title => &mut self.title,
+                CreateDeckSection::Title
+                | CreateDeckSection::Format
+                | CreateDeckSection::Template => &mut self.title,
                 CreateDeckSection::PrimaryCommander => &mut self.com1,
                 CreateDeckSection::SecondaryCommander => &mut self.com2,
             };
@@ -1557,20 +2775,36 @@ pub mod views {
                 KeyCode::Enter => {
                     self.tpos = 0;
                     match self.section {
-                        CreateDeckSection::Title => {
-                            self.section = CreateDeckSection::PrimaryCommander
-                        }
+                        CreateDeckSection::Title => self.section = CreateDeckSection::Format,
+                        CreateDeckSection::Format | CreateDeckSection::Template => {}
                         CreateDeckSection::PrimaryCommander => {
                             if let Some(i) = self.vpos.selected() {
                                 let c = rcfn(conn, &self.vcn[i], None).unwrap();
-                                match c.is_commander() {
-                                    super::CommanderType::Default => {
-                                        let did =
-                                            ideck(conn, &self.title, &c.name, None, "Commander")
-                                                .unwrap();
+                                let ct = match self.dt {
+                                    DeckType::Oathbreaker => super::CommanderType::Partner,
+                                    DeckType::Constructed => super::CommanderType::Default,
+                                    _ => c.is_commander(),
+                                };
+                                match ct {
+                                    // The search list only offers cards that can lead the deck in
+                                    // its format, which is wider than is_commander in some formats.
+                                    super::CommanderType::Default
+                                    | super::CommanderType::Invalid => {
+                                        let did = ideck(
+                                            conn,
+                                            &self.title,
+                                            &c.name,
+                                            None,
+                                            &self.dt.to_string(),
+                                        )
+                                        .unwrap();
                                         return ViewExit::NewDeck(did);
                                     }
-                                    super::CommanderType::Partner => {
+                                    super::CommanderType::Partner
+                                    | super::CommanderType::FriendsForever
+                                    | super::CommanderType::ChooseBackground
+                                    | super::CommanderType::DoctorsCompanion
+                                    | super::CommanderType::Doctor => {
                                         self.com1 = c.name;
                                         self.section = CreateDeckSection::SecondaryCommander;
                                         self.vcn = Vec::new();
@@ -1583,7 +2817,6 @@ pub mod views {
                                         self.vcn = vec![scn];
                                         self.vpos.select(Some(0));
                                     }
-                                    super::CommanderType::Invalid => {}
                                 }
                             }
                         }
@@ -1594,14 +2827,15 @@ pub mod views {
                                     &self.title,
                                     &self.com1,
                                     Some(self.vcn[i].clone()),
-                                    "Commander",
+                                    &self.dt.to_string(),
                                 )
                                 .unwrap();
                                 return ViewExit::NewDeck(did);
                             }
                             None => {
-                                let did = ideck(conn, &self.title, &self.com1, None, "Commander")
-                                    .unwrap();
+                                let did =
+                                    ideck(conn, &self.title, &self.com1, None, &self.dt.to_string())
+                                        .unwrap();
                                 return ViewExit::NewDeck(did);
                             }
                         },
@@ -1640,6 +2874,13 @@ pub mod views {
                     .split(cut[0]),
             );
 
+            let cut = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Min(18), Constraint::Length(20), Constraint::Length(20)])
+                .split(vrct[0]);
+            vrct[0] = cut[0];
+
+            let (l1, l2) = self.dt.labels();
             let vli: Vec<ListItem> = self
                 .vcn
                 .iter()
@@ -1648,7 +2889,7 @@ pub mod views {
             let list = List::new(vli)
                 .block(
                     Block::default()
-                        .title("Select Commander")
+                        .title(format!("Select {}", l1))
                         .borders(Borders::ALL),
                 )
                 .style(Style::default().fg(Color::White))
@@ -1657,58 +2898,68 @@ pub mod views {
                         .add_modifier(Modifier::ITALIC)
                         .fg(Color::Yellow),
                 );
-            let text = self.rstyle();
 
-            let (title, com1, com2) = match self.section {
+            let active = Style::default().fg(Color::Yellow);
+            let mut title = Paragraph::new(self.title.clone())
+                .block(Block::default().borders(Borders::ALL).title("Deck Name"));
+            let mut format = Paragraph::new(self.dt.to_string())
+                .block(Block::default().borders(Borders::ALL).title("Format"));
+            let tname = match self.template {
+                Some(i) => self.templates[i].clone(),
+                None => String::from("None"),
+            };
+            let mut template = Paragraph::new(tname.clone())
+                .block(Block::default().borders(Borders::ALL).title("Tag Template"));
+            let mut com1 = Paragraph::new(self.com1.clone())
+                .block(Block::default().borders(Borders::ALL).title(l1));
+            let mut com2 = Paragraph::new(self.com2.clone())
+                .block(Block::default().borders(Borders::ALL).title(l2));
+            match self.section {
                 CreateDeckSection::Title => {
-                    let text = self.rstyle();
-                    let title = Paragraph::new(text).block(
+                    title = Paragraph::new(self.rstyle()).block(
                         Block::default()
                             .borders(Borders::ALL)
                             .title("Deck Name")
-                            .style(Style::default().fg(Color::Yellow)),
+                            .style(active),
                     );
-                    let com1 = Paragraph::new(self.com1.clone())
-                        .block(Block::default().borders(Borders::ALL).title("Commander"));
-                    let com2 = Paragraph::new(self.com2.clone()).block(
+                }
+                CreateDeckSection::Format => {
+                    format = Paragraph::new(format!("< {} >", self.dt)).block(
                         Block::default()
                             .borders(Borders::ALL)
-                            .title("Secondary Commander"),
+                            .title("Format")
+                            .style(active),
                     );
-                    (title, com1, com2)
                 }
-                CreateDeckSection::PrimaryCommander => {
-                    let title = Paragraph::new(self.title.clone())
-                        .block(Block::default().borders(Borders::ALL).title("Deck Name"));
-                    let com1 = Paragraph::new(text).block(
+                CreateDeckSection::Template => {
+                    template = Paragraph::new(format!("< {} >", tname)).block(
                         Block::default()
                             .borders(Borders::ALL)
-                            .title("Commander")
-                            .style(Style::default().fg(Color::Yellow)),
+                            .title("Tag Template")
+                            .style(active),
                     );
-                    let com2 = Paragraph::new(self.com2.clone()).block(
+                }
+                CreateDeckSection::PrimaryCommander => {
+                    com1 = Paragraph::new(self.rstyle()).block(
                         Block::default()
                             .borders(Borders::ALL)
-                            .title("Secondary Commander"),
+                            .title(l1)
+                            .style(active),
                     );
-                    (title, com1, com2)
                 }
                 CreateDeckSection::SecondaryCommander => {
-                    let title = Paragraph::new(self.title.clone())
-                        .block(Block::default().borders(Borders::ALL).title("Deck Name"));
-                    let com1 = Paragraph::new(self.com1.clone())
-                        .block(Block::default().borders(Borders::ALL).title("Commander"));
-                    let com2 = Paragraph::new(text).block(
+                    com2 = Paragraph::new(self.rstyle()).block(
                         Block::default()
                             .borders(Borders::ALL)
-                            .title("Secondary Commander")
-                            .style(Style::default().fg(Color::Yellow)),
+                            .title(l2)
+                            .style(active),
                     );
-                    (title, com1, com2)
                 }
             };
 
             frame.render_widget(title, vrct[0]);
+            frame.render_widget(format, cut[1]);
+            frame.render_widget(template, cut[2]);
             frame.render_stateful_widget(list, vrct[1], &mut self.vpos.clone());
             frame.render_widget(com1, vrct[2]);
             if self.section == CreateDeckSection::SecondaryCommander || !self.com2.is_empty() {
@@ -1717,10 +2968,14 @@ pub mod views {
         }
 
         fn uvcn(&mut self, active: &str, conn: &Connection) {
-            let rvcn = if self.section == CreateDeckSection::SecondaryCommander {
-                rvcnfnp(conn, active)
+            let rvcn = if self.section != CreateDeckSection::SecondaryCommander {
+                rvcnfn(conn, active, self.dt)
+            } else if self.dt == DeckType::Oathbreaker {
+                let c = rcfn(conn, &self.com1, None).unwrap();
+                rvssfn(conn, active, &c.color_identity)
             } else {
-                rvcnfn(conn, active)
+                let c = rcfn(conn, &self.com1, None).unwrap();
+                rvcnfnp(conn, active, self.dt, &c.is_commander())
             };
             self.vcn = match rvcn {
                 Ok(vs) => {
@@ -1740,7 +2995,9 @@ pub mod views {
 
         fn rstyle(&self) -> Spans {
             let mut st = match self.section {
-                CreateDeckSection::Title => self.title.clone(),
+                CreateDeckSection::Title
+                | CreateDeckSection::Format
+                | CreateDeckSection::Template => self.title.clone(),
                 CreateDeckSection::PrimaryCommander => self.com1.clone(),
                 CreateDeckSection::SecondaryCommander => self.com2.clone(),
             };
@@ -1773,24 +3030,105 @@ pub mod views {
     }
 
     impl OpenDeckView {
-        pub fn new() -> Self {
+        pub fn new(trash_days: u32) -> Self {
             Self {
+                all: Vec::new(),
                 decks: Vec::new(),
+                totals: HashMap::new(),
                 state: Default::default(),
                 deleting: false,
+                copy: None,
+                typing: None,
+                input: String::new(),
+                message: String::new(),
+                filter: String::new(),
+                sort: 0,
+                desc: false,
+                list: DeckList::Decks,
+                trash_days,
             }
         }
 
         pub fn init(&mut self, conn: &Connection) {
-            let decks = rvd(conn).unwrap();
-            if !decks.is_empty() {
-                self.state.select(Some(0));
-            }
-            self.decks = decks;
+            self.all = rvd(conn).unwrap();
+            self.totals = rvdt(conn).unwrap_or_default();
             self.deleting = false;
+            self.copy = None;
+            self.typing = None;
+            self.refresh();
+        }
+
+        fn rtotals(&self, did: i32) -> DeckTotals {
+            self.totals.get(&did).copied().unwrap_or_default()
+        }
+
+        // Applies the filter and sort order, keeping the selected deck selected if it's still shown.
+        fn refresh(&mut self) {
+            let selected = self.state.selected().and_then(|i| self.decks.get(i)).map(|d| d.id);
+            let words: Vec<String> = self.filter.to_lowercase().split_whitespace().map(String::from).collect();
+            let mut decks: Vec<Deck> = self
+                .all
+                .iter()
+                .filter(|d| match self.list {
+                    DeckList::Decks => !d.archived && d.deleted.is_none(),
+                    DeckList::Archived => d.archived && d.deleted.is_none(),
+                    DeckList::Trash => d.deleted.is_some(),
+                })
+                .filter(|d| {
+                    let s = format!(
+                        "{} {} {} {} {}",
+                        d.name,
+                        rcoms(d),
+                        d.color,
+                        d.deck_type,
+                        d.labels.join(" ")
+                    )
+                    .to_lowercase();
+                    words.iter().all(|w| s.contains(w.as_str()))
+                })
+                .cloned()
+                .collect();
+            decks.sort_by(|a, b| {
+                let (ta, tb) = (self.rtotals(a.id), self.rtotals(b.id));
+                let o = match self.sort {
+                    0 => a.id.cmp(&b.id),
+                    1 => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+                    2 => rcoms(a).cmp(&rcoms(b)),
+                    3 => a.color.cmp(&b.color),
+                    4 => a.deck_type.to_string().cmp(&b.deck_type.to_string()),
+                    5 => ta.cards.cmp(&tb.cards),
+                    6 => ta.price.partial_cmp(&tb.price).unwrap_or(std::cmp::Ordering::Equal),
+                    7 => a.modified.cmp(&b.modified),
+                    _ => a.labels.cmp(&b.labels),
+                };
+                if self.desc {
+                    o.reverse()
+                } else {
+                    o
+                }
+            });
+            self.decks = decks;
+            let i = selected
+                .and_then(|did| self.decks.iter().position(|d| d.id == did))
+                .or(if self.decks.is_empty() { None } else { Some(0) });
+            self.state.select(i);
+        }
+
+        pub fn smessage(&mut self, s: String) {
+            self.message = s;
         }
 
         pub fn handle_input(&mut self, c: KeyCode) -> OpenDeckViewExit {
+            if !self.message.is_empty() {
+                self.message.clear();
+                return OpenDeckViewExit::Hold;
+            }
+            if let Some(step) = self.copy.clone() {
+                return self.handle_copy(c, step);
+            }
+            if let Some(input) = self.typing {
+                return self.handle_typing(c, input);
+            }
             match c {
                 KeyCode::Up => self.previous(),
                 KeyCode::Down => self.next(),
@@ -1805,13 +3143,60 @@ pub mod views {
                         self.deleting = false;
                         if let Some(d) = self.remove() {
                             let did = d.id;
-                            return OpenDeckViewExit::DeleteDeck(did);
+                            return match self.list {
+                                DeckList::Trash => OpenDeckViewExit::DeleteDeck(did),
+                                _ => OpenDeckViewExit::TrashDeck(did),
+                            };
                         }
-                    } else if let Some(i) = self.state.selected() {
+                    } else if let Some(i) = self.state.selected().filter(|_| self.list != DeckList::Trash) {
                         let did = self.decks.get(i).unwrap().id;
                         return OpenDeckViewExit::OpenDeck(did);
                     }
                 }
+                KeyCode::Char('c') if self.list != DeckList::Trash => {
+                    if let Some(i) = self.state.selected() {
+                        self.input = format!("{} (copy)", self.decks[i].name);
+                        self.copy = Some(CopyStep::Name);
+                    }
+                }
+                KeyCode::Char('l') if self.list != DeckList::Trash => {
+                    if let Some(i) = self.state.selected() {
+                        self.input = self.decks[i].labels.join(", ");
+                        self.typing = Some(DeckInput::Labels(self.decks[i].id));
+                    }
+                }
+                KeyCode::Char('/') => self.typing = Some(DeckInput::Filter),
+                KeyCode::Char('a') if self.list != DeckList::Trash => {
+                    if let Some(i) = self.state.selected() {
+                        let d = &self.decks[i];
+                        return OpenDeckViewExit::ArchiveDeck(d.id, !d.archived);
+                    }
+                }
+                KeyCode::Char('r') if self.list == DeckList::Trash => {
+                    if let Some(i) = self.state.selected() {
+                        return OpenDeckViewExit::RestoreDeck(self.decks[i].id);
+                    }
+                }
+                KeyCode::Tab => {
+                    self.list = match self.list {
+                        DeckList::Decks => DeckList::Archived,
+                        DeckList::Archived => DeckList::Trash,
+                        DeckList::Trash => DeckList::Decks,
+                    };
+                    self.state.select(None);
+                    self.refresh();
+                }
+                // Pressing a column's number sorts by it, or reverses the order if it already does.
+                KeyCode::Char(n @ '1'..='9') => {
+                    let col = n as usize - '1' as usize;
+                    self.desc = col == self.sort && !self.desc;
+                    self.sort = col;
+                    self.refresh();
+                }
+                KeyCode::Esc if !self.filter.is_empty() => {
+                    self.filter.clear();
+                    self.refresh();
+                }
                 KeyCode::Esc => return OpenDeckViewExit::Cancel,
                 _ => {}
             }
@@ -1819,9 +3204,87 @@ pub mod views {
             OpenDeckViewExit::Hold
         }
 
+        fn handle_typing(&mut self, c: KeyCode, input: DeckInput) -> OpenDeckViewExit {
+            match (c, input) {
+                (KeyCode::Esc, DeckInput::Filter) => {
+                    self.filter.clear();
+                    self.typing = None;
+                    self.refresh();
+                }
+                (KeyCode::Enter, DeckInput::Filter) => self.typing = None,
+                (KeyCode::Backspace, DeckInput::Filter) => {
+                    self.filter.pop();
+                    self.refresh();
+                }
+                (KeyCode::Char(c), DeckInput::Filter) => {
+                    self.filter.push(c);
+                    self.refresh();
+                }
+                (KeyCode::Esc, _) => self.typing = None,
+                (KeyCode::Backspace, _) => {
+                    self.input.pop();
+                }
+                (KeyCode::Char(c), _) => self.input.push(c),
+                (KeyCode::Enter, DeckInput::Labels(did)) => {
+                    self.typing = None;
+                    let labels = self
+                        .input
+                        .split(',')
+                        .map(|s| s.trim().to_string())
+                        .filter(|s| !s.is_empty())
+                        .unique()
+                        .collect();
+                    return OpenDeckViewExit::Labels(did, labels);
+                }
+                _ => {}
+            }
+            OpenDeckViewExit::Hold
+        }
+
+        fn handle_copy(&mut self, c: KeyCode, step: CopyStep) -> OpenDeckViewExit {
+            match c {
+                KeyCode::Esc => self.copy = None,
+                KeyCode::Backspace => {
+                    self.input.pop();
+                }
+                KeyCode::Char(c) => self.input.push(c),
+                KeyCode::Enter => {
+                    let input = self.input.trim().to_string();
+                    self.input.clear();
+                    match step {
+                        CopyStep::Name if input.is_empty() => self.copy = None,
+                        CopyStep::Name => self.copy = Some(CopyStep::Commander(input)),
+                        CopyStep::Commander(name) => {
+                            self.copy = None;
+                            if let Some(i) = self.state.selected() {
+                                let coms = if input.is_empty() { None } else { Some(input) };
+                                return OpenDeckViewExit::CopyDeck(self.decks[i].id, name, coms);
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+            OpenDeckViewExit::Hold
+        }
+
         pub fn render(&self, frame: &mut tui::Frame<CrosstermBackend<std::io::Stdout>>) {
+            let cut = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(5), Constraint::Length(8)].as_ref())
+                .split(frame.size());
             let table = self.rdt();
-            frame.render_stateful_widget(table, frame.size(), &mut self.state.clone());
+            frame.render_stateful_widget(table, cut[0], &mut self.state.clone());
+
+            let notes = match self.state.selected() {
+                Some(i) => self.decks[i].notes.clone(),
+                None => String::new(),
+            };
+            let notes = Paragraph::new(notes)
+                .block(Block::default().title("Notes").borders(Borders::ALL))
+                .style(Style::default().fg(Color::White))
+                .wrap(Wrap { trim: false });
+            frame.render_widget(notes, cut[1]);
 
             if self.deleting {
                 if let Some(i) = self.state.selected() {
@@ -1831,7 +3294,13 @@ pub mod views {
                     //     .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
                     //     .split(frame.size());
                     let title = "Confirm Deletion";
-                    let message = format!("Are you sure you want to delete the below deck?\n{deck}\nPress Enter to confirm.");
+                    let message = match self.list {
+                        DeckList::Trash => format!("Are you sure you want to delete the below deck for good?\n{deck}\nPress Enter to confirm."),
+                        _ => format!(
+                            "Move the below deck to the trash? It can be restored for {} days.\n{deck}\nPress Enter to confirm.",
+                            self.trash_days
+                        ),
+                    };
                     let err_message = Paragraph::new(message)
                         .block(Block::default().borders(Borders::ALL).title(title));
                     let area = centered_rect(60, frame.size());
@@ -1839,6 +3308,35 @@ pub mod views {
                     frame.render_widget(err_message, area);
                 };
             }
+
+            if let Some(DeckInput::Labels(_)) = self.typing {
+                let message = format!(
+                    "Labels for this deck, separated by commas:\n{}",
+                    self.input
+                );
+                let popup = Paragraph::new(message)
+                    .block(Block::default().borders(Borders::ALL).title("Deck Labels"))
+                    .wrap(Wrap { trim: false });
+                let area = centered_rect(60, frame.size());
+                frame.render_widget(tui::widgets::Clear, area);
+                frame.render_widget(popup, area);
+            }
+
+            let message = match &self.copy {
+                Some(CopyStep::Name) => format!("Name of the copy:\n{}", self.input),
+                Some(CopyStep::Commander(_)) => format!(
+                    "New commander(s), separated by |, or leave empty to keep them:\n{}",
+                    self.input
+                ),
+                None if !self.message.is_empty() => self.message.clone(),
+                None => return,
+            };
+            let popup = Paragraph::new(message)
+                .block(Block::default().borders(Borders::ALL).title("Duplicate Deck"))
+                .wrap(Wrap { trim: false });
+            let area = centered_rect(60, frame.size());
+            frame.render_widget(tui::widgets::Clear, area);
+            frame.render_widget(popup, area);
         }
 
         fn remove(&mut self) -> Option<Deck> {
@@ -1847,6 +3345,7 @@ pub mod views {
             }
             let a = self.state.selected().unwrap();
             let d = self.decks.remove(a);
+            self.all.retain(|deck| deck.id != d.id);
 
             if self.decks.is_empty() {
                 self.state = TableState::default();
@@ -1893,12 +3392,13 @@ pub mod views {
 
         fn rdt(&self) -> Table {
             let decks = self.decks.clone();
-            let headers = Row::new(vec![
-                Cell::from("ID"),
-                Cell::from("Deck Name"),
-                Cell::from("Commander(s)"),
-                Cell::from("Color"),
-            ])
+            let headers = Row::new(DECK_COLUMNS.iter().enumerate().map(|(i, h)| {
+                match (i == self.sort, self.desc) {
+                    (true, false) => Cell::from(format!("{} ▲", h)),
+                    (true, true) => Cell::from(format!("{} ▼", h)),
+                    _ => Cell::from(*h),
+                }
+            }))
             .style(
                 Style::default()
                     .add_modifier(Modifier::BOLD)
@@ -1912,11 +3412,17 @@ pub mod views {
                     None => (1, String::new()),
                 };
 
+                let totals = self.rtotals(deck.id);
                 let r = Row::new(vec![
                     Cell::from(deck.id.to_string()),
                     Cell::from(deck.name),
                     Cell::from(format!("{}\n{}", deck.commander.name, com2)),
                     Cell::from(deck.color),
+                    Cell::from(deck.deck_type.to_string()),
+                    Cell::from(totals.cards.to_string()),
+                    Cell::from(format!("{:.2}", totals.price)),
+                    Cell::from(deck.modified.chars().take(16).collect::<String>()),
+                    Cell::from(deck.labels.join(", ")),
                 ])
                 .height(height)
                 .style(Style::default());
@@ -1924,14 +3430,35 @@ pub mod views {
                 rows.push(r);
             }
 
+            let title = match self.typing {
+                Some(DeckInput::Filter) => format!("Filter: {}_", self.filter),
+                _ if !self.filter.is_empty() => format!("Filter: {}    Esc: clear filter", self.filter),
+                _ => match self.list {
+                    DeckList::Decks => String::from(
+                        "Decks    Enter: open  c: duplicate  l: labels  a: archive  /: filter  1-9: sort  Delete: trash  Tab: archived",
+                    ),
+                    DeckList::Archived => String::from(
+                        "Archived    Enter: open  c: duplicate  l: labels  a: unarchive  /: filter  1-9: sort  Delete: trash  Tab: trash",
+                    ),
+                    DeckList::Trash => format!(
+                        "Trash (emptied after {} days)    r: restore  /: filter  1-9: sort  Delete: delete for good  Tab: decks",
+                        self.trash_days
+                    ),
+                },
+            };
             let table = Table::new(rows)
                 .header(headers)
-                .block(Block::default().borders(Borders::ALL))
+                .block(Block::default().title(title).borders(Borders::ALL))
                 .widths(&[
                     Constraint::Length(4),
-                    Constraint::Percentage(40),
-                    Constraint::Percentage(40),
+                    Constraint::Percentage(20),
+                    Constraint::Percentage(25),
                     Constraint::Length(7),
+                    Constraint::Length(12),
+                    Constraint::Length(7),
+                    Constraint::Length(9),
+                    Constraint::Length(16),
+                    Constraint::Min(8),
                 ])
                 .column_spacing(1)
                 .highlight_style(
@@ -1944,50 +3471,574 @@ pub mod views {
         }
     }
 
-    impl DeckView {
-        pub fn new(
-            did: i32,
-            settings: Rc<RefCell<DeckSettings>>,
-            dbc: Arc<Mutex<Connection>>,
-        ) -> DeckView {
-            let deck = rdfdid(&dbc.lock().unwrap(), did).unwrap();
-            let cf = CardFilter::from(
-                did,
-                &deck.color,
-                settings.borrow().df,
-                settings.borrow().ordering,
-            );
-            let st = settings
-                .borrow()
-                .tags
-                .iter()
-                .position(|s| s == &String::from("main"))
-                .unwrap();
-            let vcdec = rvcnfcf(&dbc.lock().unwrap(), &cf.make_query(false, "")).unwrap();
+    impl TagManagerView {
+        pub fn new(conn: &Connection, tags: Vec<String>, message: String) -> Self {
+            let usage: HashMap<String, (u32, u32)> = rvtu(conn)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(t, cards, decks)| (t, (cards, decks)))
+                .collect();
+            let mut vt: Vec<String> = usage.keys().cloned().chain(tags).unique().collect();
+            sort_tags(&mut vt);
+            let mut sl = StatefulList::with_items(vt);
+            sl.next();
 
-            let mut slde = StatefulList::with_items(vcdec.clone());
-            let name = slde.next().unwrap();
-            let ac = Some(rcfn(&dbc.lock().unwrap(), &name, Some(cf.did)).unwrap());
-            let sldb = StatefulList::default();
+            Self {
+                sl,
+                usage,
+                mode: TagManagerMode::Browse,
+                input: String::new(),
+                message,
+            }
+        }
 
-            DeckView {
-                omni: String::new(),
-                omniprev: String::new(),
-                omnipos: 0,
-                vsomni: Vec::new(),
-                slde,
-                sldb,
-                vcdec,
-                st,
+        pub fn handle_input(&mut self, c: KeyCode, conn: &Connection) -> TagManagerExit {
+            match self.mode.clone() {
+                TagManagerMode::Browse => {
+                    let tag = self.sl.get().cloned();
+                    match (c, tag) {
+                        (KeyCode::Esc, _) => return TagManagerExit::Cancel,
+                        (KeyCode::Up, _) => {
+                            self.sl.previous();
+                        }
+                        (KeyCode::Down, _) => {
+                            self.sl.next();
+                        }
+                        (KeyCode::Char('r'), Some(tag)) => {
+                            self.input = tag;
+                            self.mode = TagManagerMode::Rename;
+                        }
+                        (KeyCode::Char('m'), Some(_)) => {
+                            self.input.clear();
+                            self.mode = TagManagerMode::Merge;
+                        }
+                        (KeyCode::Char('d') | KeyCode::Delete, Some(tag)) => {
+                            let (cards, decks) = rqtiadc(conn, &tag).unwrap_or_default();
+                            self.message = format!(
+                                "Delete {} and its children from {} card(s) in {} deck(s)? (y/n)",
+                                tag, cards, decks
+                            );
+                            self.mode = TagManagerMode::Confirm(tag, None);
+                        }
+                        _ => {}
+                    }
+                }
+                TagManagerMode::Rename | TagManagerMode::Merge => match c {
+                    KeyCode::Esc => {
+                        self.message.clear();
+                        self.mode = TagManagerMode::Browse;
+                    }
+                    KeyCode::Backspace => {
+                        self.input.pop();
+                    }
+                    KeyCode::Char(c) if tag_char(c) => self.input.push(c),
+                    KeyCode::Enter => {
+                        let tag = self.sl.get().cloned().unwrap_or_default();
+                        let new = self.input.trim_matches('/').to_string();
+                        let exists = self.sl.items.contains(&new);
+                        if new.is_empty() || new == tag {
+                            self.mode = TagManagerMode::Browse;
+                        } else if !valid_tag(&new) {
+                            self.message = format!("{} has an empty part between slashes.", new);
+                        } else if self.mode == TagManagerMode::Merge && !exists {
+                            self.message = format!("There is no tag named {} to merge into.", new);
+                        } else if tag_under(&new, &tag).is_some() {
+                            self.message = format!("{} can't be moved under itself.", tag);
+                        } else {
+                            let (cards, decks) = rqtiadc(conn, &tag).unwrap_or_default();
+                            self.message = format!(
+                                "{} {} {} {} in {} card(s) across {} deck(s)? (y/n)",
+                                if exists { "Merge" } else { "Rename" },
+                                tag,
+                                if exists { "into" } else { "to" },
+                                new,
+                                cards,
+                                decks
+                            );
+                            self.mode = TagManagerMode::Confirm(tag, Some(new));
+                        }
+                    }
+                    _ => {}
+                },
+                TagManagerMode::Confirm(tag, new) => {
+                    self.mode = TagManagerMode::Browse;
+                    if c == KeyCode::Char('y') {
+                        return TagManagerExit::Apply(tag, new);
+                    }
+                    self.message.clear();
+                }
+            }
+            TagManagerExit::Hold
+        }
+
+        pub fn render(&self, frame: &mut tui::Frame<CrosstermBackend<std::io::Stdout>>) {
+            let vli: Vec<ListItem> = self
+                .sl
+                .items
+                .iter()
+                .map(|t| {
+                    let (cards, decks) = self.usage.get(t).copied().unwrap_or_default();
+                    ListItem::new(format!(
+                        "{} ({} card(s) in {} deck(s))",
+                        tag_label(t, &self.sl.items),
+                        cards,
+                        decks
+                    ))
+                })
+                .collect();
+            let list = List::new(vli)
+                .block(Block::default().title("Manage Tags").borders(Borders::ALL))
+                .highlight_style(
+                    Style::default()
+                        .add_modifier(Modifier::BOLD)
+                        .fg(Color::Cyan),
+                );
+
+            let text = match self.mode {
+                TagManagerMode::Rename => format!("Rename to: {}", self.input),
+                TagManagerMode::Merge => format!("Merge into: {}", self.input),
+                _ if !self.message.is_empty() => self.message.clone(),
+                _ => String::from(
+                    "r: rename    m: merge into another tag    d: delete    Esc: back",
+                ),
+            };
+            let mut text = vec![Spans::from(text)];
+            if matches!(self.mode, TagManagerMode::Rename | TagManagerMode::Merge) && !self.message.is_empty() {
+                text.push(Spans::from(Span::styled(
+                    self.message.clone(),
+                    Style::default().fg(Color::Red),
+                )));
+            }
+            let pm = Paragraph::new(text).block(Block::default().borders(Borders::ALL));
+
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(3), Constraint::Length(4)].as_ref())
+                .split(frame.size());
+            frame.render_stateful_widget(list, chunks[0], &mut self.sl.state.clone());
+            frame.render_widget(pm, chunks[1]);
+        }
+    }
+
+    impl SnapshotView {
+        pub fn new(conn: &Connection, did: i32) -> Self {
+            let title = rdfdid(conn, did)
+                .map(|deck| format!("Snapshots of {}", deck.name))
+                .unwrap_or_else(|_| String::from("Snapshots"));
+            let mut sv = Self {
+                did,
+                title,
+                vs: Vec::new(),
+                state: ListState::default(),
+                base: 0,
+                diff: DeckDiff::default(),
+                mode: SnapshotMode::Browse,
+                message: String::new(),
+            };
+            sv.reload(conn);
+            // Start by comparing the newest snapshot with the deck as it is now.
+            if sv.vs.len() > 1 {
+                sv.base = 1;
+                sv.state.select(Some(0));
+            }
+            sv.udiff(conn);
+            sv
+        }
+
+        fn reload(&mut self, conn: &Connection) {
+            self.vs = std::iter::once(None)
+                .chain(rvsfd(conn, self.did).unwrap_or_default().into_iter().map(Some))
+                .collect();
+            if self.base >= self.vs.len() {
+                self.base = 0;
+            }
+            let i = self.state.selected().unwrap_or(0).min(self.vs.len() - 1);
+            self.state.select(Some(i));
+        }
+
+        fn sid(&self, i: usize) -> Option<i32> {
+            self.vs.get(i).cloned().flatten().map(|s| s.id)
+        }
+
+        fn udiff(&mut self, conn: &Connection) {
+            let to = self.state.selected().unwrap_or(0);
+            self.diff = rdiff(conn, self.did, self.sid(self.base), self.sid(to)).unwrap_or_default();
+        }
+
+        fn label(&self, i: usize) -> String {
+            match &self.vs[i] {
+                None => String::from("Current deck"),
+                Some(s) => format!("{} ({})", s.name, s.created),
+            }
+        }
+
+        pub fn handle_input(&mut self, c: KeyCode, conn: &Connection) -> SnapshotExit {
+            let i = self.state.selected().unwrap_or(0);
+            match self.mode.clone() {
+                SnapshotMode::Browse => {
+                    self.message.clear();
+                    let snapshot = self.vs[i].clone();
+                    match (c, snapshot) {
+                        (KeyCode::Esc, _) => return SnapshotExit::Cancel,
+                        (KeyCode::Up, _) => {
+                            self.state.select(Some(if i == 0 { self.vs.len() - 1 } else { i - 1 }));
+                            self.udiff(conn);
+                        }
+                        (KeyCode::Down, _) => {
+                            self.state.select(Some((i + 1) % self.vs.len()));
+                            self.udiff(conn);
+                        }
+                        (KeyCode::Char('b'), _) => {
+                            self.base = i;
+                            self.udiff(conn);
+                        }
+                        (KeyCode::Char('r'), Some(s)) => {
+                            self.message = format!(
+                                "Put the deck back to {}? The change to its cards can be undone. (y/n)",
+                                s.name
+                            );
+                            self.mode = SnapshotMode::Restore(s.id, s.name);
+                        }
+                        (KeyCode::Char('d') | KeyCode::Delete, Some(s)) => {
+                            self.message = format!("Delete the snapshot {}? (y/n)", s.name);
+                            self.mode = SnapshotMode::Delete(s.id, s.name);
+                        }
+                        _ => {}
+                    }
+                }
+                SnapshotMode::Restore(sid, name) => {
+                    self.mode = SnapshotMode::Browse;
+                    self.message.clear();
+                    if c == KeyCode::Char('y') {
+                        match usfs(conn, self.did, sid) {
+                            Ok(()) => return SnapshotExit::Restored(name),
+                            Err(e) => self.message = format!("Couldn't restore {}: {}", name, e),
+                        }
+                    }
+                }
+                SnapshotMode::Delete(sid, name) => {
+                    self.mode = SnapshotMode::Browse;
+                    self.message.clear();
+                    if c == KeyCode::Char('y') {
+                        match dsfd(conn, sid) {
+                            Ok(()) => {
+                                self.base = 0;
+                                self.reload(conn);
+                                self.udiff(conn);
+                            }
+                            Err(e) => self.message = format!("Couldn't delete {}: {}", name, e),
+                        }
+                    }
+                }
+            }
+            SnapshotExit::Hold
+        }
+
+        fn rdiff_text(&self) -> Vec<Spans<'_>> {
+            let to = self.state.selected().unwrap_or(0);
+            let mut text = vec![
+                Spans::from(format!("From: {}", self.label(self.base))),
+                Spans::from(format!("To:   {}", self.label(to))),
+                Spans::from(""),
+            ];
+            let d = &self.diff;
+            if d.is_empty() {
+                text.push(Spans::from("No changes."));
+                return text;
+            }
+            if let Some((old, new)) = &d.commanders {
+                text.push(Spans::from(format!("Commanders: {} -> {}", old, new)));
+            }
+            if d.notes {
+                text.push(Spans::from("Notes changed."));
+            }
+            let green = Style::default().fg(Color::Green);
+            let red = Style::default().fg(Color::Red);
+            for cn in &d.added {
+                text.push(Spans::from(Span::styled(format!("+ {}", cn), green)));
+            }
+            for cn in &d.removed {
+                text.push(Spans::from(Span::styled(format!("- {}", cn), red)));
+            }
+            for (cn, lost, gained) in &d.retagged {
+                let mut spans = vec![Span::raw(format!("~ {}:", cn))];
+                spans.extend(lost.iter().map(|t| Span::styled(format!(" -{}", t), red)));
+                spans.extend(gained.iter().map(|t| Span::styled(format!(" +{}", t), green)));
+                text.push(Spans::from(spans));
+            }
+            for (cn, old, new) in &d.moved {
+                text.push(Spans::from(format!("> {}: {} -> {}", cn, old, new)));
+            }
+            for (cn, old, new) in &d.recounted {
+                text.push(Spans::from(format!("# {}: {} -> {} copies", cn, old, new)));
+            }
+            text
+        }
+
+        pub fn render(&self, frame: &mut tui::Frame<CrosstermBackend<std::io::Stdout>>) {
+            let vli: Vec<ListItem> = (0..self.vs.len())
+                .map(|i| {
+                    let mark = if i == self.base { "base: " } else { "" };
+                    ListItem::new(format!("{}{}", mark, self.label(i)))
+                })
+                .collect();
+            let list = List::new(vli)
+                .block(Block::default().title(self.title.clone()).borders(Borders::ALL))
+                .highlight_style(
+                    Style::default()
+                        .add_modifier(Modifier::BOLD)
+                        .fg(Color::Cyan),
+                );
+            let pd = Paragraph::new(self.rdiff_text())
+                .block(Block::default().title("Changes").borders(Borders::ALL))
+                .wrap(Wrap { trim: false });
+
+            let text = if self.message.is_empty() {
+                String::from("b: compare from here    r: restore    d: delete    Esc: back")
+            } else {
+                self.message.clone()
+            };
+            let pm = Paragraph::new(text).block(Block::default().borders(Borders::ALL));
+
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(3), Constraint::Length(3)].as_ref())
+                .split(frame.size());
+            let top = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(40), Constraint::Percentage(60)].as_ref())
+                .split(chunks[0]);
+            frame.render_stateful_widget(list, top[0], &mut self.state.clone());
+            frame.render_widget(pd, top[1]);
+            frame.render_widget(pm, chunks[1]);
+        }
+    }
+
+    fn rcoms(deck: &Deck) -> String {
+        std::iter::once(deck.commander.name.as_str())
+            .chain(deck.commander2.as_ref().map(|c| c.name.as_str()))
+            .join(" ")
+    }
+
+    impl NotesView {
+        pub fn new(deck: &Deck) -> Self {
+            let mut lines: Vec<String> = deck.notes.lines().map(String::from).collect();
+            if lines.is_empty() {
+                lines.push(String::new());
+            }
+
+            Self {
+                did: deck.id,
+                title: format!("Notes for {} (Esc to save)", deck.name),
+                lines,
+                row: 0,
+                col: 0,
+            }
+        }
+
+        pub fn handle_input(&mut self, c: KeyCode, conn: &Connection) -> ViewExit {
+            match c {
+                KeyCode::Esc => {
+                    udn(conn, self.did, &self.lines.join("\n")).unwrap();
+                    return ViewExit::Cancel;
+                }
+                KeyCode::Char(c) => {
+                    let i = self.rbpos();
+                    self.lines[self.row].insert(i, c);
+                    self.col += 1;
+                }
+                KeyCode::Enter => {
+                    let i = self.rbpos();
+                    let rest = self.lines[self.row].split_off(i);
+                    self.row += 1;
+                    self.col = 0;
+                    self.lines.insert(self.row, rest);
+                }
+                KeyCode::Backspace => {
+                    if self.col > 0 {
+                        self.col -= 1;
+                        let i = self.rbpos();
+                        self.lines[self.row].remove(i);
+                    } else if self.row > 0 {
+                        let line = self.lines.remove(self.row);
+                        self.row -= 1;
+                        self.col = self.lines[self.row].chars().count();
+                        self.lines[self.row].push_str(&line);
+                    }
+                }
+                KeyCode::Delete => {
+                    if self.col < self.lines[self.row].chars().count() {
+                        let i = self.rbpos();
+                        self.lines[self.row].remove(i);
+                    } else if self.row + 1 < self.lines.len() {
+                        let line = self.lines.remove(self.row + 1);
+                        self.lines[self.row].push_str(&line);
+                    }
+                }
+                KeyCode::Left => {
+                    if self.col > 0 {
+                        self.col -= 1;
+                    } else if self.row > 0 {
+                        self.row -= 1;
+                        self.col = self.lines[self.row].chars().count();
+                    }
+                }
+                KeyCode::Right => {
+                    if self.col < self.lines[self.row].chars().count() {
+                        self.col += 1;
+                    } else if self.row + 1 < self.lines.len() {
+                        self.row += 1;
+                        self.col = 0;
+                    }
+                }
+                KeyCode::Up if self.row > 0 => {
+                    self.row -= 1;
+                    self.col = self.col.min(self.lines[self.row].chars().count());
+                }
+                KeyCode::Down if self.row + 1 < self.lines.len() => {
+                    self.row += 1;
+                    self.col = self.col.min(self.lines[self.row].chars().count());
+                }
+                KeyCode::Home => self.col = 0,
+                KeyCode::End => self.col = self.lines[self.row].chars().count(),
+                _ => {}
+            }
+            ViewExit::Hold
+        }
+
+        pub fn render(&self, frame: &mut tui::Frame<CrosstermBackend<std::io::Stdout>>) {
+            let style = Style::default().fg(Color::White);
+            let mut vspans = Vec::new();
+            for (i, line) in self.lines.iter().enumerate() {
+                if i != self.row {
+                    vspans.push(Spans::from(Span::styled(line.clone(), style)));
+                    continue;
+                }
+                // Underline the character under the cursor, as the omnibars do.
+                let mut st = line.clone();
+                st.push(' ');
+                let (s1, s2) = st.split_at(self.rbpos());
+                let n = s2.chars().next().unwrap().len_utf8();
+                let (s2, s3) = s2.split_at(n);
+                vspans.push(Spans::from(vec![
+                    Span::styled(String::from(s1), style),
+                    Span::styled(String::from(s2), style.add_modifier(Modifier::UNDERLINED)),
+                    Span::styled(String::from(s3), style),
+                ]));
+            }
+
+            let area = frame.size();
+            let scroll = (self.row + 3).saturating_sub(area.height as usize);
+            let notes = Paragraph::new(vspans)
+                .block(
+                    Block::default()
+                        .title(self.title.clone())
+                        .borders(Borders::ALL),
+                )
+                .wrap(Wrap { trim: false })
+                .scroll((scroll as u16, 0));
+            frame.render_widget(notes, area);
+        }
+
+        fn rbpos(&self) -> usize {
+            let line = &self.lines[self.row];
+            line.char_indices()
+                .nth(self.col)
+                .map(|(i, _)| i)
+                .unwrap_or(line.len())
+        }
+    }
+
+    impl DeckView {
+        pub fn new(
+            did: i32,
+            settings: Rc<RefCell<DeckSettings>>,
+            autotag: Rc<AutoTagSettings>,
+            dbc: Arc<Mutex<Connection>>,
+        ) -> DeckView {
+            let deck = rdfdid(&dbc.lock().unwrap(), did).unwrap();
+            let cf = CardFilter::from(
+                did,
+                &deck.color,
+                deck.deck_type,
+                settings.borrow().df,
+                settings.borrow().ordering,
+            );
+            let st = 0;
+            let vcdec = rvcnfcf(&dbc.lock().unwrap(), &cf.make_query(false, "")).unwrap();
+            let quantities = rvqfdid(&dbc.lock().unwrap(), cf.did).unwrap_or_default();
+
+            let mut slde = StatefulList::with_items(vcdec.clone());
+            let name = slde.next().unwrap();
+            let ac = Some(rcfn(&dbc.lock().unwrap(), &name, Some(cf.did)).unwrap());
+            let sldb = StatefulList::default();
+            let mut coms = vec![deck.commander.name];
+            if let Some(c) = deck.commander2 {
+                coms.push(c.name);
+            }
+
+            let mut dv = DeckView {
+                omni: String::new(),
+                omniprev: String::new(),
+                omnipos: 0,
+                vsomni: Vec::new(),
+                omnirun: String::new(),
+                slde,
+                sldb,
+                vcdec,
+                quantities,
+                companion_issues: Vec::new(),
+                vmde: Vec::new(),
+                vmdb: Vec::new(),
+                st,
                 ac,
+                rulings: false,
+                printings: false,
+                coms,
+                companion: deck.companion,
+                popup: None,
+                pending: None,
+                card_colors: HashMap::new(),
+                card_rulings: Vec::new(),
+                card_printings: Vec::new(),
+                uses: Vec::new(),
                 cf,
                 dvs: DeckViewSection::DeckOmni,
                 settings,
+                autotag,
                 dbc,
-            }
+            };
+            dv.ucc();
+            dv.uci();
+            dv.upanel();
+            dv
         }
 
         pub fn handle_input(&mut self, c: KeyCode) -> DeckViewExit {
+            if self.popup.take().is_some() {
+                match self.pending.take() {
+                    Some(Pending::AutoTags(vct)) if c == KeyCode::Char('y') => self.apply_autotags(&vct),
+                    Some(Pending::Add(vcn)) if c == KeyCode::Char('y') => {
+                        let n = self.add_cards(&vcn);
+                        if self.popup.is_none() {
+                            self.popup = Some(format!("Added {} cards.", n));
+                        }
+                    }
+                    Some(Pending::Remove(vcn)) if c == KeyCode::Char('y') => {
+                        let n = self.remove_cards(&vcn);
+                        self.popup = Some(format!("Removed {} cards.", n));
+                    }
+                    Some(Pending::Tag(vcn, tag, add)) if c == KeyCode::Char('y') => {
+                        let n = self.tag_cards(&vcn, &tag, add);
+                        self.popup = Some(match add {
+                            true => format!("Tagged {} cards with {}.", n, tag),
+                            false => format!("Removed {} from {} cards.", tag, n),
+                        });
+                    }
+                    _ => {}
+                }
+                return DeckViewExit::Hold;
+            }
             match self.dvs {
                 DeckViewSection::DeckOmni | DeckViewSection::DbOmni => match c {
                     KeyCode::Left => {
@@ -2057,12 +4108,206 @@ pub mod views {
                         let so = self.omni.trim();
                         if so == "/stat" {
                             return DeckViewExit::Stats;
+                        } else if so == "/notes" {
+                            return DeckViewExit::Notes(self.cf.did);
+                        } else if so == "/validate" {
+                            let vv = vdeck(&self.dbc.lock().unwrap(), self.cf.did).unwrap();
+                            self.popup = Some(match vv.is_empty() {
+                                true => String::from("The deck is legal."),
+                                false => vv.iter().map(|v| v.to_string()).join("\n"),
+                            });
+                            self.omni.clear();
+                            self.omnipos = 0;
+                            self.uvc();
+                        } else if let Some(s) = so.strip_prefix("/commander") {
+                            // "/commander!:" also removes the old commanders from the deck.
+                            let (keep, s) = match s.strip_prefix('!') {
+                                Some(s) => (false, s),
+                                None => (true, s),
+                            };
+                            let s = s.trim_start_matches(':').to_string();
+                            self.change_commanders(&s, keep);
+                            self.omni.clear();
+                            self.omnipos = 0;
+                            self.uvc();
+                        } else if let Some(cn) = so.strip_prefix("/companion") {
+                            let cn = cn.trim_start_matches(':').trim().to_string();
+                            self.set_companion(&cn);
+                            self.omni.clear();
+                            self.omnipos = 0;
+                            self.uvc();
+                        } else if let Some(s) = so.strip_prefix("/target:") {
+                            // "/target:ramp 10" sets a target, "/target:ramp" clears it.
+                            let (tag, t) = s.split_once(' ').unwrap_or((s, ""));
+                            match TagTarget::from(t) {
+                                _ if !valid_tag(tag) => {
+                                    self.popup = Some(String::from("Name a tag, as in /target:ramp 10."))
+                                }
+                                None if !t.trim().is_empty() => {
+                                    self.popup = Some(String::from(
+                                        "Targets look like 10 (at least), 8-12 or -3 (at most).",
+                                    ))
+                                }
+                                target => self.settings.borrow_mut().set_target(tag, target),
+                            }
+                            self.omni.clear();
+                            self.omnipos = 0;
+                            self.uvc();
+                        } else if so == "/tags" {
+                            let settings = self.settings.borrow();
+                            self.popup = Some(
+                                settings
+                                    .tags
+                                    .iter()
+                                    .map(|t| {
+                                        let ti = settings.info(t);
+                                        let mut s = t.clone();
+                                        if let Some(k) = ti.hotkey {
+                                            s = format!("[{}] {}", k, s);
+                                        }
+                                        if let Some(c) = ti.color {
+                                            s += &format!(" ({})", TagInfo::color_name(c));
+                                        }
+                                        if let Some(target) = settings.target(t) {
+                                            s += &format!(" target {}", target);
+                                        }
+                                        if !ti.description.is_empty() {
+                                            s += &format!(": {}", ti.description);
+                                        }
+                                        s
+                                    })
+                                    .join("\n"),
+                            );
+                            drop(settings);
+                            self.omni.clear();
+                            self.omnipos = 0;
+                            self.uvc();
+                        } else if let Some(s) = so
+                            .strip_prefix("/tagkey:")
+                            .or_else(|| so.strip_prefix("/tagcolor:"))
+                            .or_else(|| so.strip_prefix("/tagdesc:"))
+                        {
+                            // "/tagkey:ramp 1", "/tagcolor:ramp green" and "/tagdesc:ramp Mana sources";
+                            // leaving out the value clears it.
+                            let (tag, value) = s.split_once(' ').unwrap_or((s, ""));
+                            let value = value.trim();
+                            let mut ti = self.settings.borrow().info(tag);
+                            if so.starts_with("/tagkey:") {
+                                ti.hotkey = value.parse().ok().filter(|k| (1..=9).contains(k));
+                                if ti.hotkey.is_none() && !value.is_empty() {
+                                    self.popup = Some(String::from("Hotkeys are the number keys 1 to 9."));
+                                }
+                            } else if so.starts_with("/tagcolor:") {
+                                ti.color = TagInfo::color_from(value);
+                                if ti.color.is_none() && !value.is_empty() {
+                                    self.popup = Some(String::from(
+                                        "Colors are red, green, yellow, blue, magenta, cyan, gray, white, or light followed by one of the first six.",
+                                    ));
+                                }
+                            } else {
+                                ti.description = value.to_string();
+                            }
+                            if self.popup.is_none() {
+                                self.settings.borrow_mut().set_info(tag, ti);
+                            }
+                            self.omni.clear();
+                            self.omnipos = 0;
+                            self.uvc();
+                        } else if matches!(so.split([' ', ':']).next(), Some("/tagall" | "/untagall")) {
+                            // "/tagall ramp" and "/untagall ramp" act on every card in the deck list,
+                            // after a preview.
+                            let (cmd, tag) = so.split_once([' ', ':']).unwrap_or((so, ""));
+                            let tag = tag.trim().to_string();
+                            let add = cmd == "/tagall";
+                            if valid_tag(&tag) {
+                                let vcn = self.rvcnrun();
+                                if vcn.is_empty() {
+                                    self.popup = Some(String::from("There are no cards to tag."));
+                                } else {
+                                    let question = match add {
+                                        true => format!("Tag these {} cards with {}?", vcn.len(), tag),
+                                        false => format!("Remove {} from these {} cards?", tag, vcn.len()),
+                                    };
+                                    self.popup = Some(rpending(question, &vcn));
+                                    self.pending = Some(Pending::Tag(vcn, tag, add));
+                                }
+                            } else {
+                                self.popup = Some(format!("Name a tag, as in {} ramp.", cmd));
+                            }
+                            self.omni.clear();
+                            self.omnipos = 0;
+                            self.uvc();
+                        } else if so == "/removeall" || so == "/addall" {
+                            // Removes every card in the deck list, or adds every card in the
+                            // database list, after a preview.
+                            let vcn: Vec<String> = match so {
+                                "/removeall" => {
+                                    let coms = &self.coms;
+                                    self.rvcnrun().into_iter().filter(|cn| !coms.contains(cn)).collect()
+                                }
+                                _ => {
+                                    let vcdec = &self.vcdec;
+                                    self.sldb.items.iter().filter(|cn| !vcdec.contains(cn)).cloned().collect()
+                                }
+                            };
+                            let verb = if so == "/removeall" { "Remove" } else { "Add" };
+                            if vcn.is_empty() {
+                                self.popup = Some(format!("There are no cards to {}.", verb.to_lowercase()));
+                            } else {
+                                self.popup = Some(rpending(format!("{} these {} cards?", verb, vcn.len()), &vcn));
+                                self.pending = Some(match so {
+                                    "/removeall" => Pending::Remove(vcn),
+                                    _ => Pending::Add(vcn),
+                                });
+                            }
+                            self.omni.clear();
+                            self.omnipos = 0;
+                            self.uvc();
+                        } else if so == "/autotag" {
+                            self.preview_autotags();
+                            self.omni.clear();
+                            self.omnipos = 0;
+                            self.uvc();
+                        } else if let Some(name) = so
+                            .strip_prefix("/template:")
+                            .or_else(|| so.strip_prefix("/savetemplate:"))
+                        {
+                            let name = name.trim().to_lowercase();
+                            let save = so.starts_with("/savetemplate:");
+                            self.omni.clear();
+                            self.omnipos = 0;
+                            self.uvc();
+                            if name.is_empty() {
+                                self.popup = Some(String::from("Templates need a name."));
+                            } else if save {
+                                return DeckViewExit::SaveTemplate(name, self.cf.did);
+                            } else {
+                                return DeckViewExit::Template(name, self.cf.did);
+                            }
+                        } else if so == "/snapshots" {
+                            self.omni.clear();
+                            self.omnipos = 0;
+                            self.uvc();
+                            return DeckViewExit::Snapshots(self.cf.did);
+                        } else if let Some(name) = so.strip_prefix("/snapshot:") {
+                            let name = name.trim().to_string();
+                            self.omni.clear();
+                            self.omnipos = 0;
+                            self.uvc();
+                            self.popup = Some(if name.is_empty() {
+                                String::from("Snapshots need a name.")
+                            } else {
+                                match isfd(&self.dbc.lock().unwrap(), self.cf.did, &name) {
+                                    Ok(_) => format!("Saved a snapshot of the deck as {}.", name),
+                                    Err(e) => format!("Couldn't save the snapshot: {}", e),
+                                }
+                            });
                         } else if so == "/settings" || so == "/config" {
                             return DeckViewExit::Settings(self.cf.did);
                         } else {
                             let mut tag = String::new();
-                            let re = regex::Regex::new(r"/tag:(\w*)").unwrap();
-                            let omni = if let Some(cap) = re.captures(so) {
+                            let re = regex::Regex::new(r"/tag:([^\s;|]+)").unwrap();
+                            let omni = if let Some(cap) = re.captures(so).filter(|cap| valid_tag(&cap[1])) {
                                 tag = String::from(&cap[1]);
                                 let s = format!("/tag:{}", tag);
                                 let s = so.replace(&s, "");
@@ -2075,6 +4320,9 @@ pub mod views {
                             self.omni = omni.clone();
                             self.omnipos = self.omnipos.min(self.omni.len());
 
+                            if self.dvs == DeckViewSection::DeckOmni {
+                                self.omnirun = omni.clone();
+                            }
                             if !omni.is_empty() {
                                 if let Some(i) = self.vsomni.iter().position(|s| s == &omni) {
                                     self.vsomni.remove(i);
@@ -2082,8 +4330,8 @@ pub mod views {
                                 self.vsomni.push(omni);
                             }
 
+                            self.uvc();
                             if self.dvs == DeckViewSection::DbOmni {
-                                self.uvc();
                                 if self.sldb.state.selected() != None {
                                     self.dvs = DeckViewSection::DbCards;
                                 }
@@ -2143,9 +4391,32 @@ pub mod views {
                             if self.st > 0 {
                                 self.st -= 1;
                             } else {
-                                self.st = self.settings.borrow().tags.len() - 1;
+                                self.st = self.settings.borrow().tags.len().saturating_sub(1);
                             }
                         }
+                        KeyCode::Char('m') => self.toggle_mark(false),
+                        KeyCode::Char('M') => self.mark_all(),
+                        KeyCode::Delete
+                            if (self.dvs == DeckViewSection::DeckCards && !self.vmde.is_empty())
+                                || (self.dvs == DeckViewSection::DbCards && !self.vmdb.is_empty()) =>
+                        {
+                            let marked = match self.dvs {
+                                DeckViewSection::DeckCards => std::mem::take(&mut self.vmde),
+                                _ => std::mem::take(&mut self.vmdb),
+                            };
+                            let vcdec = &self.vcdec;
+                            let vcn: Vec<String> = marked.into_iter().filter(|cn| vcdec.contains(cn)).collect();
+                            self.remove_cards(&vcn);
+                        }
+                        KeyCode::Enter
+                            if self.dvs == DeckViewSection::DeckCards && !self.vmde.is_empty() =>
+                        {
+                            self.tag_marked()
+                        }
+                        KeyCode::Enter if self.dvs == DeckViewSection::DbCards && !self.vmdb.is_empty() => {
+                            let vcn = std::mem::take(&mut self.vmdb);
+                            self.add_cards(&vcn);
+                        }
                         KeyCode::Delete => {
                             let cn = sl.get().unwrap();
                             let c = rcfn(&self.dbc.lock().unwrap(), cn, None).unwrap();
@@ -2160,6 +4431,9 @@ pub mod views {
                             };
 
                             if let Some(j) = self.vcdec.iter().position(|s| s == cn) {
+                                let before =
+                                    rvcsfd(&self.dbc.lock().unwrap(), std::slice::from_ref(cn), self.cf.did)
+                                        .unwrap_or_default();
                                 self.vcdec.remove(j);
                                 let flag = self.dvs == DeckViewSection::DeckCards;
                                 dcntodc(&self.dbc.lock().unwrap(), cn, self.cf.did).unwrap();
@@ -2191,12 +4465,15 @@ pub mod views {
                                     }
                                     _ => {}
                                 }
+                                ihfd(&self.dbc.lock().unwrap(), before, self.cf.did).ok();
 
                                 self.vcdec = rvcnfcf(
                                     &self.dbc.lock().unwrap(),
                                     &self.cf.make_query(false, ""),
                                 )
                                 .unwrap();
+                                self.quantities =
+                                    rvqfdid(&self.dbc.lock().unwrap(), self.cf.did).unwrap_or_default();
 
                                 if sl.state.selected() == None {
                                     //Can only happen in Deck view
@@ -2212,31 +4489,22 @@ pub mod views {
                                     )
                                     .unwrap();
                                     self.slde.reinitialize(vc, true);
+                                    self.ucc();
                                 } else {
                                     self.uac();
                                 }
+                                self.uci();
                             }
                         }
                         KeyCode::Enter => {
                             let cn = &self.ac.as_ref().unwrap().name;
                             if self.vcdec.contains(cn) {
-                                self.toggle_tag()
-                            } else {
-                                if let Ok(vc) = ictodc(
-                                    &self.dbc.lock().unwrap(),
-                                    self.ac.as_ref().unwrap(),
-                                    self.cf.did,
-                                ) {
-                                    for c in vc {
-                                        self.vcdec.push(c.name);
-                                    }
+                                if !self.settings.borrow().tags.is_empty() {
+                                    self.toggle_tag()
                                 }
-                                let vc = rvcnfcf(
-                                    &self.dbc.lock().unwrap(),
-                                    &self.cf.make_query(false, &self.omniprev),
-                                )
-                                .unwrap();
-                                self.slde.reinitialize(vc, true);
+                            } else {
+                                let cn = cn.clone();
+                                self.add_cards(&[cn]);
                             }
                         }
                         KeyCode::Esc => return DeckViewExit::MainMenu,
@@ -2248,6 +4516,25 @@ pub mod views {
                             }
                         }
                         KeyCode::Char(' ') => self.uacr(),
+                        KeyCode::Char('r') => self.rulings = !self.rulings,
+                        KeyCode::Char('p') => self.printings = !self.printings,
+                        KeyCode::Char('e') => self.record_ac(|dv| dv.cycle_printing()),
+                        KeyCode::Char('f') => self.record_ac(|dv| dv.toggle_foil()),
+                        KeyCode::Char('z') => self.record_ac(|dv| dv.cycle_zone()),
+                        KeyCode::Char(c @ '1'..='9') => {
+                            let key = c.to_digit(10).unwrap() as u8;
+                            let tag = self.settings.borrow().tag_for_key(key);
+                            if let Some(i) = tag.and_then(|t| self.settings.borrow().find_tag(&t)) {
+                                self.st = i;
+                                if self.dvs == DeckViewSection::DeckCards && !self.vmde.is_empty() {
+                                    self.tag_marked();
+                                } else if self.ac.as_ref().is_some_and(|c| self.vcdec.contains(&c.name)) {
+                                    self.toggle_tag();
+                                }
+                            }
+                        }
+                        KeyCode::Char('+') => self.record_ac(|dv| dv.change_quantity(1)),
+                        KeyCode::Char('-') => self.record_ac(|dv| dv.change_quantity(-1)),
                         KeyCode::Char('u') => {
                             if let Some(ac) = &self.ac {
                                 if ac.stale {
@@ -2277,21 +4564,28 @@ pub mod views {
             self.cf.df = self.settings.borrow().df;
             self.cf.so = self.settings.borrow().ordering;
             if self.st >= self.settings.borrow().tags.len() {
-                self.st = self
-                    .settings
-                    .borrow()
-                    .find_tag(&String::from("main"))
-                    .unwrap();
+                self.st = 0;
             }
-            for tc in changes {
-                utindc(&self.dbc.lock().unwrap(), tc, &self.cf)
+            if changes.is_empty() {
+                self.ucc();
+                return;
             }
+            let vcn = self.vcdec.clone();
+            self.record(&vcn, |dv| {
+                for tc in changes {
+                    utindc(&dv.dbc.lock().unwrap(), tc, &dv.cf)
+                }
+            });
         }
 
         pub fn rdid(&self) -> i32 {
             self.cf.did
         }
 
+        pub fn spopup(&mut self, s: String) {
+            self.popup = Some(s);
+        }
+
         pub fn render(&self, frame: &mut tui::Frame<CrosstermBackend<std::io::Stdout>>) {
             let tag_max = self
                 .settings
@@ -2300,8 +4594,9 @@ pub mod views {
                 .iter()
                 .map(|s| s.len())
                 .max()
-                .unwrap()
-                + 2;
+                .unwrap_or_default()
+                // Room for the hotkey, live count and target.
+                + 14;
             let mut vrct = Vec::new();
             let cut = Layout::default()
                 .direction(Direction::Vertical)
@@ -2350,6 +4645,7 @@ pub mod views {
                 ]
             };
 
+            let tag_colors = self.settings.borrow().tag_colors();
             let (vli, ls) = match self.dvs {
                 DeckViewSection::DeckOmni => {
                     // _bomni = bfoc.title(format!("Tag max: {}", tag_max));
@@ -2357,13 +4653,13 @@ pub mod views {
                     _blist = bdef
                         .clone()
                         .title(format!("Deck View ({})", self.slde.items.len()));
-                    let vli = self.slde.rvli();
+                    let vli = self.slde.rvliq(&self.quantities, &self.card_colors, &self.vmde);
                     (vli, &self.slde.state)
                 }
                 DeckViewSection::DeckCards => {
                     _bomni = bdef.clone().title("Filter Deck");
                     _blist = bfoc.title(format!("Deck View ({})", self.slde.items.len()));
-                    let vli = self.slde.rvli();
+                    let vli = self.slde.rvliq(&self.quantities, &self.card_colors, &self.vmde);
                     (vli, &self.slde.state)
                 }
                 DeckViewSection::DbOmni => {
@@ -2371,22 +4667,52 @@ pub mod views {
                     _blist = bdef
                         .clone()
                         .title(format!("Database View ({})", self.sldb.items.len()));
-                    let vli = self.sldb.rvlis(&self.vcdec);
+                    let vli = self.sldb.rvlis(&self.vcdec, &self.vmdb);
                     (vli, &self.sldb.state)
                 }
                 DeckViewSection::DbCards => {
                     _bomni = bdef.clone().title("Filter Database");
                     _blist = bfoc.title(format!("Database View ({})", self.sldb.items.len()));
-                    let vli = self.sldb.rvlis(&self.vcdec);
+                    let vli = self.sldb.rvlis(&self.vcdec, &self.vmdb);
                     (vli, &self.sldb.state)
                 }
             };
 
-            let tag = &self.settings.borrow().tags[self.st];
+            let tag = self.settings.borrow().tags.get(self.st).cloned().unwrap_or_default();
             let po = Paragraph::new(Spans::from(spans)).block(_bomni);
-            let pt = Paragraph::new(tag.clone()).block(bdef.clone());
+            // Nested tags show their parents dimmed.
+            let ts = match tag_colors.get(&tag) {
+                Some(c) => Style::default().fg(*c),
+                None => Style::default(),
+            };
+            let mut vtsp = match self.settings.borrow().info(&tag).hotkey {
+                Some(k) => vec![Span::styled(format!("[{}] ", k), Style::default().fg(Color::DarkGray))],
+                None => Vec::new(),
+            };
+            match tag.rsplit_once('/') {
+                Some((parent, leaf)) => {
+                    vtsp.push(Span::styled(format!("{}/", parent), Style::default().fg(Color::DarkGray)));
+                    vtsp.push(Span::styled(leaf.to_string(), ts));
+                }
+                None => vtsp.push(Span::styled(tag.clone(), ts)),
+            };
+            if !tag.is_empty() {
+                let n = rqtfd(&self.dbc.lock().unwrap(), self.cf.did, &tag).unwrap_or_default();
+                vtsp.push(match self.settings.borrow().target(&tag) {
+                    Some(t) => Span::styled(
+                        format!(" {}/{}", n, t),
+                        Style::default().fg(if t.under(n) || t.over(n) { Color::Red } else { Color::Green }),
+                    ),
+                    None => Span::from(format!(" ({})", n)),
+                });
+            }
+            let pt = Paragraph::new(Spans::from(vtsp)).block(bdef.clone());
             let pc = match &self.ac {
-                Some(card) => card.display().block(bdef.clone()),
+                Some(card) => {
+                    let rulings = if self.rulings { &self.card_rulings[..] } else { &[] };
+                    card.display(rulings, &self.card_printings, self.printings, &self.uses, &tag_colors)
+                        .block(bdef.clone())
+                }
                 None => Paragraph::new("No card found!").block(bdef.clone()),
             };
             let lc = List::new(vli)
@@ -2397,10 +4723,116 @@ pub mod views {
                 )
                 .block(_blist);
 
+            let mut vcoms: Vec<Spans> = self.coms.iter().map(|s| Spans::from(s.clone())).collect();
+            if let Some(c) = &self.companion {
+                vcoms.push(Spans::from(format!("Companion: {}", c.name)));
+                if !self.companion_issues.is_empty() {
+                    vcoms.push(Spans::from(Span::styled(
+                        format!("{} condition problem(s)", self.companion_issues.len()),
+                        Style::default().fg(Color::Red),
+                    )));
+                }
+            }
+            let cut = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints(
+                    [
+                        Constraint::Min(3),
+                        Constraint::Length(vcoms.len() as u16 + 2),
+                    ]
+                    .as_ref(),
+                )
+                .split(vrct[2]);
+            let pcoms = Paragraph::new(vcoms).block(bdef.clone().title("Commanders"));
+
             frame.render_widget(po, vrct[0]);
             frame.render_widget(pt, vrct[1]);
-            frame.render_stateful_widget(lc, vrct[2], &mut ls.clone());
+            frame.render_stateful_widget(lc, cut[0], &mut ls.clone());
+            frame.render_widget(pcoms, cut[1]);
             frame.render_widget(pc, vrct[3]);
+
+            if let Some(message) = &self.popup {
+                let area = frame.size();
+                let height = (message.lines().count() as u16 + 3).min(area.height);
+                let area = Rect {
+                    x: area.width / 5,
+                    y: (area.height - height) / 2,
+                    width: area.width * 3 / 5,
+                    height,
+                };
+                let title = match self.pending {
+                    None => "Press any key to continue",
+                    Some(_) => "Press y to apply",
+                };
+                let popup = Paragraph::new(message.clone())
+                    .block(bdef.clone().title(title))
+                    .wrap(Wrap { trim: false });
+                frame.render_widget(tui::widgets::Clear, area);
+                frame.render_widget(popup, area);
+            }
+        }
+
+        fn change_commanders(&mut self, s: &str, keep: bool) {
+            let mut names = s.split('|').map(|s| s.trim()).filter(|s| !s.is_empty());
+            let c1 = match names.next() {
+                Some(c1) => c1,
+                None => return,
+            };
+            let c2 = names.next();
+            let vcn: Vec<String> = std::iter::once(c1)
+                .chain(c2)
+                .map(String::from)
+                .chain(self.coms.iter().cloned())
+                .collect();
+            let res = self.record(&vcn, |dv| ucom(&dv.dbc.lock().unwrap(), dv.cf.did, c1, c2, keep));
+            match res {
+                Ok(outside) => {
+                    self.udirty();
+                    self.popup = if outside.is_empty() {
+                        Some(String::from("Commanders changed. Every card fits the new color identity."))
+                    } else {
+                        Some(format!(
+                            "Commanders changed. These cards are outside the new color identity:\n{}",
+                            outside.join("\n")
+                        ))
+                    };
+                }
+                Err(_) => {
+                    self.popup = Some(format!(
+                        "Could not make {} the commander(s) of this deck.",
+                        s.replace('|', " and ")
+                    ));
+                }
+            }
+        }
+
+        // An empty name clears the companion.
+        fn set_companion(&mut self, cn: &str) {
+            let mut vcn: Vec<String> = self.companion.iter().map(|c| c.name.clone()).collect();
+            if cn.is_empty() {
+                if self.record(&vcn, |dv| ucomp(&dv.dbc.lock().unwrap(), dv.cf.did, None)).is_ok() {
+                    self.companion = None;
+                }
+                return;
+            }
+            let conn = self.dbc.lock().unwrap();
+            let c = match rcfn(&conn, cn, None) {
+                Ok(c) if c.text.contains("Companion —") => c,
+                _ => return,
+            };
+            let deck = rdfdid(&conn, self.cf.did).unwrap();
+            if deck.deck_type.rules().identity
+                && c.color_identity
+                    .iter()
+                    .any(|ch| *ch != '\u{0}' && !deck.color.contains(*ch))
+            {
+                return;
+            }
+            drop(conn);
+            vcn.push(c.name.clone());
+            if self.record(&vcn, |dv| ucomp(&dv.dbc.lock().unwrap(), dv.cf.did, Some(&c.name))).is_ok() {
+                self.companion = Some(c);
+            }
         }
 
         fn insert_tag(&mut self, tag: String) {
@@ -2408,17 +4840,305 @@ pub mod views {
             self.st = self.settings.borrow().find_tag(&tag).unwrap();
         }
 
+        // Shift with Up or Down marks the highlighted card of either list before moving.
+        pub fn shift_mark(&mut self, up: bool) {
+            if matches!(self.dvs, DeckViewSection::DeckCards | DeckViewSection::DbCards) {
+                self.toggle_mark(up);
+            }
+        }
+
+        // Marks or unmarks the highlighted card, then moves on to the next one (or the one above).
+        fn toggle_mark(&mut self, up: bool) {
+            let (sl, marked) = match self.dvs {
+                DeckViewSection::DeckOmni | DeckViewSection::DeckCards => (&mut self.slde, &mut self.vmde),
+                DeckViewSection::DbOmni | DeckViewSection::DbCards => (&mut self.sldb, &mut self.vmdb),
+            };
+            let cn = match sl.get() {
+                Some(cn) => cn.clone(),
+                None => return,
+            };
+            match marked.iter().position(|s| s == &cn) {
+                Some(i) => {
+                    marked.remove(i);
+                }
+                None => marked.push(cn),
+            }
+            if up {
+                sl.previous();
+            } else {
+                sl.next();
+            }
+            self.uac();
+        }
+
+        // Marks every card in the list, or clears the marks if there are any.
+        fn mark_all(&mut self) {
+            let (sl, marked) = match self.dvs {
+                DeckViewSection::DeckOmni | DeckViewSection::DeckCards => (&self.slde, &mut self.vmde),
+                DeckViewSection::DbOmni | DeckViewSection::DbCards => (&self.sldb, &mut self.vmdb),
+            };
+            if marked.is_empty() {
+                *marked = sl.items.clone();
+            } else {
+                marked.clear();
+            }
+        }
+
+        // Puts the active tag on every marked deck card, or takes it off if they all have it.
+        fn tag_marked(&mut self) {
+            let tag = match self.settings.borrow().tags.get(self.st) {
+                Some(t) => t.clone(),
+                None => return,
+            };
+            let add = {
+                let conn = self.dbc.lock().unwrap();
+                self.vmde
+                    .iter()
+                    .any(|cn| rcfn(&conn, cn, Some(self.cf.did)).is_ok_and(|c| !c.tags.contains(&tag)))
+            };
+            let vcn = self.vmde.clone();
+            self.tag_cards(&vcn, &tag, add);
+        }
+
+        fn tag_cards(&mut self, vcn: &[String], tag: &str, add: bool) -> usize {
+            let n = self.record(vcn, |dv| {
+                utivcndc(&dv.dbc.lock().unwrap(), vcn, tag, add, dv.cf.did).unwrap_or_default()
+            });
+            if add {
+                self.settings.borrow_mut().add_tag(tag.to_string());
+            }
+            self.uac();
+            n
+        }
+
+        fn add_cards(&mut self, vcn: &[String]) -> usize {
+            let added = self.record(vcn, |dv| -> rusqlite::Result<Vec<String>> {
+                let vc = ivctodc(&dv.dbc.lock().unwrap(), vcn, dv.cf.did)?;
+                Ok(vc.into_iter().map(|c| c.name).collect())
+            });
+            let vcn = match added {
+                Ok(vcn) => vcn,
+                Err(_) => {
+                    self.popup = Some(String::from("Could not add the cards."));
+                    return 0;
+                }
+            };
+            self.udirty();
+            self.autotag_added(&vcn);
+            vcn.len()
+        }
+
+        fn remove_cards(&mut self, vcn: &[String]) -> usize {
+            let n = self.record(vcn, |dv| {
+                dvcntodc(&dv.dbc.lock().unwrap(), vcn, dv.cf.did).unwrap_or_default()
+            });
+            self.udirty();
+            n
+        }
+
+        // Reloads the deck's cards and the deck list after cards were added or removed.
+        fn udirty(&mut self) {
+            let filter = match self.dvs {
+                DeckViewSection::DeckOmni | DeckViewSection::DeckCards => &self.omni,
+                DeckViewSection::DbOmni | DeckViewSection::DbCards => &self.omniprev,
+            };
+            let conn = self.dbc.lock().unwrap();
+            // Undo can also change the commanders and companion.
+            let deck = rdfdid(&conn, self.cf.did).unwrap();
+            self.cf = CardFilter::from(self.cf.did, &deck.color, deck.deck_type, self.cf.df, self.cf.so);
+            self.coms = std::iter::once(deck.commander.name).chain(deck.commander2.map(|c| c.name)).collect();
+            self.companion = deck.companion;
+            self.vcdec = rvcnfcf(&conn, &self.cf.make_query(false, "")).unwrap();
+            self.quantities = rvqfdid(&conn, self.cf.did).unwrap_or_default();
+            let vc = rvcnfcf(&conn, &self.cf.make_query(false, filter)).unwrap();
+            drop(conn);
+            self.slde.reinitialize(vc, true);
+            self.uci();
+            let vcdec = &self.vcdec;
+            self.vmde.retain(|cn| vcdec.contains(cn));
+            if self.dvs == DeckViewSection::DeckCards && self.slde.state.selected().is_none() {
+                self.dvs = DeckViewSection::DeckOmni;
+            }
+            self.uac();
+        }
+
+        // Previews the auto-tag rules' tags for newly added cards, if the settings ask for it.
+        fn autotag_added(&mut self, vcn: &[String]) {
+            if !self.autotag.on_add {
+                return;
+            }
+            let vct = rvatfd(&self.dbc.lock().unwrap(), self.cf.did, &self.autotag.rules).unwrap_or_default();
+            let vct: Vec<(String, String)> = vct.into_iter().filter(|(cn, _)| vcn.contains(cn)).collect();
+            if !vct.is_empty() {
+                self.show_autotags(vct);
+            }
+        }
+
+        fn preview_autotags(&mut self) {
+            let vct =
+                rvatfd(&self.dbc.lock().unwrap(), self.cf.did, &self.autotag.rules).unwrap_or_default();
+            self.show_autotags(vct);
+        }
+
+        fn show_autotags(&mut self, vct: Vec<(String, String)>) {
+            let mut vs = Vec::new();
+            for (tag, group) in &vct.iter().sorted_by(|a, b| a.1.cmp(&b.1)).group_by(|(_, t)| t) {
+                let vcn: Vec<&String> = group.map(|(cn, _)| cn).collect();
+                vs.push(format!("{} ({}): {}", tag, vcn.len(), vcn.iter().join(", ")));
+            }
+            if vs.is_empty() {
+                vs.push(String::from("The auto-tag rules have no new tags for this deck."));
+            } else {
+                vs.push(String::from("\nPress y to add these tags, or any other key to cancel."));
+            }
+            for rule in self.autotag.rules.iter().filter(|r| !vomni(&r.query)) {
+                vs.push(format!("Skipped the {} rule; its query doesn't parse: {}", rule.tag, rule.query));
+            }
+            self.popup = Some(vs.join("\n"));
+            if !vct.is_empty() {
+                self.pending = Some(Pending::AutoTags(vct));
+            }
+        }
+
+        fn apply_autotags(&mut self, vct: &[(String, String)]) {
+            let vcn: Vec<String> = vct.iter().map(|(cn, _)| cn.clone()).collect();
+            self.record(&vcn, |dv| dv.uat(vct));
+        }
+
+        fn uat(&mut self, vct: &[(String, String)]) {
+            if uatindc(&self.dbc.lock().unwrap(), self.cf.did, vct).is_err() {
+                self.popup = Some(String::from("Could not add the auto-tags."));
+                return;
+            }
+            for (_, tag) in vct {
+                self.settings.borrow_mut().add_tag(tag.clone());
+            }
+            if let Some(c) = &self.ac {
+                if self.vcdec.contains(&c.name) {
+                    self.ac = rcfn(&self.dbc.lock().unwrap(), &c.name, Some(self.cf.did)).ok();
+                }
+            }
+        }
+
         fn toggle_tag(&mut self) {
             let cn = self.ac.as_ref().unwrap().to_string();
-            self.ac = ttindc(
-                &self.dbc.lock().unwrap(),
-                &cn,
-                &self.settings.borrow().tags[self.st],
-                self.cf.did,
-            );
+            self.record(std::slice::from_ref(&cn), |dv| {
+                dv.ac = ttindc(
+                    &dv.dbc.lock().unwrap(),
+                    &cn,
+                    &dv.settings.borrow().tags[dv.st],
+                    dv.cf.did,
+                );
+            });
+        }
+
+        // Runs a deck edit, saving the cards' earlier state so that it can be undone.
+        fn record<T>(&mut self, vcn: &[String], f: impl FnOnce(&mut Self) -> T) -> T {
+            let before = rvcsfd(&self.dbc.lock().unwrap(), vcn, self.cf.did).unwrap_or_default();
+            let t = f(self);
+            ihfd(&self.dbc.lock().unwrap(), before, self.cf.did).ok();
+            self.ucc();
+            self.uci();
+            t
+        }
+
+        fn record_ac(&mut self, f: impl FnOnce(&mut Self)) {
+            let vcn: Vec<String> = self.ac.iter().map(|c| c.name.clone()).collect();
+            self.record(&vcn, f)
+        }
+
+        // Undoes the last edit to the deck, or redoes the last undone one.
+        pub fn undo(&mut self, redo: bool) {
+            let n = uhfd(&self.dbc.lock().unwrap(), self.cf.did, redo).unwrap_or_default();
+            if n == 0 {
+                self.popup = Some(format!("Nothing to {}.", if redo { "redo" } else { "undo" }));
+                return;
+            }
+            self.udirty();
+        }
+
+        fn cycle_printing(&mut self) {
+            let c = match &self.ac {
+                Some(c) if self.vcdec.contains(&c.name) => c,
+                _ => return,
+            };
+            let conn = self.dbc.lock().unwrap();
+            let printings = rvpfcn(&conn, &c.name).unwrap();
+            let i = match &c.printing {
+                Some(cp) => printings
+                    .iter()
+                    .position(|p| p.set_code == cp.set_code && p.number == cp.number)
+                    .map_or(0, |i| i + 1),
+                None => 0,
+            };
+            let next = printings.get(i).map(|p| CardPrinting {
+                set_code: p.set_code.clone(),
+                number: p.number.clone(),
+                foil: c.printing.as_ref().is_some_and(|cp| cp.foil),
+            });
+            if let Ok(card) = uprindc(&conn, c, self.cf.did, next.as_ref()) {
+                self.ac = Some(card);
+            }
+        }
+
+        fn change_quantity(&mut self, delta: i32) {
+            let c = match &self.ac {
+                Some(c) if self.vcdec.contains(&c.name) => c,
+                _ => return,
+            };
+            let quantity = c.quantity as i32 + delta;
+            if quantity < 1 {
+                return;
+            }
+            if let Some(max) = self.cf.dt.max_copies(c) {
+                if quantity as u32 > max {
+                    return;
+                }
+            }
+            let conn = self.dbc.lock().unwrap();
+            if let Ok(card) = uqindc(&conn, c, self.cf.did, quantity as u32) {
+                self.ac = Some(card);
+                self.quantities = rvqfdid(&conn, self.cf.did).unwrap_or_default();
+            }
+        }
+
+        fn toggle_foil(&mut self) {
+            let c = match &self.ac {
+                Some(c) if self.vcdec.contains(&c.name) => c,
+                _ => return,
+            };
+            if let Some(cp) = &c.printing {
+                let next = CardPrinting {
+                    foil: !cp.foil,
+                    ..cp.clone()
+                };
+                if let Ok(card) = uprindc(&self.dbc.lock().unwrap(), c, self.cf.did, Some(&next)) {
+                    self.ac = Some(card);
+                }
+            }
+        }
+
+        fn cycle_zone(&mut self) {
+            let c = match &self.ac {
+                Some(c) if self.vcdec.contains(&c.name) => c,
+                _ => return,
+            };
+            let zone = c.zone.unwrap_or(Zone::Main).next();
+            if let Ok(card) = uzindc(&self.dbc.lock().unwrap(), c, self.cf.did, zone) {
+                self.ac = Some(card);
+            }
+        }
+
+        // The deck cards matching the filter last run with Enter.
+        fn rvcnrun(&self) -> Vec<String> {
+            rvcnfcf(&self.dbc.lock().unwrap(), &self.cf.make_query(false, &self.omnirun)).unwrap_or_default()
         }
 
         fn uvc(&mut self) {
+            // Commands leave the list alone while they're typed.
+            if self.omni.starts_with('/') {
+                return;
+            }
             let (sl, general) = match self.dvs {
                 DeckViewSection::DeckOmni | DeckViewSection::DeckCards => (&mut self.slde, false),
                 DeckViewSection::DbOmni | DeckViewSection::DbCards => (&mut self.sldb, true),
@@ -2448,6 +5168,54 @@ pub mod views {
             } else {
                 self.ac = Some(rcfn(&self.dbc.lock().unwrap(), cn, Some(self.cf.did)).unwrap());
             }
+            self.ucc();
+            self.upanel();
+        }
+
+        fn upanel(&mut self) {
+            let card = match &self.ac {
+                Some(card) => card,
+                None => {
+                    self.card_rulings.clear();
+                    self.card_printings.clear();
+                    self.uses.clear();
+                    return;
+                }
+            };
+            let conn = self.dbc.lock().unwrap();
+            self.card_rulings = rvrfcn(&conn, &card.name).unwrap_or_default();
+            self.card_printings = rvpfcn(&conn, &card.name).unwrap_or_default();
+            self.uses = rvufcn(&conn, &card.name)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|u| u.did != self.cf.did)
+                .collect();
+        }
+
+        fn uci(&mut self) {
+            let c = match &self.companion {
+                Some(c) => c,
+                None => {
+                    self.companion_issues.clear();
+                    return;
+                }
+            };
+            let main: Vec<Card> = rvcfdid(&self.dbc.lock().unwrap(), self.cf.did, SortOrder::NameAsc)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|cc| cc.name != c.name && cc.zone.is_some_and(|z| z.in_deck()))
+                .collect();
+            self.companion_issues = c.companion_issues(&main, &self.cf.dt.rules());
+        }
+
+        // Cards take the color of their first colored tag.
+        fn ucc(&mut self) {
+            let tag_colors = self.settings.borrow().tag_colors();
+            self.card_colors = rvtfdid(&self.dbc.lock().unwrap(), self.cf.did)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|(cn, tags)| Some((cn, *tags.split('|').find_map(|t| tag_colors.get(t))?)))
+                .collect();
         }
 
         fn uacr(&mut self) {
@@ -2477,11 +5245,12 @@ pub mod views {
 
             self.ac =
                 Some(crate::db::rcfn(&self.dbc.lock().unwrap(), cn, Some(self.cf.did)).unwrap());
+            self.upanel();
         }
     }
 
     impl DeckStatView {
-        pub fn new(dbc: Arc<Mutex<Connection>>, did: i32) -> Self {
+        pub fn new(dbc: Arc<Mutex<Connection>>, did: i32, targets: &HashMap<String, TagTarget>) -> Self {
             let mut vtype = vec![0; 8];
             let mut vcmc = vec![0; 8];
             let mut hm_colors = HashMap::new();
@@ -2491,43 +5260,51 @@ pub mod views {
             let mut recommendations = Vec::new();
 
             let mut nonlands = 0;
+            let mut total = 0;
             let mut total_cmc: u16 = 0;
             let mut hm_costs = HashMap::new();
 
+            let deck = rdfdid(&dbc.lock().unwrap(), did).unwrap();
+            let rules = deck.deck_type.rules();
             let vcs = rvmcfd(&dbc.lock().unwrap(), did).unwrap();
 
             for c in vcs {
-                total_cmc += c.cmc as u16;
+                // The companion starts outside the deck.
+                if deck.companion.as_ref().is_some_and(|cc| cc.name == c.name) {
+                    continue;
+                }
+                let q = c.quantity as u64;
+                total += q;
+                total_cmc += c.cmc as u16 * c.quantity as u16;
                 if let Some(i) = hm_costs.get_mut(&c.cmc) {
-                    *i += 1;
+                    *i += q;
                 } else {
-                    hm_costs.insert(c.cmc, 1);
+                    hm_costs.insert(c.cmc, q);
                 }
                 if !c.types.contains("Land") {
-                    nonlands += 1;
+                    nonlands += q;
                 }
-                // TODO: Add check for legalities after adding them to CardStat
 
                 for ch in c.mana_cost.chars() {
                     if ['W', 'U', 'B', 'R', 'G', 'C', 'X'].contains(&ch) {
                         if let Some(i) = hm_colors.get_mut(&ch) {
-                            *i += 1;
+                            *i += q as usize;
                         } else {
-                            hm_colors.insert(ch, 1);
+                            hm_colors.insert(ch, q as usize);
                         }
                     }
                 }
 
                 for t in c.types.clone().split(' ') {
                     match t {
-                        "Artifact" => vtype[0] += 1,
-                        "Creature" => vtype[1] += 1,
-                        "Enchantment" => vtype[2] += 1,
-                        "Instant" => vtype[3] += 1,
-                        "Land" => vtype[4] += 1,
-                        "Legendary" => vtype[5] += 1,
-                        "Planeswalker" => vtype[6] += 1,
-                        "Sorcery" => vtype[7] += 1,
+                        "Artifact" => vtype[0] += q,
+                        "Creature" => vtype[1] += q,
+                        "Enchantment" => vtype[2] += q,
+                        "Instant" => vtype[3] += q,
+                        "Land" => vtype[4] += q,
+                        "Legendary" => vtype[5] += q,
+                        "Planeswalker" => vtype[6] += q,
+                        "Sorcery" => vtype[7] += q,
                         _ => {}
                     }
                 }
@@ -2535,45 +5312,50 @@ pub mod views {
                 match c.cmc {
                     0 => {
                         if !c.types.contains("Land") {
-                            vcmc[0] += 1;
+                            vcmc[0] += q;
                         }
                     }
-                    1 => vcmc[1] += 1,
-                    2 => vcmc[2] += 1,
-                    3 => vcmc[3] += 1,
-                    4 => vcmc[4] += 1,
-                    5 => vcmc[5] += 1,
-                    6 => vcmc[6] += 1,
-                    _ => vcmc[7] += 1,
-                }
-
-                for tag in c.tags {
-                    if let Some(v) = hm_tag.get_mut(&tag) {
-                        let a: u64 = v.checked_add(1).unwrap();
-                        hm_tag.insert(tag, a);
-                    } else {
-                        hm_tag.insert(tag, 1);
-                    }
+                    1 => vcmc[1] += q,
+                    2 => vcmc[2] += q,
+                    3 => vcmc[3] += q,
+                    4 => vcmc[4] += q,
+                    5 => vcmc[5] += q,
+                    6 => vcmc[6] += q,
+                    _ => vcmc[7] += q,
+                }
+
+                price_data.push((c.rpn(), c.price * q as f64));
+
+                // Counts roll up, so a card tagged removal/creature also counts for removal.
+                let tags: Vec<&str> = c.tags.iter().flat_map(|t| tag_ancestors(t)).unique().collect();
+                for tag in tags {
+                    *hm_tag.entry(tag.to_string()).or_default() += q;
                 }
 
-                price_data.push((c.name.clone(), c.price));
                 if c.stale {
                     fresh = false;
                 }
             }
-            hm_tag.remove_entry(&String::from("main"));
+            // Tags with a target are listed even when no card has them yet, along with their parents.
+            for tag in targets.keys() {
+                for t in tag_ancestors(tag) {
+                    hm_tag.entry(t.to_string()).or_default();
+                }
+            }
             let mut tag_data = Vec::new();
             for (k, v) in hm_tag {
                 tag_data.push((k.clone(), v));
             }
 
-            tag_data.sort_by(|a, b| {
-                let o = b.1.cmp(&a.1);
-                if o == Ordering::Equal {
-                    return a.0.cmp(&b.0);
-                }
-                o
-            });
+            // Siblings are ordered by count, with children kept under their parent.
+            let counts: HashMap<String, u64> = tag_data.iter().cloned().collect();
+            let key = |tag: &str| -> Vec<(Reverse<u64>, String)> {
+                tag_ancestors(tag)
+                    .into_iter()
+                    .map(|t| (Reverse(counts.get(t).copied().unwrap_or_default()), t.to_string()))
+                    .collect()
+            };
+            tag_data.sort_by_cached_key(|(k, _)| key(k));
             price_data.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
 
             let mut color_data = HashMap::new();
@@ -2591,12 +5373,23 @@ pub mod views {
                 }
             });
 
-            if nonlands < 60 {
+            let size = rules.size as u64;
+            if total < size || (rules.singleton && total > size) {
+                recommendations.push(format!(
+                    "{} cards in deck, but the format needs {}{}.",
+                    total,
+                    if rules.singleton { "exactly " } else { "at least " },
+                    size
+                ));
+            } else {
+                recommendations.push(format!("{} cards in deck.", total));
+            }
+            if nonlands < size * 6 / 10 {
                 recommendations.push(format!(
                     "Only {} nonland cards in deck! Consider adding more.",
                     nonlands
                 ));
-            } else if nonlands > 70 {
+            } else if nonlands > size * 7 / 10 {
                 recommendations.push(format!(
                     "{} nonland cards in deck! Is that too many?",
                     nonlands
@@ -2604,6 +5397,50 @@ pub mod views {
             } else {
                 recommendations.push(format!("{} nonland cards in deck.", nonlands));
             }
+            match vdeck(&dbc.lock().unwrap(), did).unwrap().len() {
+                0 => recommendations.push(String::from("The deck is legal.")),
+                n => recommendations.push(format!(
+                    "{} legality problem(s). Type /validate in the deck view for details.",
+                    n
+                )),
+            }
+            if let Some(cc) = &deck.companion {
+                let main: Vec<Card> = rvcfdid(&dbc.lock().unwrap(), did, SortOrder::NameAsc)
+                    .unwrap()
+                    .into_iter()
+                    .filter(|c| c.name != cc.name && c.zone.is_some_and(|z| z.in_deck()))
+                    .collect();
+                let issues = cc.companion_issues(&main, &rules);
+                if issues.is_empty() {
+                    recommendations.push(format!("Companion {} is satisfied.", cc.name));
+                } else {
+                    recommendations.push(format!(
+                        "Companion {} is broken by: {}",
+                        cc.name,
+                        issues.join(", ")
+                    ));
+                }
+            }
+            for (tag, n) in &tag_data {
+                if let Some(t) = targets.get(tag) {
+                    let n = *n as u32;
+                    if t.under(n) {
+                        recommendations.push(format!(
+                            "Only {} card(s) tagged {}; the target is {}.",
+                            n,
+                            tag,
+                            t
+                        ));
+                    } else if t.over(n) {
+                        recommendations.push(format!(
+                            "{} cards tagged {}; the target is {}.",
+                            n,
+                            tag,
+                            t
+                        ));
+                    }
+                }
+            }
             let avg_cmc: f64 = (total_cmc as f64) / (nonlands as f64);
             if avg_cmc > 4.0 {
                 recommendations.push(format!("Average mana cost {:.2}. Seems high.", avg_cmc));
@@ -2619,13 +5456,19 @@ pub mod views {
                 type_data: vtype,
                 color_data,
                 tag_data,
+                targets: targets.clone(),
                 recommendations,
+                companion: deck.companion.map(|c| c.name),
                 dbc,
                 did,
                 fresh,
             }
         }
 
+        pub fn tag_count(&self, tag: &str) -> Option<u64> {
+            self.tag_data.iter().find(|(t, _)| t == tag).map(|(_, n)| *n)
+        }
+
         pub fn recalc(&mut self) {
             if self.fresh {
                 return
@@ -2635,7 +5478,10 @@ pub mod views {
             let mut fresh = true;
 
             for c in vcs {
-                price_data.push((c.name.clone(), c.price));
+                if self.companion.as_ref() == Some(&c.name) {
+                    continue;
+                }
+                price_data.push((c.rpn(), c.price * c.quantity as f64));
                 if c.stale {
                     fresh = false;
                 }
@@ -2710,10 +5556,25 @@ pub mod views {
                 .widths(&[Constraint::Length(20), Constraint::Length(6)])
                 .column_spacing(1);
 
+            let tags: Vec<String> = self.tag_data.iter().map(|(k, _)| k.clone()).collect();
             let tag_data: Vec<ListItem> = self
                 .tag_data
                 .iter()
-                .map(|(k, v)| ListItem::new(format!("{}: {}", k, v)))
+                .map(|(k, v)| match self.targets.get(k) {
+                    Some(t) => {
+                        let n = *v as u32;
+                        let style = if t.under(n) || t.over(n) {
+                            Style::default().fg(Color::Red)
+                        } else {
+                            Style::default().fg(Color::Green)
+                        };
+                        ListItem::new(Spans::from(vec![
+                            Span::from(format!("{}: ", tag_label(k, &tags))),
+                            Span::styled(t.bar(n, 10), style),
+                        ]))
+                    }
+                    None => ListItem::new(format!("{}: {}", tag_label(k, &tags), v)),
+                })
                 .collect();
             let tl = List::new(tag_data)
                 .block(Block::default().title("List").borders(Borders::ALL))
@@ -2801,3 +5662,113 @@ pub mod views {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A settings file for one test, named so parallel runs don't share it.
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("lieutenant_{}_{}.toml", name, std::process::id()))
+    }
+
+    #[test]
+    fn tag_targets() {
+        let t = TagTarget::from("8-12").unwrap();
+        assert!(t.under(3) && !t.over(12) && t.over(13));
+        assert_eq!(TagTarget::from("-3").unwrap().to_string(), "-3");
+        assert_eq!(TagTarget::from("10"), Some(TagTarget { min: Some(10), max: None }));
+        assert_eq!(TagTarget::from("12-8"), None);
+        assert_eq!(TagTarget::from("lots"), None);
+    }
+
+    #[test]
+    fn tag_info() {
+        let (tag, ti) = TagInfo::from("draw;lightblue;2;Refills; keep 10+").unwrap();
+        assert_eq!(tag, "draw");
+        assert_eq!(ti.color, Some(Color::LightBlue));
+        assert_eq!(ti.hotkey, Some(2));
+        assert_eq!(ti.description, "Refills; keep 10+");
+        assert_eq!(ti.to_line(&tag), "draw;lightblue;2;Refills; keep 10+");
+        let (_, ti) = TagInfo::from("ramp;;0;").unwrap();
+        assert!(ti.is_empty());
+    }
+
+    #[test]
+    fn autotag_rules() {
+        let rule = AutoTagRule::from(" removal ; te:\"destroy target\" ty:!land").unwrap();
+        assert_eq!(rule.tag, "removal");
+        assert_eq!(rule.to_line(), "removal;te:\"destroy target\" ty:!land");
+        assert!(AutoTagRule::from("removal;").is_none());
+        assert!(AutoTagRule::from("removal").is_none());
+    }
+
+    #[test]
+    fn tag_templates() {
+        let p = temp_path("tag_templates");
+        std::fs::write(&p, "").unwrap();
+        let mut settings = Settings::from(FileSettings::new(&p).unwrap());
+        assert!(settings.template_names().contains(&String::from("spellslinger")));
+
+        settings.id_template(1, "voltron");
+        assert!(settings.rds(1).borrow().find_tag("attachment/equipment").is_some());
+        settings.id(2);
+        assert!(settings.apply_template(2, "spellslinger"));
+        assert!(!settings.apply_template(2, "nope"));
+        settings.rds(2).borrow_mut().set_target("cantrip", TagTarget::from("12"));
+        settings.save_template(2, "mine");
+
+        std::fs::write(&p, settings.to_toml()).unwrap();
+        let mut reloaded = Settings::from(FileSettings::new(&p).unwrap());
+        std::fs::remove_file(&p).unwrap();
+        reloaded.id_template(3, "mine");
+        let ds = reloaded.rds(3);
+        assert!(ds.borrow().find_tag("counterspell").is_some());
+        assert!(ds.borrow().find_tag("board_wipe").is_some());
+        assert_eq!(ds.borrow().target("cantrip"), TagTarget::from("12"));
+        assert_eq!(ds.borrow().target("counterspell"), TagTarget::from("5"));
+    }
+
+    #[test]
+    fn deck_tag_quoting() {
+        let p = temp_path("tag_quoting");
+        std::fs::write(&p, "").unwrap();
+        let mut settings = Settings::from(FileSettings::new(&p).unwrap());
+        settings.id(1);
+        settings.rds(1).borrow_mut().add_tag(String::from("a\"b\\c"));
+        settings.rds(1).borrow_mut().set_target("a\"b\\c", TagTarget::from("3"));
+        assert!(!valid_tag("a b"));
+        assert!(!valid_tag("removal/"));
+        assert!(valid_tag("board-wipe/mass"));
+
+        std::fs::write(&p, settings.to_toml()).unwrap();
+        let reloaded = Settings::from(FileSettings::new(&p).unwrap());
+        std::fs::remove_file(&p).unwrap();
+        let ds = reloaded.rds(1);
+        assert!(ds.borrow().find_tag("a\"b\\c").is_some());
+        assert_eq!(ds.borrow().target("a\"b\\c"), TagTarget::from("3"));
+    }
+
+    #[test]
+    fn settings_tag_changes() {
+        let p = temp_path("tag_changes");
+        std::fs::write(&p, "").unwrap();
+        let mut settings = Settings::from(FileSettings::new(&p).unwrap());
+        std::fs::remove_file(&p).unwrap();
+        settings.id_template(1, "voltron");
+        let changes = |vtch| views::Changes { df: DefaultFilter::Name, so: SortOrder::NameAsc, oir: None, vtch };
+
+        settings.change(
+            &changes(vec![views::TagChange::Change(String::from("attachment"), String::from("gear"))]),
+            Some(1),
+        );
+        let ds = settings.rds(1);
+        assert!(ds.borrow().find_tag("gear/equipment").is_some());
+        assert!(ds.borrow().find_tag("attachment/aura").is_none());
+        assert_eq!(ds.borrow().target("gear"), TagTarget::from("15"));
+
+        settings.change(&changes(vec![views::TagChange::Delete(String::from("gear"))]), Some(1));
+        assert!(ds.borrow().tags.iter().all(|t| tag_under(t, "gear").is_none()));
+        assert_eq!(ds.borrow().target("gear"), None);
+    }
+}